@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use axum::{
+    body::Body,
+    extract::{OriginalUri, Query, State},
+    http::{header::{self, HeaderMap, HeaderValue}, Method, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_util::io::ReaderStream;
+use crate::auth::keycloak;
+use crate::models::audit::{AuditEvent, AuditQuery};
+use crate::models::auth::AuthRequest;
+use crate::storage::{self, AuditPage, AuditRange};
+
+type HandlerError = (StatusCode, Json<serde_json::Value>);
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+// Events kept in memory while storage is unavailable; the oldest go first
+const MAX_PENDING: usize = 100_000;
+const DEFAULT_LIMIT: usize = 500;
+const MAX_LIMIT: usize = 10_000;
+const MAX_CSV_ROWS: usize = 1_000_000;
+// Events read per hold of the storage lock
+const READ_CHUNK: usize = 1000;
+const CSV_BUF_SIZE: usize = 64 * 1024;
+
+lazy_static! {
+    static ref PENDING: Mutex<VecDeque<AuditEvent>> = Mutex::new(VecDeque::new());
+}
+
+// Queue an event; the writer task stores it within FLUSH_INTERVAL
+pub fn record(event: AuditEvent) {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() >= MAX_PENDING {
+        tracing::warn!("Audit backlog full, dropping the oldest event");
+        pending.pop_front();
+    }
+    pending.push_back(event);
+}
+
+// Store queued events, counting downloads into the entry stats
+pub fn flush(state: &crate::AppState) {
+    let events = Vec::from(std::mem::take(&mut *PENDING.lock().unwrap()));
+    if events.is_empty() {
+        return;
+    }
+    if let Err(e) = state.storage.lock().unwrap().append_audit(&events) {
+        tracing::error!("Failed to store {} audit events: {}", events.len(), e);
+        let mut pending = PENDING.lock().unwrap();
+        let newer = std::mem::replace(&mut *pending, events.into());
+        pending.extend(newer);
+        while pending.len() > MAX_PENDING {
+            pending.pop_front();
+        }
+        return;
+    }
+    crate::webfs::stats::record(state, &events);
+}
+
+fn purge(state: &crate::AppState) {
+    let days = state.config.audit.retention_days;
+    if days == 0 {
+        return;
+    }
+    let cutoff = Utc::now() - chrono::Duration::days(days as i64);
+    match state.storage.lock().unwrap().purge_audit(cutoff) {
+        Ok(0) => {}
+        Ok(purged) => tracing::info!("Purged {} audit events older than {} days", purged, days),
+        Err(e) => tracing::error!("Failed to purge audit events: {}", e),
+    }
+}
+
+// The writer task: flushes queued events and applies the retention period
+pub fn start(state: crate::AppState) {
+    tokio::spawn(async move {
+        let mut flush_timer = tokio::time::interval(FLUSH_INTERVAL);
+        let mut purge_timer = tokio::time::interval(PURGE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = flush_timer.tick() => flush(&state),
+                _ = purge_timer.tick() => purge(&state),
+            }
+        }
+    });
+}
+
+// A file body being served. The download is recorded with the bytes sent
+// once the body is dropped, whether the client took it all or went away.
+pub struct DownloadMeter<R> {
+    inner: R,
+    sent: u64,
+    expected: u64,
+    event: Option<AuditEvent>,
+}
+
+impl<R> DownloadMeter<R> {
+    pub fn new(inner: R, expected: u64, event: Option<AuditEvent>) -> DownloadMeter<R> {
+        DownloadMeter { inner, sent: 0, expected, event }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DownloadMeter<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.sent += (buf.filled().len() - before) as u64;
+        result
+    }
+}
+
+impl<R> Drop for DownloadMeter<R> {
+    fn drop(&mut self) {
+        if let Some(mut event) = self.event.take() {
+            event.bytes = Some(self.sent);
+            event.completed = Some(self.sent >= self.expected);
+            record(event);
+        }
+    }
+}
+
+// Admins only, with a login or an API key reading all its owner may; a key
+// scoped to some channels or folders doesn't get everyone's events
+async fn admin(state: &crate::AppState, uri: &axum::http::Uri, method: &Method, headers: &HeaderMap) -> Result<(), HandlerError> {
+    let auth_request = AuthRequest::new(uri, method.as_str(), headers);
+    let auth = keycloak::check_auth(state, &auth_request, state.passwd.clone(), state.tokens.clone()).await?;
+    if auth.grant.is_some() || auth.scope.as_ref().is_some_and(|scope| scope.narrows()) || !state.config.audit.is_admin(&auth.claims) {
+        return Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Forbidden"}))));
+    }
+    Ok(())
+}
+
+fn read_error(e: anyhow::Error) -> HandlerError {
+    tracing::error!("Failed to read audit events: {}", e);
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to read audit log"})))
+}
+
+fn query_range(state: &crate::AppState, query: &AuditQuery) -> Result<AuditRange, HandlerError> {
+    let (from, to) = query.range().map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e}))))?;
+    flush(state);
+    Ok(storage::audit_range(from, to))
+}
+
+// One chunk of `range` matching the query, and the rest of the range
+fn read_page(state: &crate::AppState, query: &AuditQuery, range: AuditRange, newest_first: bool) -> anyhow::Result<AuditPage> {
+    state.storage.lock().unwrap().audit_page(range, newest_first, READ_CHUNK, |event| query.matches(event))
+}
+
+fn query_events(state: &crate::AppState, query: &AuditQuery, limit: usize) -> Result<Vec<AuditEvent>, HandlerError> {
+    let mut rest = Some(query_range(state, query)?);
+    let mut events = Vec::new();
+    while let Some(range) = rest.filter(|_| events.len() < limit) {
+        let (page, next) = read_page(state, query, range, true).map_err(read_error)?;
+        events.extend(page.into_iter().map(|(_, event)| event));
+        rest = next;
+    }
+    events.truncate(limit);
+    Ok(events)
+}
+
+// The part of `range` holding the newest `limit` matching events
+fn newest_range(state: &crate::AppState, query: &AuditQuery, range: AuditRange, limit: usize) -> anyhow::Result<Option<AuditRange>> {
+    if limit == 0 {
+        return Ok(None);
+    }
+    let mut matched = 0;
+    let mut rest = Some(range);
+    while let Some(page_range) = rest {
+        let (page, next) = read_page(state, query, page_range, true)?;
+        for (key, _) in page {
+            matched += 1;
+            if matched == limit {
+                return Ok(Some((Bound::Included(key), range.1)));
+            }
+        }
+        rest = next;
+    }
+    Ok(Some(range))
+}
+
+async fn write_csv(state: &crate::AppState, query: &AuditQuery, mut rest: Option<AuditRange>, writer: &mut (impl AsyncWrite + Unpin)) -> anyhow::Result<()> {
+    writer.write_all(format!("{}\r\n", AuditEvent::CSV_HEADER).as_bytes()).await?;
+    while let Some(range) = rest {
+        let (page, next) = read_page(state, query, range, false)?;
+        let mut csv = String::new();
+        for (_, event) in page {
+            csv.push_str(&event.csv_row());
+            csv.push_str("\r\n");
+        }
+        writer.write_all(csv.as_bytes()).await?;
+        rest = next;
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+// GET /auth/v1/audit?user=&path=&kind=&from=&to=&limit=: newest first
+pub async fn audit_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>, HandlerError> {
+    admin(&state, &uri, &method, &headers).await?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    Ok(Json(query_events(&state, &query, limit)?))
+}
+
+// GET /auth/v1/audit.csv: the same filters, oldest first
+pub async fn audit_csv_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    Query(query): Query<AuditQuery>,
+) -> Result<Response, HandlerError> {
+    admin(&state, &uri, &method, &headers).await?;
+    let limit = query.limit.unwrap_or(MAX_CSV_ROWS).min(MAX_CSV_ROWS);
+    let range = query_range(&state, &query)?;
+    let rest = newest_range(&state, &query, range, limit).map_err(read_error)?;
+    // Written out a chunk at a time as the client reads it
+    let (mut writer, reader) = tokio::io::duplex(CSV_BUF_SIZE);
+    tokio::spawn(async move {
+        if let Err(e) = write_csv(&state, &query, rest, &mut writer).await {
+            tracing::warn!("Audit CSV export stopped: {}", e);
+        }
+    });
+    let mut response = Body::from_stream(ReaderStream::new(reader)).into_response();
+    let filename = format!("attachment; filename=\"audit-{}.csv\"", Utc::now().format("%Y%m%d-%H%M%S"));
+    response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"));
+    if let Ok(value) = HeaderValue::from_str(&filename) {
+        response.headers_mut().insert(header::CONTENT_DISPOSITION, value);
+    }
+    Ok(response)
+}
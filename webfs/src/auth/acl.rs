@@ -0,0 +1,78 @@
+use axum::{Json, http::StatusCode};
+use crate::models::acl::{normalize_path, AclTarget};
+use crate::models::auth::AuthInfo;
+use crate::models::files::{Config, FolderShare};
+
+type HandlerError = (StatusCode, Json<serde_json::Value>);
+
+// The channel or folder share a request path reads. /fs/v1/{lang}/{channel}
+// paths are channels; anything else is served from the user's folder share.
+// Rules match the normalized path, as the file served is looked up by it;
+// None for a path that does not normalize.
+pub fn target(config: &Config, path: &str, folder: Option<&FolderShare>) -> Option<AclTarget> {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let path = normalize_path(path)?;
+    let mut target = AclTarget::new(&path);
+    if let Some(rest) = path.strip_prefix("/fs/v1/") {
+        let mut parts = rest.split('/');
+        if let (Some(lang), Some(name)) = (parts.next(), parts.next()) {
+            if config.channels.get(lang).is_some_and(|m| m.contains_key(name)) {
+                target.channel = Some(format!("{}/{}", lang, name));
+                return Some(target);
+            }
+        }
+    }
+    target.folder = folder.and_then(|f| folder_id(config, f));
+    Some(target)
+}
+
+// The key of a folder share under `folders`
+pub fn folder_id(config: &Config, folder: &FolderShare) -> Option<String> {
+    config.folders.iter()
+        .find(|(_, f)| f.name == folder.name && f.base_file_path == folder.base_file_path)
+        .map(|(id, _)| id.clone())
+}
+
+// Whether the acl rules let the user read `path`. `folder` overrides the
+// user's own folder share, for requests naming one.
+pub fn permits(state: &crate::AppState, auth: &AuthInfo, path: &str, folder: Option<&FolderShare>) -> bool {
+    // The signer's rules were checked when the URL was signed; the URL
+    // reaches no further than its scope
+    if let Some(grant) = &auth.grant {
+        return grant.covers(path);
+    }
+    let folder = folder.or(auth.folder.as_ref());
+    let Some(target) = target(&state.config, path, folder) else { return false };
+    // An API key reads what its owner may, narrowed to its scope
+    if auth.scope.as_ref().is_some_and(|scope| !scope.covers(&target)) {
+        return false;
+    }
+    let folder = folder.filter(|_| target.folder.is_some());
+    state.config.acl.allows(&auth.claims, folder, &target)
+}
+
+// A prefix grant skips the acl below its prefix, so the signer must be able
+// to read all of it: every channel under it, with no deny path rule reaching
+// into it
+pub fn authorize_prefix(state: &crate::AppState, auth: &AuthInfo, prefix: &str, folder: Option<&FolderShare>) -> Result<(), HandlerError> {
+    let forbidden = |error: &str| Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"error": error}))));
+    let Some(prefix) = normalize_path(prefix) else { return forbidden("Forbidden") };
+    let prefix = prefix.trim_end_matches('/');
+    let below = |path: &str| path == prefix || path.starts_with(&format!("{}/", prefix));
+    let hidden_channel = state.config.channels.iter()
+        .flat_map(|(lang, channels)| channels.keys().map(move |name| format!("/fs/v1/{}/{}", lang, name)))
+        .any(|path| below(&path) && !permits(state, auth, &path, folder));
+    if hidden_channel || state.config.acl.denies_below(&auth.claims, prefix) {
+        return forbidden("The prefix covers paths you can't read");
+    }
+    Ok(())
+}
+
+// 403 unless `permits`
+pub fn authorize(state: &crate::AppState, auth: &AuthInfo, path: &str, folder: Option<&FolderShare>) -> Result<(), HandlerError> {
+    if permits(state, auth, path, folder) {
+        return Ok(());
+    }
+    tracing::info!("Access denied for {} to {}", auth.claims.preferred_username.as_deref().unwrap_or(&auth.claims.sub), path);
+    Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Forbidden"}))))
+}
@@ -0,0 +1,292 @@
+use std::collections::HashSet;
+use axum::{
+    extract::{OriginalUri, Path, State},
+    http::{header::HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Json, Response},
+};
+use base64::Engine;
+use chrono::{Duration, Utc};
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+use crate::models::apikey::*;
+use crate::models::auth::{AuthInfo, AuthRequest, Claims};
+use crate::webfs::feeds::FEED_ENCLOSURE_SECS;
+use super::{acl, keycloak};
+
+type HandlerError = (StatusCode, Json<serde_json::Value>);
+
+const DEFAULT_EXPIRES_DAYS: u32 = 365;
+const MAX_EXPIRES_DAYS: u32 = 365;
+// Keys stop working once their owner hasn't signed in for this long, so a
+// user disabled at the provider loses them too
+const OWNER_CHECK_DAYS: i64 = 90;
+// A signed in owner's keys are written back at most this often
+const OWNER_REFRESH_SECS: i64 = 3600;
+// last_used is written at most this often per key
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+const ID_ALPHABET: [char; 36] = [
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r',
+    's', 't', 'u', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+];
+
+fn error(status: StatusCode, msg: &str) -> HandlerError {
+    (status, Json(serde_json::json!({"error": msg})))
+}
+
+fn hash(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The stored key for a token, if the token is good
+pub fn lookup(state: &crate::AppState, token: &str) -> Result<ApiKey, HandlerError> {
+    let invalid = || error(StatusCode::UNAUTHORIZED, "invalid api key");
+    let (id, secret) = token.strip_prefix(API_KEY_PREFIX).and_then(|t| t.split_once('_')).ok_or_else(invalid)?;
+    let storage = state.storage.lock().unwrap();
+    let mut key = storage.get_api_key(id)
+        .map_err(|e| {
+            tracing::error!("Failed to read api key {}: {}", id, e);
+            error(StatusCode::INTERNAL_SERVER_ERROR, "token verification failed")
+        })?
+        .ok_or_else(invalid)?;
+    if key.hash != hash(secret) {
+        return Err(invalid());
+    }
+    if key.is_expired() {
+        return Err(error(StatusCode::UNAUTHORIZED, "api key expired"));
+    }
+    let now = Utc::now();
+    if (now - key.owner_seen).num_days() >= OWNER_CHECK_DAYS {
+        return Err(error(StatusCode::UNAUTHORIZED, "api key owner must sign in again"));
+    }
+    if key.last_used.is_none_or(|t| (now - t).num_seconds() >= LAST_USED_RESOLUTION_SECS) {
+        key.last_used = Some(now);
+        if let Err(e) = storage.insert_api_key(&key) {
+            tracing::warn!("Failed to record use of api key {}: {}", id, e);
+        }
+    }
+    Ok(key)
+}
+
+// The identity behind an API key, for check_auth
+pub fn authenticate(state: &crate::AppState, request: &AuthRequest, token: &str) -> Result<AuthInfo, HandlerError> {
+    let key = lookup(state, token)?;
+    if key.feed.is_some() {
+        return Err(error(StatusCode::UNAUTHORIZED, "feed keys only read their feed"));
+    }
+    let method = request.method.as_deref().unwrap_or("GET").to_uppercase();
+    if key.scope.read_only && !matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS" | "PROPFIND") {
+        return Err(error(StatusCode::FORBIDDEN, "api key is read-only"));
+    }
+    let folder = state.config.folders.get(key.default_webdavfs.as_deref().unwrap_or("default")).cloned();
+    let mut auth = AuthInfo::new(key.claims(), folder);
+    auth.scope = Some(key.scope.clone());
+    Ok(auth)
+}
+
+// A fresh sign-in of `claims`: the owner's keys take on their current
+// groups and roles, and keep working for OWNER_CHECK_DAYS
+pub fn refresh_owner(state: &crate::AppState, claims: &Claims) {
+    let storage = state.storage.lock().unwrap();
+    let keys = match storage.api_keys_of(&claims.sub) {
+        Ok(keys) => keys,
+        Err(e) => {
+            tracing::error!("Failed to list api keys of {}: {}", claims.sub, e);
+            return;
+        }
+    };
+    let now = Utc::now();
+    let (groups, roles) = (claims.groups.clone().unwrap_or_default(), claim_roles(claims));
+    for mut key in keys {
+        let unchanged = key.groups == groups && key.roles == roles && key.default_webdavfs == claims.default_webdavfs;
+        if unchanged && (now - key.owner_seen).num_seconds() < OWNER_REFRESH_SECS {
+            continue;
+        }
+        key.groups = groups.clone();
+        key.roles = roles.clone();
+        key.default_webdavfs = claims.default_webdavfs.clone();
+        key.owner_seen = now;
+        if let Err(e) = storage.insert_api_key(&key) {
+            tracing::warn!("Failed to refresh api key {}: {}", key.id, e);
+        }
+    }
+}
+
+fn claim_roles(claims: &Claims) -> Vec<String> {
+    let mut roles: HashSet<String> = claims.roles.iter().flatten().cloned().collect();
+    if let Some(access) = &claims.resource_access {
+        roles.extend(access.clients.values().flat_map(|c| c.roles.iter().cloned()));
+    }
+    let mut roles: Vec<String> = roles.into_iter().collect();
+    roles.sort();
+    roles
+}
+
+// Keys are managed with a login, not with another key or a signed URL
+async fn owner(state: &crate::AppState, uri: &axum::http::Uri, method: &Method, headers: &HeaderMap) -> Result<AuthInfo, HandlerError> {
+    let auth_request = AuthRequest::new(uri, method.as_str(), headers);
+    let auth = keycloak::check_auth(state, &auth_request, state.passwd.clone(), state.tokens.clone()).await?;
+    if auth.grant.is_some() || auth.scope.is_some() {
+        return Err(error(StatusCode::FORBIDDEN, "API keys are managed with a login"));
+    }
+    Ok(auth)
+}
+
+// POST /auth/v1/keys {"name": .., "scope": {"channels": [..], "folders": [..], "read_only": true}, "expires_in_days": 90}
+pub async fn create_key_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreateApiKeyResponse>, HandlerError> {
+    let auth = owner(&state, &uri, &method, &headers).await?;
+    if request.name.trim().is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST, "name is required"));
+    }
+    let days = request.expires_in_days.unwrap_or(DEFAULT_EXPIRES_DAYS);
+    if days == 0 || days > MAX_EXPIRES_DAYS {
+        return Err(error(StatusCode::BAD_REQUEST, &format!("expires_in_days must be 1 to {}", MAX_EXPIRES_DAYS)));
+    }
+    let (key, token) = new_key(&auth, request.name.trim(), request.scope, days)?;
+    store_key(&state, &key)?;
+    tracing::info!("Api key {} ({}) created for {}", key.id, key.name, key.username);
+    Ok(Json(CreateApiKeyResponse {
+        key: ApiKeyInfo::from(&key),
+        token,
+    }))
+}
+
+// A key carrying the caller's identity, and its token
+fn new_key(auth: &AuthInfo, name: &str, scope: ApiKeyScope, days: u32) -> Result<(ApiKey, String), HandlerError> {
+    let claims = &auth.claims;
+    let now = Utc::now();
+    let mut secret = [0u8; 32];
+    OsRng.try_fill_bytes(&mut secret).map_err(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "failed to create key"))?;
+    let secret = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(secret);
+    let id = nanoid::nanoid!(16, &ID_ALPHABET);
+    let key = ApiKey {
+        id: id.clone(),
+        name: name.to_string(),
+        hash: hash(&secret),
+        owner: claims.sub.clone(),
+        username: claims.preferred_username.clone().unwrap_or(claims.sub.clone()),
+        groups: claims.groups.clone().unwrap_or_default(),
+        roles: claim_roles(claims),
+        default_webdavfs: claims.default_webdavfs.clone(),
+        scope,
+        created_at: now,
+        expires_at: Some(now + Duration::days(days as i64)),
+        last_used: None,
+        feed: None,
+        owner_seen: now,
+    };
+    Ok((key, format!("{}{}_{}", API_KEY_PREFIX, id, secret)))
+}
+
+fn store_key(state: &crate::AppState, key: &ApiKey) -> Result<(), HandlerError> {
+    state.storage.lock().unwrap().insert_api_key(key).map_err(|e| {
+        tracing::error!("Failed to store api key: {}", e);
+        error(StatusCode::INTERNAL_SERVER_ERROR, "failed to create key")
+    })
+}
+
+// Enclosures signed for a feed key carry it as their session, so revoking
+// the key ends them too
+pub fn feed_session(key: &ApiKey) -> String {
+    format!("feed-{}", key.id)
+}
+
+fn delete_key(state: &crate::AppState, key: &ApiKey) -> Result<(), HandlerError> {
+    state.storage.lock().unwrap().delete_api_key(&key.id).map_err(|e| {
+        tracing::error!("Failed to delete api key {}: {}", key.id, e);
+        error(StatusCode::INTERNAL_SERVER_ERROR, "failed to revoke key")
+    })?;
+    if key.feed.is_some() {
+        let expires = Utc::now().timestamp() as u64 + FEED_ENCLOSURE_SECS;
+        keycloak::revoke(state, format!("sid:{}", feed_session(key)), expires);
+    }
+    Ok(())
+}
+
+// Where the client reached us, as the proxy in front tells it
+fn external_base(headers: &HeaderMap) -> String {
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok())
+        .and_then(|h| h.split(',').next())
+        .map(|h| h.trim().to_string());
+    let proto = header("x-forwarded-proto").unwrap_or("https".to_string());
+    let host = header("x-forwarded-host").or(header("host")).unwrap_or("localhost".to_string());
+    format!("{}://{}", proto, host)
+}
+
+// POST /auth/v1/feeds/{lang}/{channel}: a private feed URL for the caller,
+// replacing any earlier one for the channel
+pub async fn create_feed_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    Path((lang, name)): Path<(String, String)>,
+) -> Result<Json<PrivateFeedResponse>, HandlerError> {
+    let auth = owner(&state, &uri, &method, &headers).await?;
+    if !state.config.channels.get(&lang).is_some_and(|m| m.contains_key(&name)) {
+        return Err(error(StatusCode::NOT_FOUND, "Channel not found"));
+    }
+    acl::authorize(&state, &auth, &format!("/fs/v1/{}/{}/", lang, name), None)?;
+    let channel = format!("{}/{}", lang, name);
+    let earlier = state.storage.lock().unwrap().api_keys_of(&auth.claims.sub).map_err(|e| {
+        tracing::error!("Failed to list api keys: {}", e);
+        error(StatusCode::INTERNAL_SERVER_ERROR, "failed to create key")
+    })?;
+    for key in earlier.iter().filter(|k| k.feed.as_ref() == Some(&channel)) {
+        delete_key(&state, key)?;
+        tracing::info!("Feed key {} for {} replaced", key.id, channel);
+    }
+    let scope = ApiKeyScope { channels: vec![channel.clone()], folders: Vec::new(), read_only: true };
+    let (mut key, token) = new_key(&auth, &format!("Feed {}", channel), scope, MAX_EXPIRES_DAYS)?;
+    key.feed = Some(channel);
+    store_key(&state, &key)?;
+    tracing::info!("Feed key {} ({}) created for {}", key.id, key.name, key.username);
+    let base = format!("{}/feeds/v1/private/{}/{}", external_base(&headers), token, name);
+    Ok(Json(PrivateFeedResponse {
+        key: ApiKeyInfo::from(&key),
+        rss: format!("{}.rss", base),
+        atom: format!("{}.atom", base),
+        json: format!("{}.json", base),
+    }))
+}
+
+// GET /auth/v1/keys: the caller's keys, oldest first
+pub async fn list_keys_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ApiKeyInfo>>, HandlerError> {
+    let auth = owner(&state, &uri, &method, &headers).await?;
+    let mut keys = state.storage.lock().unwrap().api_keys_of(&auth.claims.sub).map_err(|e| {
+        tracing::error!("Failed to list api keys: {}", e);
+        error(StatusCode::INTERNAL_SERVER_ERROR, "failed to list keys")
+    })?;
+    keys.sort_by_key(|k| k.created_at);
+    Ok(Json(keys.iter().map(ApiKeyInfo::from).collect()))
+}
+
+// DELETE /auth/v1/keys/{id}
+pub async fn revoke_key_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Result<Response, HandlerError> {
+    let auth = owner(&state, &uri, &method, &headers).await?;
+    let not_found = || error(StatusCode::NOT_FOUND, "no such key");
+    let key = state.storage.lock().unwrap().get_api_key(&id)
+        .map_err(|_| error(StatusCode::INTERNAL_SERVER_ERROR, "failed to revoke key"))?
+        .filter(|k| k.owner == auth.claims.sub)
+        .ok_or_else(not_found)?;
+    delete_key(&state, &key)?;
+    tracing::info!("Api key {} ({}) revoked by {}", key.id, key.name, key.username);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
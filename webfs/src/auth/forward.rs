@@ -0,0 +1,203 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header::{self, HeaderMap, HeaderValue}, StatusCode, Uri},
+    response::{IntoResponse, Json, Response},
+};
+use crate::audit;
+use crate::models::acl::normalize_path;
+use crate::models::audit::{AuditEvent, AuditKind};
+use crate::models::auth::AuthRequest;
+use crate::webfs::stats;
+use super::{acl, keycloak};
+use super::handler::is_webdav;
+
+// The request the proxy is holding, as nginx (X-Original-*) or Traefik and
+// Caddy (X-Forwarded-*) describe it
+#[derive(Debug, Clone)]
+pub struct ForwardedRequest {
+    pub uri: String,
+    pub method: String,
+    pub host: Option<String>,
+    pub proto: String,
+}
+
+impl ForwardedRequest {
+    pub fn from_headers(headers: &HeaderMap) -> ForwardedRequest {
+        let header = |names: &[&str]| names.iter()
+            .find_map(|name| headers.get(*name).and_then(|h| h.to_str().ok()))
+            .map(|h| h.split(',').next().unwrap_or_default().trim().to_string())
+            .filter(|h| !h.is_empty());
+        ForwardedRequest {
+            uri: header(&["x-forwarded-uri", "x-original-uri"]).unwrap_or("/".to_string()),
+            method: header(&["x-forwarded-method", "x-original-method"]).unwrap_or("GET".to_string()).to_uppercase(),
+            host: header(&["x-forwarded-host", "host"]),
+            proto: header(&["x-forwarded-proto"]).unwrap_or("https".to_string()),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        self.uri.split(['?', '#']).next().unwrap_or("/")
+    }
+
+    // url() without the query, which may hold a signature, and with the path
+    // normalized as the acl reads it
+    pub fn file_url(&self) -> Option<String> {
+        let path = normalize_path(self.path())?;
+        Some(match &self.host {
+            Some(host) => format!("{}://{}{}", self.proto, host, path),
+            None => path,
+        })
+    }
+
+    pub fn url(&self) -> String {
+        match &self.host {
+            Some(host) => format!("{}://{}{}", self.proto, host, self.uri),
+            None => self.uri.clone(),
+        }
+    }
+}
+
+// GET /auth/v1/forward: forward auth for Traefik, Caddy and the like, which
+// pass a redirect on to the browser
+pub async fn forward_handler(State(state): State<crate::AppState>, headers: HeaderMap) -> Response {
+    forward(&state, &headers, true).await
+}
+
+// GET /auth/v1/nginx: nginx's auth_request only tells 2xx from 401 and 403,
+// so browsers get a 401 with the login page in Location
+pub async fn nginx_handler(State(state): State<crate::AppState>, headers: HeaderMap) -> Response {
+    forward(&state, &headers, false).await
+}
+
+async fn forward(state: &crate::AppState, headers: &HeaderMap, redirect: bool) -> Response {
+    let request = ForwardedRequest::from_headers(headers);
+    let user_agent = headers.get(header::USER_AGENT).and_then(|h| h.to_str().ok()).unwrap_or("");
+    let is_webdav = is_webdav(&request.method, user_agent);
+    let config = &state.config.forward_auth;
+
+    tracing::debug!("Forward auth check: method={}, uri={}, is_webdav={}", request.method, request.uri, is_webdav);
+    // Bypass rules and the acl both match the path as normalize_path resolves
+    // it, which is how the file server behind us reads it
+    if normalize_path(request.path()).is_none() {
+        tracing::info!("Forward auth refused path {}", request.path());
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "Invalid path"}))).into_response();
+    }
+    if config.bypasses(&request.method, request.path(), is_webdav) {
+        tracing::debug!("Bypassing auth for {} {}", request.method, request.path());
+        return StatusCode::OK.into_response();
+    }
+
+    let uri = Uri::try_from(request.uri.as_str()).unwrap_or(Uri::from_static("/"));
+    let auth_request = AuthRequest::new(&uri, &request.method, headers);
+    let auth_identity = match keycloak::check_auth(state, &auth_request, state.passwd.clone(), state.tokens.clone()).await {
+        Ok(auth_identity) => auth_identity,
+        Err((status, msg)) => {
+            tracing::info!("Forward auth failed for {}", request.uri);
+            let accepts_html = headers.get(header::ACCEPT).and_then(|h| h.to_str().ok()).is_some_and(|a| a.contains("text/html"));
+            let login = config.login_redirect(&request.url()).filter(|_| !is_webdav && accepts_html);
+            return match login {
+                Some(login) => login_response(status, &login, redirect),
+                None => (status, msg).into_response(),
+            };
+        }
+    };
+    if let Err(denied) = acl::authorize(state, &auth_identity, request.path(), None) {
+        return denied.into_response();
+    }
+    // Channel media is served from behind forward auth, so its fetches are
+    // the downloads counted in the entries' stats
+    if let Some(url) = request.file_url().filter(|url| request.method == "GET" && !stats::channel_files(&state.config, url).is_empty()) {
+        audit::record(AuditEvent::new(AuditKind::Download, &url, 200).client(headers).identity(&auth_identity.claims));
+    }
+
+    let claims = &auth_identity.claims;
+    let user = claims.preferred_username.clone().unwrap_or(claims.sub.clone());
+    let groups = claims.groups.iter().flatten().map(|g| g.trim_start_matches('/')).collect::<Vec<_>>().join(",");
+    let folder = auth_identity.folder.as_ref().and_then(|f| acl::folder_id(&state.config, f));
+    let socket_auth = auth_identity.folder.as_ref().map(|f| f.access_token());
+    let mut response = Json(&auth_identity).into_response();
+    let response_headers = response.headers_mut();
+    let mut insert = |name: &'static str, value: &str| {
+        match HeaderValue::from_str(value) {
+            Ok(value) => { response_headers.insert(name, value); }
+            Err(_) => tracing::warn!("Can't send {} as {}", value, name),
+        }
+    };
+    insert("X-Webdav-Socket", &config.socket);
+    if let Some(socket_auth) = &socket_auth {
+        insert("X-Socket-Auth", socket_auth);
+    }
+    insert("X-Auth-User", &user);
+    insert("X-Auth-Groups", &groups);
+    if let Some(folder) = &folder {
+        insert("X-Auth-Folder", folder);
+    }
+    response
+}
+
+fn login_response(status: StatusCode, login: &str, redirect: bool) -> Response {
+    let status = if redirect { StatusCode::FOUND } else { status };
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    if let Ok(location) = HeaderValue::from_str(login) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use crate::auth::local::{LocalProvider, UsersFile};
+    use crate::models::acl::AclEffect;
+    use crate::models::auth::BasicAuthRequest;
+    use crate::models::files::Channel;
+    use crate::storage::Storage;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_media_fetch_counts_download() {
+        let dir = std::env::temp_dir().join(format!("webfs-forward-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("media")).unwrap();
+        std::fs::write(dir.join("media/2024-01-07 Sunday Talk.mp4"), b"").unwrap();
+
+        let mut config = Channel::read_config("config-test.yaml").unwrap();
+        config.acl.default = AclEffect::Allow;
+        let channel = Channel {
+            name: "talks".into(),
+            file_path: dir.join("media").to_string_lossy().to_string(),
+            media_link: "https://media.example/Talks".into(),
+            filter_extensions: vec![".mp4".into()],
+            ..Default::default()
+        };
+        config.channels.entry("en".into()).or_default().insert("talks".into(), channel.clone());
+        let users: UsersFile = serde_yaml::from_str(&format!("users:\n  alice:\n    password: \"{}\"\n", bcrypt::hash("pw", 4).unwrap())).unwrap();
+        let state = crate::AppState {
+            auth: Arc::new(LocalProvider::new(users.users, Some("secret".into()))),
+            base_path: dir.to_string_lossy().to_string(),
+            rss_days: 7,
+            http_client: reqwest::Client::new(),
+            config,
+            channel_cache: Arc::new(Mutex::new(HashMap::new())),
+            storage: Arc::new(Mutex::new(Storage::new(&dir.join("webfs.db").to_string_lossy()).unwrap())),
+            passwd: moka::future::Cache::new(10),
+            tokens: moka::future::Cache::new(10),
+        };
+        state.storage.lock().unwrap().channel_entries(&channel).unwrap();
+
+        let login = BasicAuthRequest { username: "alice".into(), password: "pw".into(), use_cache: false };
+        let token = keycloak::authenticate(state.clone(), login, state.passwd.clone(), state.tokens.clone()).await.unwrap().jwt_token;
+        let mut headers = HeaderMap::new();
+        headers.insert("x-original-uri", HeaderValue::from_static("/Talks/2024-01-07%20Sunday%20Talk.mp4"));
+        headers.insert("x-forwarded-host", HeaderValue::from_static("media.example"));
+        headers.insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token)).unwrap());
+        assert_eq!(forward(&state, &headers, false).await.status(), StatusCode::OK);
+        audit::flush(&state);
+
+        let stats = state.storage.lock().unwrap().entry_stats(&channel.cache_id()).unwrap();
+        assert_eq!(stats.values().map(|s| s.downloads).sum::<u64>(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
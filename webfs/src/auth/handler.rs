@@ -1,26 +1,35 @@
 use axum::{
     extract::{State, OriginalUri, Request},
     response::{IntoResponse, Response},
-    http::{StatusCode, Method, Uri, header::{ HeaderMap, HeaderValue}},
+    http::{StatusCode, Method, header::{ HeaderMap, HeaderValue}},
     response::Json,
-    body::to_bytes,
+    body::Bytes,
 };
+use crate::audit;
+use crate::models::audit::{AuditEvent, AuditKind};
 use crate::models::auth::*;
-use crate::auth::keycloak;
+use crate::auth::{acl, keycloak};
 
 pub async fn authenticate_handler(
     State(state): State<crate::AppState>,
+    headers: HeaderMap,
     Json(auth_req): Json<BasicAuthRequest>,
 ) -> Result<Json<AuthResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let username = auth_req.username.clone();
     let response = keycloak::authenticate(
         state.clone(),
         auth_req,
-        &state.http_client,
         state.passwd.clone(),
         state.tokens.clone(),
     )
     .await
-    .map_err(|(status, msg)| (status, Json(serde_json::json!({"error": msg}))))?;
+    .map_err(|(status, msg)| {
+        let mut event = AuditEvent::new(AuditKind::LoginFailed, "/auth/v1/login", status.as_u16()).client(&headers).detail(&msg);
+        event.username = Some(username.clone());
+        audit::record(event);
+        (status, Json(serde_json::json!({"error": msg})))
+    })?;
+    audit::record(AuditEvent::new(AuditKind::Login, "/auth/v1/login", 200).client(&headers).identity(&response.claims));
     Ok(Json(response))
 }
 
@@ -29,16 +38,32 @@ pub async fn signurl_handler(
     OriginalUri(uri): OriginalUri,
     method: Method,
     headers: HeaderMap,
-    Json(request): Json<SignUrlRequest>,
+    Json(mut request): Json<SignUrlRequest>,
 ) -> Result<Json<SignUrlResponse>, (StatusCode, Json<serde_json::Value>)> {
     let signing_keys = keycloak::SIGNING_KEYS.clone();
     let auth_request = AuthRequest::new(&uri, method.as_str(), &headers);
     match keycloak::check_auth(&state, &auth_request, state.passwd.clone(), state.tokens.clone()).await {
         Ok(auth_identity) => {
+            if auth_identity.grant.is_some() {
+                return Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Signed URLs can't sign other URLs"}))));
+            }
+            if request.fs_id.is_empty() {
+                request.fs_id = auth_identity.claims.default_webdavfs.clone().unwrap_or_default();
+            }
+            request.sub = auth_identity.claims.preferred_username.clone().unwrap_or(auth_identity.claims.sub.clone());
+            request.sid = auth_identity.claims.sid.clone();
+            let url_path = url::Url::parse(&request.url).map(|u| u.path().to_string()).unwrap_or_else(|_| request.url.clone());
+            let folder = state.config.folders.get(&request.fs_id).filter(|_| !request.fs_id.is_empty());
+            acl::authorize(&state, &auth_identity, &url_path, folder)?;
+            if request.scope == SignScope::Prefix {
+                acl::authorize_prefix(&state, &auth_identity, &url_path, folder)?;
+            }
             let response = {   
                 let mut signing_keys = signing_keys.write().await;
                 signing_keys.generate_signed_url(&request)
             }.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))))?;
+            let detail = format!("id={} scope={} expires={}", response.id, request.scope.as_str(), response.expires_at.to_rfc3339());
+            audit::record(AuditEvent::new(AuditKind::SignUrl, &url_path, 200).client(&headers).identity(&auth_identity.claims).detail(&detail));
             Ok(Json(response))
         },
         Err((status, msg)) => {
@@ -48,57 +73,6 @@ pub async fn signurl_handler(
     }
 }
 
-pub async fn nginx_handler(
-    State(state): State<crate::AppState>,
-    headers: HeaderMap,
-) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
-    let auth_uri = headers.get("x-original-uri").and_then(|h| h.to_str().ok()).unwrap_or("/");
-    let method = headers.get("x-original-method").and_then(|h| h.to_str().ok()).unwrap_or("GET");
-    let user_agent = headers.get("user-agent").and_then(|h| h.to_str().ok()).unwrap_or("");
-
-    let is_webdav = is_webdav(method, user_agent);
-
-    tracing::info!("Nginx auth check: method={}, user_agent={}, is_webdav={}", method, user_agent, is_webdav);
-    
-    if !is_webdav {
-        if auth_uri.starts_with("/css/") || auth_uri.starts_with("/images/") || auth_uri.starts_with("/js/") || auth_uri.starts_with("/fonts/") || auth_uri.starts_with("/assets/") || (auth_uri.starts_with("/") && !auth_uri[1..].contains('/') && auth_uri.contains('.')) {
-            tracing::info!("Skipping auth for non-WebDAV CSR request");
-            return Ok(Response::builder().status(200).body("".into()).unwrap());
-        }
-        if auth_uri.starts_with("/auth/") || auth_uri.starts_with("/fs/") {
-            tracing::info!("Skipping auth for non-WebDAV API request");
-            return Ok(Response::builder().status(200).body("".into()).unwrap());
-        }
-        if auth_uri == "/" {
-            tracing::info!("Skipping auth for CSR request");
-            return Ok(Response::builder().status(200).body("".into()).unwrap());
-        }
-    }
-
-    let uri = Uri::try_from(auth_uri).unwrap_or(Uri::from_static("/"));
-    let auth_request = AuthRequest::new(&uri, method, &headers);
-    let auth_request_clone = auth_request.clone();
-    match keycloak::check_auth(&state, &auth_request_clone, state.passwd.clone(), state.tokens.clone()).await {
-        Ok(auth_identity) => {
-
-            tracing::info!("auth_identity: {}", serde_json::to_string(&auth_identity).unwrap());
-            let json = Json(auth_identity.clone());
-            let mut response = json.into_response();
-            let auth = auth_identity.folder.and_then(|f| Some(f.access_token()));
-            response.headers_mut().insert("X-Webdav-Socket", HeaderValue::from_static("media"));
-            if let Some(auth) = auth{
-                response.headers_mut().insert("X-Socket-Auth", HeaderValue::from_str(&auth).unwrap());
-            }
-            //Ok(response)
-            Ok(response)
-        },
-        Err((status, msg)) => {
-            tracing::info!("Nginx auth failed for {}", auth_request.url.as_ref().unwrap().clone());
-            Err((status, msg))
-        }
-    }
-}
-
 pub fn is_webdav(method: &str, user_agent: &str) -> bool {
     match method {
         "PROPFIND" | "MKCOL" | "COPY" | "MOVE" | "LOCK" | "UNLOCK" | "OPTIONS" => true,
@@ -121,6 +95,31 @@ pub fn is_webdav(method: &str, user_agent: &str) -> bool {
     }
 }
 
+// POST /auth/v1/logout, with the access token as for any request and
+// optionally {"refresh_token": ".."}
+pub async fn logout_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let request: LogoutRequest = if body.is_empty() {
+        LogoutRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e.to_string()}))))?
+    };
+    let auth_request = AuthRequest::new(&uri, method.as_str(), &headers);
+    let jwt_token = auth_request.jwt_token.clone()
+        .ok_or((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "no token"}))))?;
+    let auth = keycloak::check_auth(&state, &auth_request, state.passwd.clone(), state.tokens.clone()).await?;
+    keycloak::logout(&state, &jwt_token, &auth, request.refresh_token.as_deref()).await;
+
+    let mut response = StatusCode::NO_CONTENT.into_response();
+    response.headers_mut().insert("Set-Cookie", HeaderValue::from_static("jwt_token=; Max-Age=0; Path=/"));
+    Ok(response)
+}
+
 pub async fn refresh_handler(
     State(state): State<crate::AppState>,
     Json(refresh_req): Json<RefreshRequest>,
@@ -128,8 +127,6 @@ pub async fn refresh_handler(
     let response = keycloak::refresh_token(
         state.clone(),
         refresh_req,
-        &state.http_client,
-        state.passwd.clone(),
         state.tokens.clone(),
     )
     .await
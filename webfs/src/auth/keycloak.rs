@@ -1,28 +1,48 @@
-use axum::{http::{StatusCode, Uri}, response::Json};
+use axum::{http::StatusCode, response::Json};
 use base64::Engine;
 use chrono::Utc;
-use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use async_trait::async_trait;
 use lazy_static::lazy_static;
 use openssl::string;
 use reqwest::Client;
 use sha2::{Sha256, Digest};
-use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::time::Duration;
+use hmac::{Hmac, Mac};
+use rand::TryRngCore;
+use rand::rngs::OsRng;
 use tokio::sync::RwLock;
 use moka::future::Cache;
-use std::time::{Duration, Instant};
 use tracing;
 
-use crate::models::{auth::*, files::FolderShare};
+use crate::audit;
+use crate::models::{audit::{AuditEvent, AuditKind}, auth::*, files::FolderShare};
+use super::oidc::{JwtPolicy, OidcEndpoints, OidcProvider};
+use super::provider::AuthProvider;
 
-struct CachedJWKS {
-  jwks: JWKS,
-  fetched_at: Instant,
-}
+type HmacSha256 = Hmac<Sha256>;
+
+// How long a rejected token is answered from cache
+const REJECTED_TTL: Duration = Duration::from_secs(60);
+// How long a logged out session stays revoked when its refresh token's expiry is unknown
+const REVOKED_SESSION_SECS: u64 = 3600 * 24 * 30;
 
 lazy_static! {
-  static ref JWKS_CACHE: Arc<RwLock<Option<CachedJWKS>>> = Arc::new(RwLock::new(None));
-  pub static ref SIGNING_KEYS: Arc<RwLock<SigningKeys>> = Arc::new(RwLock::new(SigningKeys::new(3600 * 24 * 30, 3600)));
+  pub static ref SIGNING_KEYS: Arc<RwLock<SigningKeys>> = Arc::new(RwLock::new(SigningKeys::from_env(3600 * 24 * 30, 3600)));
+  // Random per process, so cache keys can't be computed from outside
+  static ref CACHE_SALT: [u8; 32] = {
+    let mut salt = [0u8; 32];
+    OsRng.try_fill_bytes(&mut salt).unwrap();
+    salt
+  };
+  // Revoked "sid:.." and "jti:.." ids with their expiry, loaded from storage on first use
+  static ref REVOKED: std::sync::RwLock<Option<HashMap<String, u64>>> = std::sync::RwLock::new(None);
+  // Tokens the provider rejected, so a flood of bad tokens doesn't reach it
+  static ref REJECTED_TOKENS: Cache<String, String> = Cache::builder()
+    .max_capacity(100_000)
+    .time_to_live(REJECTED_TTL)
+    .build();
 }
 
 fn hash_token(token: &str) -> String {
@@ -31,259 +51,209 @@ fn hash_token(token: &str) -> String {
     format!("{:x}", hasher.finalize())
 }
 
-async fn get_jwks(keycloak_url: &str, realm: &str, http_client: &Client) -> Result<JWKS, StatusCode> {
-    // Check cache
-  {
-    let cache = JWKS_CACHE.read().await;
-    if let Some(cached) = &*cache {
-      if cached.fetched_at.elapsed() < Duration::from_secs(14400) { // 4 hours
-        return Ok(cached.jwks.clone());
-      }
-    }
-  }
-
-    // Fetch new
-    let jwks_url = format!("{}/realms/{}/protocol/openid-connect/certs", keycloak_url, realm);
-    let jwks_response = http_client
-        .get(&jwks_url)
-        .send()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+// Key of the passwd, tokens and rejected caches: never the token or password itself
+pub fn cache_key(value: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(CACHE_SALT.as_slice()).unwrap();
+    mac.update(value.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
 
-    let jwks: JWKS = jwks_response
-        .json()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+fn password_key(username: &str, password: &str) -> String {
+    cache_key(&format!("{}\0{}", username, password))
+}
 
-    // Update cache
-    let mut cache = JWKS_CACHE.write().await;
-    *cache = Some(CachedJWKS {
-        jwks: jwks.clone(),
-        fetched_at: Instant::now(),
+// Whether the session or token of `claims` was logged out
+pub fn is_revoked(state: &crate::AppState, claims: &Claims) -> bool {
+    let ids = [claims.sid.as_ref().map(|sid| format!("sid:{}", sid)), claims.jti.as_ref().map(|jti| format!("jti:{}", jti))];
+    let now = Utc::now().timestamp() as u64;
+    let check = |revoked: &HashMap<String, u64>| ids.iter().flatten().any(|id| revoked.get(id).is_some_and(|exp| *exp > now));
+    if let Some(revoked) = REVOKED.read().unwrap().as_ref() {
+        return check(revoked);
+    }
+    let loaded = state.storage.lock().unwrap().revoked().unwrap_or_else(|e| {
+        tracing::error!("Failed to load revoked tokens: {}", e);
+        HashMap::new()
     });
-
-    Ok(jwks)
+    let result = check(&loaded);
+    REVOKED.write().unwrap().get_or_insert(loaded);
+    result
 }
 
-pub async fn authenticate(
-    state: crate::AppState,
-    auth_req: BasicAuthRequest,
-    http_client: &Client,
-    passwd: Cache<String, AuthResponse>,
-    tokens: Cache<String, AuthResponse>
-) -> Result<AuthResponse, (StatusCode, String)> {
-    if auth_req.use_cache{
-        let key = format!("{}:{}", &auth_req.username, &auth_req.password);
-        if let Some(auth) = passwd.get(&key).await {
-            return Ok(auth);
-        }
+// Refuse a "sid:.." or "jti:.." from now until `expires`
+pub fn revoke(state: &crate::AppState, id: String, expires: u64) {
+    if let Err(e) = state.storage.lock().unwrap().revoke(&id, expires) {
+        tracing::error!("Failed to store revoked {}: {}", id, e);
     }
+    let mut revoked = REVOKED.write().unwrap();
+    if let Some(revoked) = revoked.as_mut() {
+        revoked.insert(id, expires);
+    }
+}
 
-    let token_url = format!(
-        "{}/realms/{}/protocol/openid-connect/token",
-        state.keycloak_url, state.realm
-    );
-
-    let mut params = HashMap::new();
-    params.insert("client_id", state.client_id.to_string());
-    params.insert("client_secret", state.client_secret.to_string());
-    params.insert("grant_type", "password".to_string());
-    params.insert("username", auth_req.username.clone());
-    params.insert("password", auth_req.password.clone());
-    params.insert("scope", "openid".to_string());
-
-    tracing::debug!("Login attempt {} for user: {}", &token_url, auth_req.username);
-    let response = http_client
-        .post(&token_url)
-        .form(&params)
-        .send()
-        .await
-        .map_err(|e| {
-            tracing::debug!("Error sending auth request: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to send authentication request: {}", e))
-        })?;
-
-    if response.status().is_success() {
-        let token: TokenResponse = response
-            .json()
-            .await
-            .map_err(|e| {
-                tracing::debug!("Error parsing token response: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to parse token response: {}", e))
-            })?;
-
-        // Decode claims
-        let claims = decode_jwt_payload_struct(&token.access_token)
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to decode JWT claims: {}", e)))?;
-        tracing::debug!("Login successful for user: {}", auth_req.username);
+// Cached logins outlive their access token when the token is short-lived
+fn unexpired(auth: AuthResponse) -> Option<AuthResponse> {
+    (auth.claims.exp > Utc::now().timestamp() as u64).then_some(auth)
+}
 
-        // Calculate expiration dates
-        let now = Utc::now();
-        let expires_at = (now + chrono::Duration::seconds(token.expires_in as i64)).to_rfc3339();
-        let refresh_expires_at = (now + chrono::Duration::seconds(token.refresh_expires_in as i64)).to_rfc3339();
+// Keycloak realm endpoints; access tokens are issued for the "account" audience
+pub struct KeycloakProvider {
+    oidc: OidcProvider,
+    logout_url: String,
+}
 
-        let mut folder: Option<FolderShare> = None;
-        if let Some(ref fs_id) = claims.default_webdavfs {
-            if !fs_id.is_empty(){
-                folder = state.config.folders.get(fs_id).cloned();
-                if folder.is_none() {
-                    return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Folder {} not found", fs_id)));
-                }                
-            }
-        }
-        let resp = AuthResponse {
-            jwt_token: token.access_token.clone(),
-            refresh_token: token.refresh_token,
-            token_hash: hash_token(&token.access_token),
-            expires_at,
-            refresh_expires_at,
-            claims,
-            folder,
-        };
-        passwd.insert(format!("{}:{}", &auth_req.username, &auth_req.password), resp.clone()).await;
-        tokens.insert(resp.token_hash.clone(), resp.clone()).await;
-        tokens.insert(resp.jwt_token.clone(), resp.clone()).await;
-        Ok(resp)
-    } else {
-        let body = response.text().await.unwrap_or_else(|_| "Failed to read response body".to_string());
-        let error_msg = if let Ok(keycloak_err) = serde_json::from_str::<KeycloakError>(&body) {
-            keycloak_err.error_description.unwrap_or_else(|| body.clone())
-        } else {
-            body.clone()
+impl KeycloakProvider {
+    pub fn new(keycloak_url: &str, realm: &str, client_id: &str, client_secret: &str, http_client: Client) -> KeycloakProvider {
+        let issuer = format!("{}/realms/{}", keycloak_url, realm);
+        let endpoints = OidcEndpoints {
+            token_endpoint: format!("{}/protocol/openid-connect/token", issuer),
+            jwks_uri: format!("{}/protocol/openid-connect/certs", issuer),
+            revocation_endpoint: None,
+            issuer,
         };
-        tracing::debug!("Login invalid for user: {}, response body: {}", auth_req.username, body);
-        Err((StatusCode::UNAUTHORIZED, error_msg))
+        KeycloakProvider {
+            logout_url: format!("{}/protocol/openid-connect/logout", endpoints.issuer),
+            oidc: OidcProvider::new("keycloak", endpoints, client_id, client_secret, "account", JwtPolicy::from_env(client_id), http_client),
+        }
     }
 }
 
-pub async fn verify_token(
-    keycloak_url: &str,
-    realm: &str,
-    token: &str,
-    http_client: &Client,
-) -> Result<bool, StatusCode> {
-    let jwks = get_jwks(keycloak_url, realm, http_client).await?;
-
-    // Decode header to get kid
-    let header = decode_header(token).map_err(|_| StatusCode::UNAUTHORIZED)?;
-    let kid = header.kid.ok_or(StatusCode::UNAUTHORIZED)?;
-    tracing::debug!("Verify JWT Token Kid: {}", kid);
+#[async_trait]
+impl AuthProvider for KeycloakProvider {
+    fn name(&self) -> &str {
+        "keycloak"
+    }
 
-    // Find the key
-    let key = jwks.keys.into_iter().find(|k| k.kid == kid).ok_or(StatusCode::UNAUTHORIZED)?;
+    async fn authenticate(&self, username: &str, password: &str) -> Result<TokenResponse, (StatusCode, String)> {
+        self.oidc.authenticate(username, password).await
+    }
 
-    // Create decoding key
-    let decoding_key = DecodingKey::from_rsa_components(&key.n, &key.e).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    async fn refresh(&self, refresh_token: &str) -> Result<TokenResponse, (StatusCode, String)> {
+        self.oidc.refresh(refresh_token).await
+    }
 
-    // Validate
-    let mut validation = Validation::new(Algorithm::RS256);
-    validation.set_issuer(&[&format!("{}/realms/{}", keycloak_url, realm)]);
-    validation.set_audience(&["account"]);
-    validation.validate_exp = false; // We check exp manually
+    async fn verify(&self, token: &str) -> Result<Claims, (StatusCode, String)> {
+        self.oidc.verify(token).await
+    }
 
-    tracing::debug!("Validation Start: {}", format!("{}/realms/{}", keycloak_url, realm));
-    match decode::<Claims>(token, &decoding_key, &validation) {
-        Ok(token_data) => {
-            // Check expiration manually
-            let now = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-                .as_secs() as u64;
-            tracing::debug!("Auth Token Data Claims Exp: {} {}", token_data.claims.exp.to_string(), token_data.claims.exp > now);
-            if token_data.claims.exp > now {
-                Ok(true)
-            } else {
-                Ok(false)
-            }
-        }
-        Err(e) => {
-            tracing::debug!("Token verification error: {}", e);
-            // If signature validation failed, refresh JWKS and try again
-            if matches!(e.kind(), jsonwebtoken::errors::ErrorKind::InvalidSignature) {
-                // Clear cache
-                {
-                    let mut cache = JWKS_CACHE.write().await;
-                    *cache = None;
-                }
-                // Get fresh JWKS
-                let fresh_jwks = get_jwks(keycloak_url, realm, http_client).await?;
-                let fresh_key = fresh_jwks.keys.into_iter().find(|k| k.kid == kid).ok_or(StatusCode::UNAUTHORIZED)?;
-                let fresh_decoding_key = DecodingKey::from_rsa_components(&fresh_key.n, &fresh_key.e).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-                match decode::<Claims>(token, &fresh_decoding_key, &validation) {
-                    Ok(_) => Ok(true),
-                    Err(_) => Ok(false),
-                }
-            } else {
-                Ok(false)
-            }
+    // Backchannel logout ends the whole Keycloak session, not just this token
+    async fn logout(&self, refresh_token: Option<&str>) -> Result<(), (StatusCode, String)> {
+        let Some(refresh_token) = refresh_token else { return Ok(()) };
+        let mut params = HashMap::new();
+        params.insert("refresh_token", refresh_token.to_string());
+        let response = self.oidc.post_form(&self.logout_url, params).await?;
+        if !response.status().is_success() {
+            return Err((StatusCode::BAD_GATEWAY, format!("Keycloak logout failed: {}", response.status())));
         }
+        Ok(())
     }
 }
 
-pub async fn refresh_token(
+pub async fn authenticate(
     state: crate::AppState,
-    refresh_req: RefreshRequest,
-    http_client: &Client,
+    auth_req: BasicAuthRequest,
     passwd: Cache<String, AuthResponse>,
     tokens: Cache<String, AuthResponse>
 ) -> Result<AuthResponse, (StatusCode, String)> {
-    let token_url = format!(
-        "{}/realms/{}/protocol/openid-connect/token",
-        state.keycloak_url, state.realm
-    );
-
-    let mut params = HashMap::new();
-    params.insert("client_id", state.client_id.to_string());
-    params.insert("client_secret", state.client_secret.to_string());
-    params.insert("grant_type", "refresh_token".to_string());
-    params.insert("refresh_token", refresh_req.refresh_token);
+    if auth_req.use_cache{
+        let key = password_key(&auth_req.username, &auth_req.password);
+        if let Some(auth) = passwd.get(&key).await.and_then(unexpired) {
+            return Ok(auth);
+        }
+    }
 
-    let response = http_client
-        .post(&token_url)
-        .form(&params)
-        .send()
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to send refresh request".to_string()))?;
+    tracing::debug!("Login attempt with {} for user: {}", state.auth.name(), auth_req.username);
+    let token = state.auth.authenticate(&auth_req.username, &auth_req.password).await.map_err(|(status, msg)| {
+        tracing::debug!("Login invalid for user: {}: {}", auth_req.username, msg);
+        (status, msg)
+    })?;
+    tracing::debug!("Login successful for user: {}", auth_req.username);
+    let resp = auth_response(&state, token)?;
+    super::apikey::refresh_owner(&state, &resp.claims);
+    passwd.insert(password_key(&auth_req.username, &auth_req.password), resp.clone()).await;
+    tokens.insert(cache_key(&resp.jwt_token), resp.clone()).await;
+    Ok(resp)
+}
 
-    if response.status().is_success() {
-        let token: TokenResponse = response
-            .json()
-            .await
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse refresh response".to_string()))?;
+pub async fn refresh_token(
+    state: crate::AppState,
+    refresh_req: RefreshRequest,
+    tokens: Cache<String, AuthResponse>
+) -> Result<AuthResponse, (StatusCode, String)> {
+    let token = state.auth.refresh(&refresh_req.refresh_token).await?;
+    let resp = auth_response(&state, token)?;
+    if is_revoked(&state, &resp.claims) {
+        return Err((StatusCode::UNAUTHORIZED, "Session logged out".to_string()));
+    }
+    super::apikey::refresh_owner(&state, &resp.claims);
+    tokens.insert(cache_key(&resp.jwt_token), resp.clone()).await;
+    Ok(resp)
+}
 
-        // Decode claims
-        let claims = decode_jwt_payload_struct(&token.access_token)
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Failed to decode JWT claims".to_string()))?;
+// Log out the session of `auth`: end it at the provider, drop every cached
+// login and token of it, and refuse its sid and jti until they expire
+pub async fn logout(state: &crate::AppState, jwt_token: &str, auth: &AuthInfo, refresh_token: Option<&str>) {
+    if let Err((status, msg)) = state.auth.logout(refresh_token).await {
+        // The session is still revoked here, so this only matters for other clients of the provider
+        tracing::warn!("Provider logout failed: {} {}", status, msg);
+    }
+    let claims = &auth.claims;
+    let same_session = |cached: &AuthResponse| {
+        (claims.sid.is_some() && cached.claims.sid == claims.sid) || (claims.jti.is_some() && cached.claims.jti == claims.jti)
+    };
+    for cache in [&state.tokens, &state.passwd] {
+        let keys: Vec<String> = cache.iter()
+            .filter(|(_, cached)| same_session(cached))
+            .map(|(key, _)| (*key).clone())
+            .collect();
+        for key in keys {
+            cache.invalidate(&key).await;
+        }
+    }
+    state.tokens.invalidate(&cache_key(jwt_token)).await;
 
-        // Calculate expiration dates
-        let now = Utc::now();
-        let expires_at = (now + chrono::Duration::seconds(token.expires_in as i64)).to_rfc3339();
-        let refresh_expires_at = (now + chrono::Duration::seconds(token.refresh_expires_in as i64)).to_rfc3339();
+    if let Some(jti) = &claims.jti {
+        revoke(state, format!("jti:{}", jti), claims.exp);
+    }
+    if let Some(sid) = &claims.sid {
+        // Refresh tokens of the session live longer than the access token
+        let refresh_exp = refresh_token
+            .and_then(|t| decode_jwt_payload_struct(t).ok())
+            .map(|c| c.exp)
+            .unwrap_or(claims.exp + REVOKED_SESSION_SECS);
+        revoke(state, format!("sid:{}", sid), refresh_exp.max(claims.exp));
+    }
+    tracing::info!("Logged out {} (sid {:?})", claims.preferred_username.as_deref().unwrap_or(&claims.sub), claims.sid);
+}
 
-        let mut folder: Option<FolderShare> = None;
-        if let Some(ref fs_id) = claims.default_webdavfs {
-            if !fs_id.is_empty(){
-                folder = state.config.folders.get(fs_id).cloned();
-                if folder.is_none() {
-                    return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Folder {} not found", fs_id)));
-                }                
+// The login response for a token from the provider, with the user's folder share
+fn auth_response(state: &crate::AppState, token: TokenResponse) -> Result<AuthResponse, (StatusCode, String)> {
+    // Decode claims
+    let claims = decode_jwt_payload_struct(&token.access_token)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to decode JWT claims: {}", e)))?;
+
+    // Calculate expiration dates
+    let now = Utc::now();
+    let expires_at = (now + chrono::Duration::seconds(token.expires_in as i64)).to_rfc3339();
+    let refresh_expires_at = (now + chrono::Duration::seconds(token.refresh_expires_in as i64)).to_rfc3339();
+
+    let mut folder: Option<FolderShare> = None;
+    if let Some(ref fs_id) = claims.default_webdavfs {
+        if !fs_id.is_empty(){
+            folder = state.config.folders.get(fs_id).cloned();
+            if folder.is_none() {
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, format!("Folder {} not found", fs_id)));
             }
         }
-        let resp = AuthResponse {
-            jwt_token: token.access_token.clone(),
-            refresh_token: token.refresh_token,
-            token_hash: hash_token(&token.access_token),
-            expires_at,
-            refresh_expires_at,
-            claims,
-            folder,
-        };
-        tokens.insert(resp.token_hash.clone(), resp.clone()).await;
-        tokens.insert(resp.jwt_token.clone(), resp.clone()).await;
-        Ok(resp)
-    } else {
-        Err((StatusCode::UNAUTHORIZED, "Invalid refresh token".to_string()))
     }
+    Ok(AuthResponse {
+        jwt_token: token.access_token.clone(),
+        refresh_token: token.refresh_token,
+        token_hash: hash_token(&token.access_token),
+        expires_at,
+        refresh_expires_at,
+        claims,
+        folder,
+    })
 }
 
 pub fn decode_jwt_payload_struct(token: &str) -> Result<Claims, Box<dyn std::error::Error>> {
@@ -300,27 +270,42 @@ pub fn decode_jwt_payload_struct(token: &str) -> Result<Claims, Box<dyn std::err
 
 pub async fn check_auth(state: &crate::AppState, request: &AuthRequest, passwd: Cache<String, AuthResponse>, tokens: Cache<String, AuthResponse>) -> 
     Result<AuthInfo, (StatusCode, Json<serde_json::Value>)>{
-    if !request.jwt_token.is_none() {
-        let jwt_token = request.jwt_token.as_ref().unwrap().clone();
-        tracing::debug!("Auth JWT token: {}", &jwt_token);
-        if let Some(auth) = tokens.get(&jwt_token).await {
+    let result = verify_request(state, request, passwd, tokens).await;
+    // Requests without credentials are anonymous, not failed
+    if let Err((status, msg)) = &result {
+        if *status == StatusCode::UNAUTHORIZED && request.has_credentials() {
+            let reason = msg.get("error").and_then(|e| e.as_str()).unwrap_or_default();
+            audit::record(AuditEvent::from_request(AuditKind::AuthFailed, request, status.as_u16()).detail(reason));
+        }
+    }
+    result
+}
+
+async fn verify_request(state: &crate::AppState, request: &AuthRequest, passwd: Cache<String, AuthResponse>, tokens: Cache<String, AuthResponse>) -> 
+    Result<AuthInfo, (StatusCode, Json<serde_json::Value>)>{
+    if let Some(jwt_token) = request.jwt_token.as_ref() {
+        let key = cache_key(jwt_token);
+        if let Some(msg) = REJECTED_TOKENS.get(&key).await {
+            return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": msg}))));
+        }
+        if let Some(auth) = tokens.get(&key).await.and_then(unexpired) {
             return Ok(AuthInfo::FromAuth(auth));
         }
-        let active = verify_token(
-            &state.keycloak_url,
-            &state.realm,
-            &request.jwt_token.as_ref().unwrap(),
-            &state.http_client,
-        )
-        .await
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "token verification failed"}))))?;
-        
-        if !active {
-            return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "token inactive"}))));
+        let claims = match state.auth.verify(jwt_token).await {
+            Ok(claims) => claims,
+            Err((status, msg)) => {
+                // Provider or network failures are not the token's fault
+                if status == StatusCode::UNAUTHORIZED {
+                    REJECTED_TOKENS.insert(key, msg.clone()).await;
+                }
+                return Err((status, Json(serde_json::json!({"error": msg}))));
+            }
+        };
+        if is_revoked(state, &claims) {
+            REJECTED_TOKENS.insert(key, "token revoked".to_string()).await;
+            return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "token revoked"}))));
         }
-
-        let claims = decode_jwt_payload_struct(&jwt_token)
-            .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "failed to decode claims"}))))?;
+        super::apikey::refresh_owner(state, &claims);
         let folder = if let Some(ref fs_id) = claims.default_webdavfs {
             state.config.folders.get(fs_id).cloned()
         } else {
@@ -328,13 +313,15 @@ pub async fn check_auth(state: &crate::AppState, request: &AuthRequest, passwd:
         };
         return Ok(AuthInfo::new(claims, folder));
     }
+    if let Some(api_key) = request.api_key.as_ref() {
+        return super::apikey::authenticate(state, request, api_key);
+    }
     let basic_auth = request.basic_auth();
     if let Some(basic_auth) = basic_auth {
-        tracing::debug!("Keycloak auth: {} / {}", &basic_auth.username, &basic_auth.password);
+        tracing::debug!("Basic auth: {}", &basic_auth.username);
         match authenticate(
             state.clone(),
             basic_auth,
-            &state.http_client,
             state.passwd.clone(),
             state.tokens.clone(),
         ).await {
@@ -342,30 +329,35 @@ pub async fn check_auth(state: &crate::AppState, request: &AuthRequest, passwd:
                 return Ok(AuthInfo::FromAuth(auth_resp))
             }
             Err((status, msg)) => {
-                tracing::debug!("Keycloak Failed: {} / {}", status.as_str(), msg);
-                return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "Keycloak authentication failed", "status": status.as_u16(), "msg": msg}))));
+                tracing::debug!("Basic auth failed: {} / {}", status.as_str(), msg);
+                return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "Authentication failed", "status": status.as_u16(), "msg": msg}))));
             }
         }
     }
     if let (Some(uri), Some(method)) = (request.url.as_ref(), request.method.as_ref()) {
         if uri.contains("key_id="){
             let signing_keys = SIGNING_KEYS.clone();
-            let uri_obj = Uri::try_from(uri).unwrap();
             tracing::debug!("Verify signurl attempt {} for user: {}", &method, uri.clone());
             match SignUrlResponse::from_url(&method, uri){
                 Ok(resp) => {
+                    // Keys rotated or revoked by another instance
+                    if signing_keys.read().await.stale(&resp.key_id) {
+                        if let Err(e) = signing_keys.write().await.reload() {
+                            tracing::error!("Failed to reload signing keyring: {}", e);
+                        }
+                    }
                     let signing_keys = signing_keys.read().await;
-                    match signing_keys.verify_signed_url(&resp).await {
-                        Ok(_) => {
-                            let query = uri_obj.query().unwrap_or("");
+                    match signing_keys.verify_signed_url(&resp) {
+                        Ok(grant) => {
                             drop(signing_keys);
-                            let auth = tokens.get(&resp.tid).await
-                                .map(|auth| Ok(AuthInfo::FromAuth(auth)))
-                                .unwrap_or(Err((StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "token verification failed"})))))?;
-                            return Ok(auth)
+                            return signed_grant(state, request, grant)
+                                .map_err(|msg| {
+                                    tracing::debug!("Signurl {} refused: {}", &resp.id, msg);
+                                    (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": msg})))
+                                });
                         }
                         Err(e) => {
-                            tracing::debug!("Bad signurl: {} / {}", &resp.tid, &resp.key_id);
+                            tracing::debug!("Bad signurl: {} / {}", &resp.id, &resp.key_id);
                             return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": e.to_string()}))))
                         }
                     }
@@ -378,4 +370,57 @@ pub async fn check_auth(state: &crate::AppState, request: &AuthRequest, passwd:
         }
     }
     return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "no token"}))));
-}
\ No newline at end of file
+}
+
+// The identity a verified signed URL carries, once the limits its signature
+// doesn't settle alone are checked
+fn signed_grant(state: &crate::AppState, request: &AuthRequest, grant: SignedGrant) -> Result<AuthInfo, String> {
+    if grant.sub.is_empty() {
+        return Err("URL carries no identity".to_string());
+    }
+    if let Some(ip) = &grant.ip {
+        if request.client_ip.as_ref() != Some(ip) {
+            return Err("URL is bound to another address".to_string());
+        }
+    }
+    let now = Utc::now().timestamp() as u64;
+    let claims = Claims {
+        acr: None,
+        address: None,
+        allowed_origins: None,
+        aud: String::new(),
+        azp: None,
+        default_webdavfs: Some(grant.fs_id.clone()).filter(|fs| !fs.is_empty()),
+        email_verified: None,
+        exp: grant.expires,
+        family_name: None,
+        given_name: None,
+        groups: None,
+        iat: now,
+        iss: "webfs-signurl".to_string(),
+        jti: Some(grant.id.clone()),
+        preferred_username: Some(grant.sub.clone()),
+        resource_access: None,
+        roles: None,
+        scope: None,
+        session_state: None,
+        sid: grant.sid.clone(),
+        sub: grant.sub.clone(),
+        typ: Some("Signed".to_string()),
+    };
+    if is_revoked(state, &claims) {
+        return Err("token revoked".to_string());
+    }
+    if let Some(max_uses) = grant.max_uses {
+        let uses = state.storage.lock().unwrap().use_signed_url(&grant.id, grant.expires)
+            .map_err(|e| {
+                tracing::error!("Failed to count signed URL use: {}", e);
+                "token verification failed".to_string()
+            })?;
+        if uses > max_uses {
+            return Err("URL used up".to_string());
+        }
+    }
+    let folder = state.config.folders.get(if grant.fs_id.is_empty() { "default" } else { &grant.fs_id }).cloned();
+    Ok(AuthInfo { claims, folder, grant: Some(grant), scope: None })
+}
@@ -0,0 +1,120 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::{anyhow, Context, Result};
+use chacha20poly1305::{aead::{Aead, KeyInit}, ChaCha20Poly1305, Key, Nonce};
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::models::auth::HmacSigningKey;
+
+const MAGIC: &[u8] = b"WFSK1";
+const NONCE_LEN: usize = 12;
+
+#[derive(Default, Serialize, Deserialize)]
+struct Keyring {
+    keys: Vec<HmacSigningKey>,
+}
+
+// The signed URL keys in one file, so every webfs instance sharing it (and
+// the utils command) signs and verifies with the same keys. The file is
+// sealed with ChaCha20-Poly1305 under a key derived from SIGNING_MASTER_KEY.
+pub struct KeyringFile {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for KeyringFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyringFile").field("path", &self.path).finish()
+    }
+}
+
+impl KeyringFile {
+    pub fn new(path: &str, master_key: &str) -> KeyringFile {
+        let key = Sha256::digest(master_key.as_bytes());
+        KeyringFile {
+            path: PathBuf::from(path),
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    // SIGNING_MASTER_KEY (a long random string, e.g. `openssl rand -base64 32`)
+    // and SIGNING_KEYRING, the file path. None without a master key.
+    pub fn from_env() -> Option<KeyringFile> {
+        let master_key = std::env::var("SIGNING_MASTER_KEY").ok().filter(|k| !k.is_empty())?;
+        let path = std::env::var("SIGNING_KEYRING").unwrap_or("/srv/data/webfs/signing.keys".to_string());
+        Some(KeyringFile::new(&path, &master_key))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // When the file last changed; None while it doesn't exist
+    pub fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    // All keys in the file, revoked ones included; empty when there is no file yet
+    pub fn load(&self) -> Result<Vec<HmacSigningKey>> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read keyring {}", self.path.display())),
+        };
+        if data.len() < MAGIC.len() + NONCE_LEN || !data.starts_with(MAGIC) {
+            return Err(anyhow!("{} is not a webfs keyring", self.path.display()));
+        }
+        let (nonce, sealed) = data[MAGIC.len()..].split_at(NONCE_LEN);
+        let plain = self.cipher.decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| anyhow!("Failed to decrypt keyring {}: wrong SIGNING_MASTER_KEY?", self.path.display()))?;
+        let keyring: Keyring = serde_json::from_slice(&plain)?;
+        Ok(keyring.keys)
+    }
+
+    // Change the keys under an exclusive lock, so instances rotating at the
+    // same time don't drop each other's keys. Returns the keys written.
+    pub fn update<F: FnOnce(&mut Vec<HmacSigningKey>) -> Result<()>>(&self, change: F) -> Result<Vec<HmacSigningKey>> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let lock = OpenOptions::new().create(true).truncate(false).write(true).open(self.sibling("lock"))?;
+        lock.lock()?;
+        // A file we can't read is never overwritten
+        let mut keys = self.load()?;
+        change(&mut keys)?;
+        self.write(&keys)?;
+        Ok(keys)
+    }
+
+    fn sibling(&self, ext: &str) -> PathBuf {
+        PathBuf::from(format!("{}.{}", self.path.display(), ext))
+    }
+
+    fn write(&self, keys: &[HmacSigningKey]) -> Result<()> {
+        let plain = serde_json::to_vec(&Keyring { keys: keys.to_vec() })?;
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.try_fill_bytes(&mut nonce)?;
+        let sealed = self.cipher.encrypt(Nonce::from_slice(&nonce), plain.as_slice())
+            .map_err(|_| anyhow!("Failed to encrypt keyring"))?;
+        // Readers see the old file or the new one, never half of it
+        let tmp = self.sibling("tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                file.set_permissions(fs::Permissions::from_mode(0o600))?;
+            }
+            file.write_all(MAGIC)?;
+            file.write_all(&nonce)?;
+            file.write_all(&sealed)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
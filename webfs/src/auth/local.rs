@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use anyhow::Context;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use serde::Deserialize;
+use crate::models::auth::{Claims, TokenResponse};
+use super::provider::AuthProvider;
+
+const ISSUER: &str = "webfs-local";
+const AUDIENCE: &str = "webfs";
+const ACCESS_TOKEN_SECS: u64 = 3600;
+const REFRESH_TOKEN_SECS: u64 = 3600 * 24 * 30;
+
+// Users of the local backend, from AUTH_USERS_FILE:
+//
+//   users:
+//     alice:
+//       password: "$argon2id$v=19$m=19456,t=2,p=1$..."   # or bcrypt "$2b$12$..."
+//       groups: ["gjcc"]
+//       roles: ["staff"]
+//       default_webdavfs: "default"
+//       name: "Alice"
+#[derive(Debug, Deserialize)]
+pub struct UsersFile {
+    #[serde(default)]
+    pub users: HashMap<String, LocalUser>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalUser {
+    // argon2 or bcrypt hash
+    pub password: String,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub default_webdavfs: Option<String>,
+    pub name: Option<String>,
+}
+
+impl LocalUser {
+    fn verify_password(&self, password: &str) -> bool {
+        if self.password.starts_with("$argon2") {
+            PasswordHash::new(&self.password)
+                .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+                .unwrap_or(false)
+        } else if self.password.starts_with("$2") {
+            bcrypt::verify(password, &self.password).unwrap_or(false)
+        } else {
+            false
+        }
+    }
+}
+
+// Users from a file, with HS256 tokens issued by webfs itself. For small
+// deployments, or a laptop without an identity server.
+pub struct LocalProvider {
+    users: HashMap<String, LocalUser>,
+    secret: Vec<u8>,
+}
+
+impl LocalProvider {
+    pub fn from_file(path: &str, secret: Option<String>) -> anyhow::Result<LocalProvider> {
+        let file = std::fs::File::open(path).with_context(|| format!("Failed to open users file {}", path))?;
+        let users: UsersFile = serde_yaml::from_reader(file).with_context(|| format!("Failed to parse users file {}", path))?;
+        for (name, user) in &users.users {
+            if !user.password.starts_with("$argon2") && !user.password.starts_with("$2") {
+                tracing::warn!("User {} has no argon2 or bcrypt password hash and cannot log in", name);
+            }
+        }
+        tracing::info!("Loaded {} local users from {}", users.users.len(), path);
+        Ok(LocalProvider::new(users.users, secret))
+    }
+
+    pub fn new(users: HashMap<String, LocalUser>, secret: Option<String>) -> LocalProvider {
+        let secret = match secret.filter(|s| !s.is_empty()) {
+            Some(secret) => secret.into_bytes(),
+            None => {
+                tracing::warn!("AUTH_SECRET not set; local tokens are invalidated on restart");
+                let mut key = vec![0u8; 32];
+                OsRng.try_fill_bytes(&mut key).unwrap();
+                key
+            }
+        };
+        LocalProvider { users, secret }
+    }
+
+    fn claims(&self, username: &str, user: &LocalUser, sid: &str, typ: &str, ttl: u64) -> Claims {
+        let now = chrono::Utc::now().timestamp() as u64;
+        Claims {
+            acr: None,
+            address: None,
+            allowed_origins: None,
+            aud: AUDIENCE.to_string(),
+            azp: None,
+            default_webdavfs: user.default_webdavfs.clone(),
+            email_verified: None,
+            exp: now + ttl,
+            family_name: None,
+            given_name: user.name.clone(),
+            groups: Some(user.groups.clone()),
+            iat: now,
+            iss: ISSUER.to_string(),
+            jti: Some(nanoid::nanoid!()),
+            preferred_username: Some(username.to_string()),
+            resource_access: None,
+            roles: Some(user.roles.clone()),
+            scope: Some("openid".to_string()),
+            session_state: None,
+            sid: Some(sid.to_string()),
+            sub: username.to_string(),
+            typ: Some(typ.to_string()),
+        }
+    }
+
+    // Tokens of a new session, or of the session `sid` on refresh
+    fn issue(&self, username: &str, user: &LocalUser, sid: Option<&str>) -> Result<TokenResponse, (StatusCode, String)> {
+        let sid = sid.map(|s| s.to_string()).unwrap_or_else(|| nanoid::nanoid!());
+        let key = EncodingKey::from_secret(&self.secret);
+        let sign = |claims: &Claims| encode(&Header::new(Algorithm::HS256), claims, &key)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to issue token: {}", e)));
+        Ok(TokenResponse {
+            access_token: sign(&self.claims(username, user, &sid, "Bearer", ACCESS_TOKEN_SECS))?,
+            expires_in: ACCESS_TOKEN_SECS as u32,
+            refresh_expires_in: REFRESH_TOKEN_SECS as u32,
+            refresh_token: Some(sign(&self.claims(username, user, &sid, "Refresh", REFRESH_TOKEN_SECS))?),
+            token_type: "Bearer".to_string(),
+            not_before_policy: 0,
+            session_state: None,
+            scope: Some("openid".to_string()),
+            id_token: None,
+        })
+    }
+
+    // Claims of a token we issued, of type `typ`, for a user still in the file
+    fn decode(&self, token: &str, typ: &str) -> Option<(Claims, &LocalUser)> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.set_issuer(&[ISSUER]);
+        validation.set_audience(&[AUDIENCE]);
+        let claims = decode::<Claims>(token, &DecodingKey::from_secret(&self.secret), &validation)
+            .map_err(|e| tracing::debug!("Local token rejected: {}", e))
+            .ok()?
+            .claims;
+        if claims.typ.as_deref() != Some(typ) {
+            return None;
+        }
+        let user = self.users.get(&claims.sub)?;
+        Some((claims, user))
+    }
+}
+
+#[async_trait]
+impl AuthProvider for LocalProvider {
+    fn name(&self) -> &str {
+        "local"
+    }
+
+    async fn authenticate(&self, username: &str, password: &str) -> Result<TokenResponse, (StatusCode, String)> {
+        let invalid = || (StatusCode::UNAUTHORIZED, "Invalid user credentials".to_string());
+        let user = self.users.get(username).cloned().ok_or_else(invalid)?;
+        // Hashing is slow on purpose; keep it off the async workers
+        let password = password.to_string();
+        let checked = user.clone();
+        let valid = tokio::task::spawn_blocking(move || checked.verify_password(&password)).await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if !valid {
+            return Err(invalid());
+        }
+        self.issue(username, &user, None)
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<TokenResponse, (StatusCode, String)> {
+        let (claims, user) = self.decode(refresh_token, "Refresh")
+            .ok_or((StatusCode::UNAUTHORIZED, "Invalid refresh token".to_string()))?;
+        self.issue(&claims.sub, user, claims.sid.as_deref())
+    }
+
+    async fn verify(&self, token: &str) -> Result<Claims, (StatusCode, String)> {
+        self.decode(token, "Bearer")
+            .map(|(claims, _)| claims)
+            .ok_or((StatusCode::UNAUTHORIZED, "token inactive".to_string()))
+    }
+}
@@ -1,2 +1,10 @@
+pub mod acl;
+pub mod apikey;
+pub mod forward;
 pub mod keycloak;
-pub mod handler;
\ No newline at end of file
+pub mod keyring;
+pub mod handler;
+pub mod local;
+pub mod oidc;
+pub mod provider;
+pub mod ratelimit;
\ No newline at end of file
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::RwLock;
+use crate::models::auth::{Claims, KeycloakError, TokenResponse, JWKS};
+use super::provider::AuthProvider;
+
+const JWKS_TTL: Duration = Duration::from_secs(14400); // 4 hours
+// Unknown key ids fetch the JWKS again at most this often
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+// Algorithms a JWK with an RSA modulus and exponent can verify
+const RSA_ALGORITHMS: &[Algorithm] = &[Algorithm::RS256, Algorithm::RS384, Algorithm::RS512, Algorithm::PS256, Algorithm::PS384, Algorithm::PS512];
+
+// Checks on access tokens besides signature, issuer and audience
+#[derive(Debug, Clone)]
+pub struct JwtPolicy {
+    // Accepted "alg" headers
+    pub algorithms: Vec<Algorithm>,
+    // Clock skew allowed for exp and nbf, in seconds
+    pub leeway: u64,
+    // Accepted "azp" (authorized party); when set, tokens without one are refused
+    pub clients: Vec<String>,
+}
+
+impl JwtPolicy {
+    // JWT_ALGORITHMS (default "RS256"), JWT_LEEWAY_SECS (default 30) and
+    // JWT_CLIENTS (default: our own client id), comma separated
+    pub fn from_env(client_id: &str) -> JwtPolicy {
+        let list = |name: &str, default: &str| -> Vec<String> {
+            std::env::var(name).unwrap_or(default.to_string())
+                .split(',')
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .collect()
+        };
+        let algorithms = list("JWT_ALGORITHMS", "RS256").iter()
+            .filter_map(|name| match name.parse::<Algorithm>() {
+                Ok(alg) if RSA_ALGORITHMS.contains(&alg) => Some(alg),
+                _ => {
+                    tracing::warn!("Ignoring JWT algorithm {}: only RSA signatures are supported", name);
+                    None
+                }
+            })
+            .collect();
+        JwtPolicy {
+            algorithms,
+            leeway: std::env::var("JWT_LEEWAY_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30),
+            clients: list("JWT_CLIENTS", client_id),
+        }
+    }
+}
+
+// The parts of an OpenID provider's metadata we use
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcEndpoints {
+    pub issuer: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+    // RFC 7009 token revocation, if the provider has it
+    #[serde(default)]
+    pub revocation_endpoint: Option<String>,
+}
+
+struct CachedJWKS {
+    jwks: JWKS,
+    fetched_at: Instant,
+}
+
+// Any OpenID Connect provider supporting the password and refresh_token
+// grants, with RSA signed access tokens
+pub struct OidcProvider {
+    name: String,
+    discovery_url: Option<String>,
+    endpoints: RwLock<Option<OidcEndpoints>>,
+    client_id: String,
+    client_secret: String,
+    // Expected "aud" of access tokens
+    audience: String,
+    policy: JwtPolicy,
+    http_client: Client,
+    jwks: RwLock<Option<CachedJWKS>>,
+}
+
+impl OidcProvider {
+    pub fn new(name: &str, endpoints: OidcEndpoints, client_id: &str, client_secret: &str, audience: &str, policy: JwtPolicy, http_client: Client) -> OidcProvider {
+        OidcProvider {
+            name: name.to_string(),
+            discovery_url: None,
+            endpoints: RwLock::new(Some(endpoints)),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            audience: audience.to_string(),
+            policy,
+            http_client,
+            jwks: RwLock::new(None),
+        }
+    }
+
+    // Endpoints come from {issuer}/.well-known/openid-configuration, fetched on
+    // first use so the server starts while the provider is unreachable
+    pub fn discover(issuer: &str, client_id: &str, client_secret: &str, audience: &str, policy: JwtPolicy, http_client: Client) -> OidcProvider {
+        OidcProvider {
+            name: "oidc".to_string(),
+            discovery_url: Some(format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'))),
+            endpoints: RwLock::new(None),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            audience: audience.to_string(),
+            policy,
+            http_client,
+            jwks: RwLock::new(None),
+        }
+    }
+
+    async fn endpoints(&self) -> Result<OidcEndpoints, (StatusCode, String)> {
+        if let Some(endpoints) = &*self.endpoints.read().await {
+            return Ok(endpoints.clone());
+        }
+        let url = self.discovery_url.as_deref().unwrap_or_default();
+        let endpoints: OidcEndpoints = self.http_client.get(url).send().await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| {
+                tracing::error!("OIDC discovery failed for {}: {}", url, e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "OIDC discovery failed".to_string())
+            })?
+            .json().await
+            .map_err(|e| {
+                tracing::error!("Invalid OIDC configuration from {}: {}", url, e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Invalid OIDC configuration".to_string())
+            })?;
+        tracing::info!("OIDC issuer {}, token endpoint {}", endpoints.issuer, endpoints.token_endpoint);
+        *self.endpoints.write().await = Some(endpoints.clone());
+        Ok(endpoints)
+    }
+
+    // The cached JWKS, fetched again when stale, or on `refresh` unless it
+    // was fetched very recently
+    async fn jwks(&self, jwks_uri: &str, refresh: bool) -> Result<JWKS, (StatusCode, String)> {
+        let fresh = |cached: &CachedJWKS| {
+            let age = cached.fetched_at.elapsed();
+            age < JWKS_MIN_REFRESH || (!refresh && age < JWKS_TTL)
+        };
+        if let Some(cached) = self.jwks.read().await.as_ref().filter(|c| fresh(c)) {
+            return Ok(cached.jwks.clone());
+        }
+        // Concurrent requests with an unknown kid wait for one fetch
+        let mut cache = self.jwks.write().await;
+        if let Some(cached) = cache.as_ref().filter(|c| fresh(c)) {
+            return Ok(cached.jwks.clone());
+        }
+        let failed = |e: reqwest::Error| {
+            tracing::error!("Failed to fetch JWKS from {}: {}", jwks_uri, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "token verification failed".to_string())
+        };
+        let jwks: JWKS = self.http_client.get(jwks_uri).send().await.map_err(failed)?
+            .json().await.map_err(failed)?;
+        tracing::info!("Fetched {} signing keys from {}", jwks.keys.len(), jwks_uri);
+        *cache = Some(CachedJWKS { jwks: jwks.clone(), fetched_at: Instant::now() });
+        Ok(jwks)
+    }
+
+    // POST a form with our client credentials to one of the provider's endpoints
+    pub async fn post_form(&self, url: &str, mut params: HashMap<&str, String>) -> Result<reqwest::Response, (StatusCode, String)> {
+        params.insert("client_id", self.client_id.clone());
+        if !self.client_secret.is_empty() {
+            params.insert("client_secret", self.client_secret.clone());
+        }
+        self.http_client
+            .post(url)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| {
+                tracing::debug!("Error sending request to {}: {}", url, e);
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to send authentication request: {}", e))
+            })
+    }
+
+    async fn token_request(&self, grant: HashMap<&str, String>) -> Result<TokenResponse, (StatusCode, String)> {
+        let endpoints = self.endpoints().await?;
+        let response = self.post_form(&endpoints.token_endpoint, grant).await?;
+        if response.status().is_success() {
+            return response.json().await.map_err(|e| {
+                tracing::debug!("Error parsing token response: {}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to parse token response: {}", e))
+            });
+        }
+        let body = response.text().await.unwrap_or_else(|_| "Failed to read response body".to_string());
+        tracing::debug!("Token request rejected: {}", body);
+        let error_msg = match serde_json::from_str::<KeycloakError>(&body) {
+            Ok(err) => err.error_description.unwrap_or(err.error),
+            Err(_) => body,
+        };
+        Err((StatusCode::UNAUTHORIZED, error_msg))
+    }
+}
+
+#[async_trait]
+impl AuthProvider for OidcProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn authenticate(&self, username: &str, password: &str) -> Result<TokenResponse, (StatusCode, String)> {
+        let mut params = HashMap::new();
+        params.insert("grant_type", "password".to_string());
+        params.insert("username", username.to_string());
+        params.insert("password", password.to_string());
+        params.insert("scope", "openid".to_string());
+        self.token_request(params).await
+    }
+
+    async fn refresh(&self, refresh_token: &str) -> Result<TokenResponse, (StatusCode, String)> {
+        let mut params = HashMap::new();
+        params.insert("grant_type", "refresh_token".to_string());
+        params.insert("refresh_token", refresh_token.to_string());
+        self.token_request(params).await
+            .map_err(|(status, msg)| if status == StatusCode::UNAUTHORIZED { (status, "Invalid refresh token".to_string()) } else { (status, msg) })
+    }
+
+    async fn verify(&self, token: &str) -> Result<Claims, (StatusCode, String)> {
+        let inactive = || (StatusCode::UNAUTHORIZED, "token inactive".to_string());
+        let endpoints = self.endpoints().await?;
+        let header = decode_header(token).map_err(|_| inactive())?;
+        if !self.policy.algorithms.contains(&header.alg) {
+            tracing::debug!("Token algorithm {:?} not allowed", header.alg);
+            return Err(inactive());
+        }
+        let kid = header.kid.ok_or_else(inactive)?;
+        tracing::debug!("Verify JWT Token Kid: {}", kid);
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.policy.leeway;
+        validation.validate_nbf = true;
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+        validation.set_issuer(&[&endpoints.issuer]);
+        validation.set_audience(&[&self.audience]);
+
+        // A signature or key we don't know may mean the provider rotated its
+        // keys: fetch them again once
+        for refresh in [false, true] {
+            let jwks = self.jwks(&endpoints.jwks_uri, refresh).await?;
+            let Some(key) = jwks.keys.iter().find(|k| k.kid == kid) else { continue };
+            let decoding_key = DecodingKey::from_rsa_components(&key.n, &key.e)
+                .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "token verification failed".to_string()))?;
+            match decode::<Claims>(token, &decoding_key, &validation) {
+                Ok(data) => {
+                    let clients = &self.policy.clients;
+                    if !clients.is_empty() && data.claims.azp.as_ref().is_none_or(|azp| !clients.contains(azp)) {
+                        tracing::debug!("Token issued to client {:?} not allowed", data.claims.azp);
+                        return Err(inactive());
+                    }
+                    return Ok(data.claims);
+                }
+                Err(e) if matches!(e.kind(), jsonwebtoken::errors::ErrorKind::InvalidSignature) => {
+                    tracing::debug!("Token verification error: {}", e);
+                }
+                Err(e) => {
+                    tracing::debug!("Token verification error: {}", e);
+                    return Err(inactive());
+                }
+            }
+        }
+        Err(inactive())
+    }
+
+    async fn logout(&self, refresh_token: Option<&str>) -> Result<(), (StatusCode, String)> {
+        let endpoints = self.endpoints().await?;
+        let (Some(url), Some(refresh_token)) = (&endpoints.revocation_endpoint, refresh_token) else {
+            return Ok(());
+        };
+        let mut params = HashMap::new();
+        params.insert("token", refresh_token.to_string());
+        params.insert("token_type_hint", "refresh_token".to_string());
+        let response = self.post_form(url, params).await?;
+        if !response.status().is_success() {
+            return Err((StatusCode::BAD_GATEWAY, format!("Token revocation failed: {}", response.status())));
+        }
+        Ok(())
+    }
+}
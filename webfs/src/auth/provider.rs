@@ -0,0 +1,59 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use axum::http::StatusCode;
+use reqwest::Client;
+use crate::models::auth::{Claims, TokenResponse};
+use super::{keycloak::KeycloakProvider, local::LocalProvider, oidc::{JwtPolicy, OidcProvider}};
+
+// An identity backend: password login, token refresh and bearer token
+// validation. Errors carry the status to answer with and a message.
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &str;
+    async fn authenticate(&self, username: &str, password: &str) -> Result<TokenResponse, (StatusCode, String)>;
+    async fn refresh(&self, refresh_token: &str) -> Result<TokenResponse, (StatusCode, String)>;
+    // The claims of a valid, unexpired access token
+    async fn verify(&self, token: &str) -> Result<Claims, (StatusCode, String)>;
+    // End the session at the provider. Backends without one rely on the
+    // sid/jti revocation list alone.
+    async fn logout(&self, _refresh_token: Option<&str>) -> Result<(), (StatusCode, String)> {
+        Ok(())
+    }
+}
+
+// The provider picked by AUTH_PROVIDER: "keycloak" (default), "oidc" or "local"
+//   keycloak: KEYCLOAK_URL, REALM, CLIENT_ID, CLIENT_SECRET
+//   oidc:     OIDC_ISSUER, CLIENT_ID, CLIENT_SECRET, OIDC_AUDIENCE (default CLIENT_ID)
+//   local:    AUTH_USERS_FILE, AUTH_SECRET (optional; tokens do not survive a restart without it)
+// keycloak and oidc also read the JwtPolicy settings, JWT_ALGORITHMS...
+pub fn from_env(http_client: &Client) -> anyhow::Result<Arc<dyn AuthProvider>> {
+    let var = |name: &str| std::env::var(name).map_err(|e| {
+        tracing::error!("{} not set: {}", name, e);
+        anyhow::anyhow!("{} not set", name)
+    });
+    let provider = std::env::var("AUTH_PROVIDER").unwrap_or("keycloak".to_string());
+    let provider: Arc<dyn AuthProvider> = match provider.as_str() {
+        "keycloak" => {
+            let mut url = var("KEYCLOAK_URL")?;
+            if !url.starts_with("http") {
+                url = format!("https://{}", url);
+            }
+            Arc::new(KeycloakProvider::new(&url, &var("REALM")?, &var("CLIENT_ID")?, &var("CLIENT_SECRET")?, http_client.clone()))
+        }
+        "oidc" => {
+            let client_id = var("CLIENT_ID")?;
+            Arc::new(OidcProvider::discover(
+                &var("OIDC_ISSUER")?,
+                &client_id,
+                &std::env::var("CLIENT_SECRET").unwrap_or_default(),
+                &std::env::var("OIDC_AUDIENCE").unwrap_or(client_id.clone()),
+                JwtPolicy::from_env(&client_id),
+                http_client.clone(),
+            ))
+        }
+        "local" => Arc::new(LocalProvider::from_file(&var("AUTH_USERS_FILE")?, std::env::var("AUTH_SECRET").ok())?),
+        other => anyhow::bail!("Unknown AUTH_PROVIDER '{}'", other),
+    };
+    tracing::info!("Auth provider: {}", provider.name());
+    Ok(provider)
+}
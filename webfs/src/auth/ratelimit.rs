@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{ConnectInfo, Request, State},
+    http::{header::{HeaderMap, RETRY_AFTER}, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Json, Response},
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use crate::models::auth::AuthRequest;
+use super::forward::ForwardedRequest;
+use crate::models::ratelimit::{RateLimitConfig, RouteLimit};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Past this many tracked addresses and usernames, idle ones are dropped
+const MAX_ENTRIES: usize = 10_000;
+const MAX_LOGIN_BODY: usize = 64 * 1024;
+
+#[derive(Default)]
+struct Attempts {
+    window_start: Option<Instant>,
+    requests: u32,
+    failures: u32,
+    last_failure: Option<Instant>,
+    locked_until: Option<Instant>,
+}
+
+// Totals since start, per route
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RouteCounters {
+    pub requests: u64,
+    pub throttled: u64,
+    pub failures: u64,
+    pub lockouts: u64,
+}
+
+#[derive(Default)]
+struct Limiter {
+    // "{route}|ip|{addr}" and "{route}|user|{name}"
+    entries: HashMap<String, Attempts>,
+    counters: HashMap<String, RouteCounters>,
+}
+
+lazy_static! {
+    static ref LIMITER: Mutex<Limiter> = Mutex::new(Limiter::default());
+}
+
+impl Limiter {
+    // Seconds to wait when `keys` are locked out or the address is over its rate
+    fn check(&mut self, route: &str, limit: &RouteLimit, keys: &[String], ip_key: Option<&String>) -> Result<(), u64> {
+        let now = Instant::now();
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.retain(|_, e| {
+                e.locked_until.is_some_and(|t| t > now)
+                    || e.last_failure.is_some_and(|t| now - t < Duration::from_secs(limit.window_secs))
+                    || e.window_start.is_some_and(|t| now - t < RATE_WINDOW)
+            });
+        }
+        let counters = self.counters.entry(route.to_string()).or_default();
+        counters.requests += 1;
+        for key in keys {
+            if let Some(until) = self.entries.get(key).and_then(|e| e.locked_until).filter(|t| *t > now) {
+                counters.throttled += 1;
+                return Err((until - now).as_secs() + 1);
+            }
+        }
+        if let (Some(key), true) = (ip_key, limit.requests_per_minute > 0) {
+            let entry = self.entries.entry(key.clone()).or_default();
+            let start = *entry.window_start.get_or_insert(now);
+            if now - start >= RATE_WINDOW {
+                entry.window_start = Some(now);
+                entry.requests = 0;
+            }
+            entry.requests += 1;
+            if entry.requests > limit.requests_per_minute {
+                counters.throttled += 1;
+                let elapsed = now - entry.window_start.unwrap_or(now);
+                return Err(RATE_WINDOW.saturating_sub(elapsed).as_secs() + 1);
+            }
+        }
+        Ok(())
+    }
+
+    // Each failure past `max_failures` doubles the lockout
+    fn failed(&mut self, route: &str, limit: &RouteLimit, keys: &[String]) {
+        let now = Instant::now();
+        let counters = self.counters.entry(route.to_string()).or_default();
+        counters.failures += 1;
+        for key in keys {
+            let entry = self.entries.entry(key.clone()).or_default();
+            if entry.last_failure.is_some_and(|t| now - t > Duration::from_secs(limit.window_secs)) {
+                entry.failures = 0;
+            }
+            entry.failures += 1;
+            entry.last_failure = Some(now);
+            if limit.max_failures > 0 && entry.failures >= limit.max_failures {
+                let doublings = (entry.failures - limit.max_failures).min(32);
+                let secs = limit.lockout_secs.saturating_mul(1 << doublings).min(limit.max_lockout_secs);
+                entry.locked_until = Some(now + Duration::from_secs(secs));
+                counters.lockouts += 1;
+                tracing::warn!("Locked out {} for {}s after {} failures", key, secs, entry.failures);
+            }
+        }
+    }
+
+    // A good password clears its username's failures, not the address's:
+    // one valid account must not reset guessing at others
+    fn succeeded(&mut self, user_key: Option<&String>) {
+        if let Some(entry) = user_key.and_then(|key| self.entries.get_mut(key)) {
+            entry.failures = 0;
+            entry.locked_until = None;
+        }
+    }
+}
+
+// The address of the client, looking through trusted proxies. Behind the
+// unix socket there is no peer address and the peer is the local proxy.
+pub fn client_ip(config: &RateLimitConfig, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+    if peer.is_some_and(|p| !config.is_trusted(&p)) {
+        return peer;
+    }
+    let forwarded: Vec<IpAddr> = headers.get_all("x-forwarded-for").iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect();
+    // Proxies append, so the last address not of a trusted proxy is the client
+    if let Some(ip) = forwarded.iter().rev().find(|ip| !config.is_trusted(ip)) {
+        return Some(*ip);
+    }
+    headers.get("x-real-ip")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.trim().parse().ok())
+        .or(forwarded.first().copied())
+        .or(peer)
+}
+
+// Which limits a request falls under, and the username it tries
+async fn classify(request: Request) -> Result<(Request, Option<&'static str>, Option<String>), Response> {
+    if request.method() == Method::POST && request.uri().path() == "/auth/v1/login" {
+        let (parts, body) = request.into_parts();
+        let bytes = to_bytes(body, MAX_LOGIN_BODY).await
+            .map_err(|_| StatusCode::PAYLOAD_TOO_LARGE.into_response())?;
+        let username = serde_json::from_slice::<serde_json::Value>(&bytes).ok()
+            .and_then(|v| v.get("username").and_then(|u| u.as_str()).map(|u| u.to_string()));
+        return Ok((Request::from_parts(parts, Body::from(bytes)), Some("login"), username));
+    }
+    // Forward auth asks about the request the proxy is holding
+    let uri = match request.uri().path() {
+        "/auth/v1/nginx" | "/auth/v1/forward" => ForwardedRequest::from_headers(request.headers()).uri,
+        _ => request.uri().to_string(),
+    };
+    let auth = AuthRequest::new(request.uri(), request.method().as_str(), request.headers());
+    if auth.username.is_some() {
+        return Ok((request, Some("basic"), auth.username));
+    }
+    if auth.jwt_token.is_none() && uri.contains("key_id=") {
+        return Ok((request, Some("signurl"), None));
+    }
+    Ok((request, None, None))
+}
+
+fn too_many(retry_after: u64) -> Response {
+    let mut response = (StatusCode::TOO_MANY_REQUESTS, Json(serde_json::json!({"error": "Too many attempts", "retry_after": retry_after}))).into_response();
+    response.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    response
+}
+
+// Middleware throttling credential checks by client address and username.
+// Answers 401 count as failures, 2xx as successes.
+pub async fn limit(State(state): State<crate::AppState>, mut request: Request, next: Next) -> Response {
+    let config = &state.config.rate_limit;
+    let peer = request.extensions().get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip());
+    let ip = client_ip(config, peer, request.headers());
+    // Later checks (signed URL address binding) see the resolved address only
+    request.headers_mut().remove("x-real-ip");
+    if let Some(ip) = ip {
+        request.headers_mut().insert("x-real-ip", HeaderValue::from_str(&ip.to_string()).unwrap());
+    }
+    let (request, route, username) = match classify(request).await {
+        Ok(classified) => classified,
+        Err(response) => return response,
+    };
+    let Some(route) = route else {
+        return next.run(request).await;
+    };
+    let limit = config.route(route);
+    let ip_key = ip.map(|ip| format!("{}|ip|{}", route, ip));
+    let user_key = username.map(|name| format!("{}|user|{}", route, name.to_lowercase()));
+    let keys: Vec<String> = ip_key.iter().chain(user_key.iter()).cloned().collect();
+    if let Err(retry_after) = LIMITER.lock().unwrap().check(route, &limit, &keys, ip_key.as_ref()) {
+        tracing::info!("Throttled {} request from {:?}", route, ip);
+        return too_many(retry_after);
+    }
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        LIMITER.lock().unwrap().failed(route, &limit, &keys);
+    } else if response.status().is_success() {
+        LIMITER.lock().unwrap().succeeded(user_key.as_ref());
+    }
+    response
+}
+
+// GET /auth/v1/limits: counters for monitoring, without addresses or names
+pub async fn limits_handler() -> Json<serde_json::Value> {
+    let limiter = LIMITER.lock().unwrap();
+    let now = Instant::now();
+    let locked = limiter.entries.values().filter(|e| e.locked_until.is_some_and(|t| t > now)).count();
+    Json(serde_json::json!({
+        "routes": limiter.counters,
+        "locked": locked,
+        "tracked": limiter.entries.len(),
+    }))
+}
@@ -1,10 +1,7 @@
-use anyhow::Context;
 use std::result::Result;
 use chrono::{Utc};
 use clap::{Arg, Command};
-use quick_xml::Writer;
-use std::fs::File;
-use std::io::BufWriter;
+use webfs::models::feed::FeedFormat;
 use webfs::models::files::{Config, Channel};
 
 fn default_filter_extension() -> String {
@@ -36,6 +33,10 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             .long("days")
             .value_name("DAYS")
             .help("Number of days of history to include in the RSS feed"))
+        .arg(Arg::new("format")
+            .long("format")
+            .value_name("FORMATS")
+            .help("Comma separated feed formats to write (rss, atom, json); defaults to the channel's configured feeds"))
         .arg(Arg::new("log_file")
             .long("log-file")
             .value_name("LOG_FILE")
@@ -50,6 +51,10 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let start_date = matches.get_one::<String>("days")
         .and_then(|s| s.parse::<i32>().ok())
         .map(|days| Utc::now().date_naive() - chrono::Duration::days(days.abs() as i64));
+    let formats: Option<Vec<FeedFormat>> = match matches.get_one::<String>("format") {
+        Some(list) => Some(list.split(',').map(FeedFormat::parse).collect::<anyhow::Result<Vec<_>>>()?),
+        None => None,
+    };
 
     let config: Config = Channel::read_config(&config_path)?;
 
@@ -79,10 +84,8 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     tracing::info!("Processing {} channels", channels_to_process.len());
     for (channel_name, ch) in channels_to_process {
 
-        let output_path = &ch.output_path;
-
         tracing::info!("---------------------------------------------------------");
-        tracing::info!("Refreshing RSS Channel {} {}", channel_name, output_path);
+        tracing::info!("Refreshing RSS Channel {} {}", channel_name, ch.output_path);
 
         // Read and filter files from the directory
         let entries = Channel::read_dir(&ch)?;
@@ -91,24 +94,33 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             continue;
         }
 
-        // Create output file and XML writer
-        let file = File::create(output_path).context("Failed to create output file")?;
-        let buf_writer = BufWriter::new(file);
-        let mut writer = Writer::new(buf_writer);
-
         // Process entries
         let mut ch = ch.clone();
         ch.set_entries(entries);
 
-        // Write RSS
-        ch.write_rss(&mut writer, start_date)?;
+        // Write each requested feed format to its configured output path
+        let outputs: Vec<(FeedFormat, String)> = match &formats {
+            Some(formats) => {
+                let configured = ch.feed_outputs();
+                formats.iter().map(|format| {
+                    let path = configured.iter().find(|(f, _)| f == format).map(|(_, p)| p.clone())
+                        .unwrap_or_else(|| std::path::Path::new(&ch.output_path).with_extension(format.extension()).to_string_lossy().to_string());
+                    (*format, path)
+                }).collect()
+            }
+            None => ch.feed_outputs(),
+        };
+        for (format, output_path) in &outputs {
+            let count = format.write_tofile(&mut ch, start_date, output_path)?;
+            tracing::info!("{} feed written to {} with {} entries", format.extension(), output_path, count);
+        }
 
         // Print first ten file names of sorted entries in channel
         for (i, entry) in ch.entries.iter().take(10).enumerate() {
             tracing::info!("{}: {} {}", i + 1, entry.file_name, entry.location);
         }
 
-        tracing::info!("RSS feed written to {} with {} entries", output_path, ch.entries.len());
+        tracing::info!("Channel {} has {} entries", channel_name, ch.entries.len());
     }
 
     Ok(())
@@ -1,8 +1,70 @@
+use clap::{Arg, Command};
 use webfs::models::auth::{SigningKeys, SignUrlRequest, SignUrlResponse};
 
+// Same lifetimes as the server's keyring
+const KEY_EXPIRES_SECS: u64 = 3600 * 24 * 30;
+const SIG_EXPIRES_SECS: u64 = 3600;
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut signing_keys = SigningKeys::new(3600, 3600);
+    dotenv::dotenv().ok();
+    let matches = Command::new("utils")
+        .subcommand(Command::new("signurl")
+            .about("Sign a URL, verify it and a tampered copy"))
+        .subcommand(Command::new("keys")
+            .about("Manage the signed URL keyring (SIGNING_KEYRING, SIGNING_MASTER_KEY)")
+            .subcommand_required(true)
+            .subcommand(Command::new("list").about("List key ids and their state"))
+            .subcommand(Command::new("rotate").about("Start signing with a new key"))
+            .subcommand(Command::new("revoke")
+                .about("Stop accepting URLs signed with a key")
+                .arg(Arg::new("key_id").value_name("KEY_ID").required(true))))
+        .get_matches();
+
+    match matches.subcommand() {
+        Some(("keys", keys)) => {
+            let mut signing_keys = SigningKeys::from_env(KEY_EXPIRES_SECS, SIG_EXPIRES_SECS);
+            if signing_keys.store.is_none() {
+                return Err("SIGNING_MASTER_KEY is not set".into());
+            }
+            signing_keys.reload()?;
+            match keys.subcommand() {
+                Some(("rotate", _)) => {
+                    let key = signing_keys.rotate()?;
+                    println!("Signing with {} until {}", key.key_id, key.expires_at);
+                }
+                Some(("revoke", args)) => {
+                    let key_id = args.get_one::<String>("key_id").ok_or("key_id argument missing")?;
+                    signing_keys.revoke(key_id)?;
+                    println!("Revoked {}", key_id);
+                }
+                _ => list_keys(&signing_keys),
+            }
+        }
+        _ => signurl_demo(SigningKeys::from_env(KEY_EXPIRES_SECS, SIG_EXPIRES_SECS))?,
+    }
+    Ok(())
+}
+
+fn list_keys(signing_keys: &SigningKeys) {
+    let mut keys: Vec<_> = signing_keys.keys.values().collect();
+    keys.sort_by_key(|k| k.created_at);
+    let current = signing_keys.cur_key.as_ref().map(|k| k.key_id.as_str());
+    for key in keys {
+        let state = if key.revoked {
+            "revoked"
+        } else if current == Some(key.key_id.as_str()) {
+            "signing"
+        } else if key.is_retired(signing_keys.grace_secs) {
+            "expired"
+        } else {
+            "verifying"
+        };
+        println!("{}\t{}\tcreated {}\texpires {}", key.key_id, state, key.created_at, key.expires_at);
+    }
+}
+
+fn signurl_demo(mut signing_keys: SigningKeys) -> Result<(), Box<dyn std::error::Error>> {
     let req = SignUrlRequest::new("GET","https://example.com/files/report.pdf?user=alice");
 
     // Generate
@@ -11,13 +73,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Verify (should succeed)
     let verified_resp = SignUrlResponse::from_url("GET", &signed_resp.url)?;
-    let verified = signing_keys.verify_signed_url(&verified_resp).await?;
-    println!("Verified: {}", verified);
+    let verified = signing_keys.verify_signed_url(&verified_resp)?;
+    println!("Verified: {} for {:?}", verified.path, verified.sub);
 
     // Tamper with it (should fail)
     let tampered_url = signed_resp.url.replace("alice", "bob");
     let tampered_resp = SignUrlResponse::from_url("GET", &tampered_url)?;
-    match signing_keys.verify_signed_url(&tampered_resp).await {
+    match signing_keys.verify_signed_url(&tampered_resp) {
         Ok(_) => println!("❌ Verification failed!"),
         Err(e) => println!("✅ Tampered URL rejected: {}", e),
     }
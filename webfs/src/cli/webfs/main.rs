@@ -1,8 +1,13 @@
-use webfs::auth::handler::{authenticate_handler, refresh_handler, signurl_handler, nginx_handler};
+use webfs::auth::handler::{authenticate_handler, refresh_handler, logout_handler, signurl_handler};
+use webfs::auth::forward::{forward_handler, nginx_handler};
+use webfs::auth::apikey::{create_feed_handler, create_key_handler, list_keys_handler, revoke_key_handler};
+use webfs::auth::ratelimit::{self, limits_handler};
+use webfs::audit::{self, audit_csv_handler, audit_handler};
 use webfs::models::auth::SigningKeys;
 
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{delete, get, post},
     Router,
 };
 use tokio::net::{TcpListener, UnixListener};
@@ -13,9 +18,12 @@ use tower_http::cors::CorsLayer;
 use webfs::models::files::Channel;
 use webfs::storage::Storage;
 use webfs::webfs::handler::*;
+use webfs::webfs::feeds::{feed_handler, private_feed_handler};
+use webfs::webfs::search::search_handler;
 use moka::future::Cache;
 use std::time::Duration;
 use std::env;
+use std::net::SocketAddr;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -51,17 +59,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     };
 
-    let keycloak_url = {
-        let mut url = std::env::var("KEYCLOAK_URL").map_err(|e| {
-            tracing::error!("KEYCLOAK_URL not set: {}", e);
-            e
-        })?;
-        if !url.starts_with("http") {
-            url = format!("https://{}", url);
-        }
-        url
-    };
-
     let db_path = std::env::var("DB_PATH").unwrap_or("/srv/data/webfs/files.db".to_string());
 
     tracing::info!("Creating Database path: {}", db_path);
@@ -74,22 +71,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     };
 
+    let rss_days = std::env::var("RSS_DAYS").unwrap_or("-1".to_string()).parse::<i32>().ok();
+
+    let http_client = Client::new();
+    let auth = webfs::auth::provider::from_env(&http_client).map_err(|e| {
+        tracing::error!("Failed to set up auth provider: {}", e);
+        e
+    })?;
+
     let state = AppState {
-        keycloak_url,
-        realm: std::env::var("REALM").map_err(|e| {
-            tracing::error!("REALM not set: {}", e);
-            e
-        })?,
-        client_id: std::env::var("CLIENT_ID").map_err(|e| {
-            tracing::error!("CLIENT_ID not set: {}", e);
-            e
-        })?,
-        client_secret: std::env::var("CLIENT_SECRET").map_err(|e| {
-            tracing::error!("CLIENT_SECRET not set: {}", e);
-            e
-        })?,
+        auth,
         base_path: std::env::var("BASE_PATH").unwrap_or("/srv/media".to_string()),
-        http_client: Client::new(),
+        rss_days: rss_days.unwrap_or(7),
+        http_client,
         config: config.clone(),
         channel_cache: std::sync::Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
         storage: std::sync::Arc::new(std::sync::Mutex::new(storage)),
@@ -110,7 +104,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let watch_path = std::env::var("WATCH_PATH").unwrap_or("".to_string());
     let rss_outpath = std::env::var("RSS_OUT_PATH").unwrap_or("/srv/aux/rss".to_string());
     let file_pattern = std::env::var("FILE_PATTERN").unwrap_or(r"zsv[\d]{6}.*\.docx".to_string());
-    let rss_days = std::env::var("RSS_DAYS").unwrap_or("-1".to_string()).parse::<i32>().ok();
 
     let monitor_config = webfs::webfs::file_monitor::MonitorConfig {
         config: config.clone(),
@@ -119,9 +112,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         rss_days: rss_days.unwrap_or(7),
         rss_output_path: rss_outpath.clone(),
         video_list_path: watch_path.clone(),
+        debounce_ms: std::env::var("WATCH_DEBOUNCE_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(2000),
+        rescan_secs: std::env::var("RESCAN_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(3600),
     };
     tracing::info!("Starting rss outpath for path: {}", rss_outpath);
     tracing::info!("Starting file monitor for path: {} and file pattern: {}", watch_path, file_pattern);
+    audit::start(state.clone());
     let state_clone = state.clone();
     tokio::spawn(async move {
         if let Err(e) = webfs::webfs::file_monitor::start_file_monitor(&monitor_config, state_clone.storage, state_clone.channel_cache).await {
@@ -132,10 +128,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let app = Router::new()
         .route("/auth/v1/login", post(authenticate_handler))
         .route("/auth/v1/refresh", post(refresh_handler))
+        .route("/auth/v1/logout", post(logout_handler))
         .route("/auth/v1/signurl", post(signurl_handler))
         .route("/fs/v1/", get(list_files_root_handler))
         .route("/auth/v1/nginx", get(nginx_handler))
+        .route("/auth/v1/forward", get(forward_handler))
+        .route("/fs/v1/search", get(search_handler))
         .route("/fs/v1/{*path}", get(list_files_handler))
+        .route("/feeds/v1/{lang}/{feed}", get(feed_handler))
+        .route("/feeds/v1/private/{token}/{feed}", get(private_feed_handler))
+        .route("/auth/v1/limits", get(limits_handler))
+        .route("/auth/v1/audit", get(audit_handler))
+        .route("/auth/v1/audit.csv", get(audit_csv_handler))
+        .route("/auth/v1/keys", get(list_keys_handler).post(create_key_handler))
+        .route("/auth/v1/keys/{id}", delete(revoke_key_handler))
+        .route("/auth/v1/feeds/{lang}/{channel}", post(create_feed_handler))
+        .layer(middleware::from_fn_with_state(state.clone(), ratelimit::limit))
         .layer(CorsLayer::permissive())
         .with_state(state);
 
@@ -166,7 +174,7 @@ async fn serve_tcp(app: Router, port: String) -> Result<(), Box<dyn std::error::
         e
     })?;
     tokio::select! {
-        result = axum::serve(listener, app) => {
+        result = axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()) => {
             result.map_err(|e| {
                 tracing::error!("Failed to serve TCP: {}", e);
                 e
@@ -1,5 +1,7 @@
+pub mod audit;
 pub mod auth;
 pub mod models;
+pub mod search;
 pub mod storage;
 pub mod webfs;
 
@@ -16,11 +18,10 @@ use crate::models::auth::AuthResponse;
 
 #[derive(Clone)]
 pub struct AppState {
-    pub keycloak_url: String,
-    pub realm: String,
-    pub client_id: String,
-    pub client_secret: String,
+    // Keycloak, generic OIDC or the local users file
+    pub auth: Arc<dyn auth::provider::AuthProvider>,
     pub base_path: String,
+    pub rss_days: i32,
     pub http_client: Client,
     pub config: models::files::Config,
     pub channel_cache: Arc<Mutex<HashMap<String, (models::files::Channel, DateTime<Utc>)>>>,
@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use serde::Deserialize;
+use super::auth::Claims;
+use super::files::FolderShare;
+use super::scan::glob_to_regex;
+
+// Access control rules from the `acl` section of config.yaml:
+//
+//   acl:
+//     default: deny
+//     policies:
+//       members:
+//         groups: ["gjcc"]
+//         allow:
+//           channels: ["zh/*", "en/videos-all"]
+//       staff:
+//         roles: ["staff"]
+//         inherits: ["members"]
+//         allow:
+//           folders: ["*"]
+//         deny:
+//           paths: ["/fs/v1/private/**"]
+//
+// A user gets every policy naming one of their groups or roles, plus the
+// policies those inherit. Deny rules win over allow rules; a request no
+// rule matches gets `default`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AclConfig {
+    #[serde(default)]
+    pub default: AclEffect,
+    #[serde(default)]
+    pub policies: HashMap<String, AclPolicy>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AclEffect {
+    // Without an acl section every authenticated user reads everything, as before
+    #[default]
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AclPolicy {
+    // Keycloak groups ("gjcc" or "/gjcc") and realm or client roles
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // Policy names whose rules also apply
+    #[serde(default)]
+    pub inherits: Vec<String>,
+    #[serde(default)]
+    pub allow: AclRules,
+    #[serde(default)]
+    pub deny: AclRules,
+}
+
+// Glob patterns: channels as "lang/name", folder share ids, and URL paths
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AclRules {
+    #[serde(default)]
+    pub channels: Globs,
+    #[serde(default)]
+    pub folders: Globs,
+    #[serde(default)]
+    pub paths: Globs,
+}
+
+impl AclRules {
+    pub fn matches(&self, target: &AclTarget) -> bool {
+        let channel = target.channel.as_deref().is_some_and(|c| self.channels.matches(c));
+        let folder = target.folder.as_deref().is_some_and(|f| self.folders.matches(f));
+        channel || folder || self.paths.matches(&target.path)
+    }
+}
+
+// Globs as in scan excludes, matched from the start of the value; "a/**"
+// also covers "a" itself. Config rules are compiled when the config is read.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "Vec<String>")]
+pub struct Globs(Vec<(String, Regex)>);
+
+impl Globs {
+    pub fn new(patterns: &[String]) -> Result<Globs, String> {
+        patterns.iter().map(|pattern| {
+            let pattern = pattern.trim_start_matches('/');
+            let re = Regex::new(&glob_to_regex(pattern)).map_err(|e| format!("Invalid acl pattern '{}': {}", pattern, e))?;
+            Ok((pattern.to_string(), re))
+        }).collect::<Result<_, String>>().map(Globs)
+    }
+
+    pub fn matches(&self, value: &str) -> bool {
+        let value = value.trim_start_matches('/');
+        self.0.iter().any(|(pattern, re)| {
+            re.is_match(value) || pattern.strip_suffix("/**").is_some_and(|dir| value.trim_end_matches('/') == dir)
+        })
+    }
+
+    // Whether a pattern may match `prefix` or a path below it, judging by
+    // the pattern's text up to its first wildcard
+    pub fn reaches_below(&self, prefix: &str) -> bool {
+        let prefix = prefix.trim_matches('/');
+        self.0.iter().any(|(pattern, _)| {
+            let literal = &pattern[..pattern.find(['*', '?', '[', '{']).unwrap_or(pattern.len())];
+            prefix.is_empty() || literal == prefix || literal.starts_with(&format!("{}/", prefix)) || prefix.starts_with(literal)
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for Globs {
+    type Error = String;
+
+    fn try_from(patterns: Vec<String>) -> Result<Globs, String> {
+        Globs::new(&patterns)
+    }
+}
+
+// What a request reads: always a URL path, and the channel or folder share
+// it falls under when known
+#[derive(Debug, Clone, Default)]
+pub struct AclTarget {
+    pub path: String,
+    // "lang/name"
+    pub channel: Option<String>,
+    pub folder: Option<String>,
+}
+
+// The path as the file server resolves it: percent-decoded, with repeated
+// slashes merged and "." and ".." resolved. None for an encoded slash or
+// backslash, which would split a segment the rules saw whole, or for ".."
+// above the root.
+pub fn normalize_path(path: &str) -> Option<String> {
+    let lower = path.to_ascii_lowercase();
+    if lower.contains("%2f") || lower.contains("%5c") {
+        return None;
+    }
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let trailing = decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing && !segments.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+impl AclTarget {
+    pub fn new(path: &str) -> AclTarget {
+        AclTarget { path: path.to_string(), ..Default::default() }
+    }
+}
+
+impl AclConfig {
+    // Names of the policies granted to `claims`, with inherited ones
+    pub fn policies_for(&self, claims: &Claims) -> HashSet<String> {
+        let groups: HashSet<&str> = claims.groups.iter().flatten().map(|g| g.trim_start_matches('/')).collect();
+        let mut roles: HashSet<&str> = claims.roles.iter().flatten().map(|r| r.as_str()).collect();
+        if let Some(access) = &claims.resource_access {
+            roles.extend(access.clients.values().flat_map(|c| c.roles.iter().map(|r| r.as_str())));
+        }
+        let mut pending: Vec<&String> = self.policies.iter()
+            .filter(|(_, p)| {
+                p.groups.iter().any(|g| groups.contains(g.trim_start_matches('/')))
+                    || p.roles.iter().any(|r| roles.contains(r.as_str()))
+            })
+            .map(|(name, _)| name)
+            .collect();
+        let mut granted = HashSet::new();
+        while let Some(name) = pending.pop() {
+            if !granted.insert(name.clone()) {
+                continue;
+            }
+            match self.policies.get(name) {
+                Some(policy) => pending.extend(policy.inherits.iter()),
+                None => tracing::warn!("Acl policy '{}' inherits unknown policy", name),
+            }
+        }
+        granted
+    }
+
+    // Whether a deny rule of `claims` may match a URL path at or below `prefix`
+    pub fn denies_below(&self, claims: &Claims, prefix: &str) -> bool {
+        self.policies_for(claims).iter()
+            .filter_map(|name| self.policies.get(name))
+            .any(|p| p.deny.paths.reaches_below(prefix))
+    }
+
+    // Whether `claims` may read `target`. Without a matching rule, a folder
+    // share with a `group` is only readable by members of that group.
+    pub fn allows(&self, claims: &Claims, folder: Option<&FolderShare>, target: &AclTarget) -> bool {
+        let policies: Vec<&AclPolicy> = self.policies_for(claims).iter()
+            .filter_map(|name| self.policies.get(name))
+            .collect();
+        if policies.iter().any(|p| p.deny.matches(target)) {
+            return false;
+        }
+        if policies.iter().any(|p| p.allow.matches(target)) {
+            return true;
+        }
+        if let Some(folder) = folder.filter(|f| !f.group.is_empty()) {
+            let group = folder.group.trim_start_matches('/');
+            return claims.groups.iter().flatten().any(|g| g.trim_start_matches('/') == group);
+        }
+        self.default == AclEffect::Allow
+    }
+}
@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use super::acl::{AclRules, AclTarget, Globs};
+use super::auth::Claims;
+
+// Tokens look like "wfs_{id}_{secret}"; only a hash of the secret is kept
+pub const API_KEY_PREFIX: &str = "wfs_";
+
+// What a key may read, within what its owner may read. Empty channels and
+// folders mean no narrowing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiKeyScope {
+    // Globs as in acl rules: "lang/name" and folder share ids
+    #[serde(default)]
+    pub channels: Vec<String>,
+    #[serde(default)]
+    pub folders: Vec<String>,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+impl ApiKeyScope {
+    // Whether the key reads less than its owner
+    pub fn narrows(&self) -> bool {
+        !self.channels.is_empty() || !self.folders.is_empty()
+    }
+
+    pub fn covers(&self, target: &AclTarget) -> bool {
+        if !self.narrows() {
+            return true;
+        }
+        match (Globs::new(&self.channels), Globs::new(&self.folders)) {
+            (Ok(channels), Ok(folders)) => AclRules { channels, folders, paths: Globs::default() }.matches(target),
+            (Err(e), _) | (_, Err(e)) => {
+                tracing::error!("Api key scope: {}", e);
+                false
+            }
+        }
+    }
+}
+
+// A user's API key as stored; the identity is the owner's when the key was made
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    // sha256 of the secret, hex
+    pub hash: String,
+    pub owner: String,
+    pub username: String,
+    pub groups: Vec<String>,
+    pub roles: Vec<String>,
+    pub default_webdavfs: Option<String>,
+    pub scope: ApiKeyScope,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    // "lang/name" for a private feed key, which reads that feed and nothing else
+    #[serde(default)]
+    pub feed: Option<String>,
+    // When the owner last signed in, and the groups and roles were refreshed
+    pub owner_seen: DateTime<Utc>,
+}
+
+impl ApiKey {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|exp| Utc::now() > exp)
+    }
+
+    // Claims standing in for the owner's token
+    pub fn claims(&self) -> Claims {
+        Claims {
+            acr: None,
+            address: None,
+            allowed_origins: None,
+            aud: String::new(),
+            azp: None,
+            default_webdavfs: self.default_webdavfs.clone(),
+            email_verified: None,
+            exp: self.expires_at.map(|exp| exp.timestamp() as u64).unwrap_or(u64::MAX),
+            family_name: None,
+            given_name: None,
+            groups: Some(self.groups.clone()),
+            iat: self.created_at.timestamp() as u64,
+            iss: "webfs-apikey".to_string(),
+            jti: Some(self.id.clone()),
+            preferred_username: Some(self.username.clone()),
+            resource_access: None,
+            roles: Some(self.roles.clone()),
+            scope: None,
+            session_state: None,
+            sid: None,
+            sub: self.owner.clone(),
+            typ: Some("ApiKey".to_string()),
+        }
+    }
+}
+
+// The parts of a key shown to its owner
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyInfo {
+    pub id: String,
+    pub name: String,
+    pub scope: ApiKeyScope,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed: Option<String>,
+}
+
+impl From<&ApiKey> for ApiKeyInfo {
+    fn from(key: &ApiKey) -> Self {
+        ApiKeyInfo {
+            id: key.id.clone(),
+            name: key.name.clone(),
+            scope: key.scope.clone(),
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used: key.last_used,
+            feed: key.feed.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    #[serde(default)]
+    pub scope: ApiKeyScope,
+    // Days until the key stops working, 1 to 365. Default 365.
+    #[serde(default)]
+    pub expires_in_days: Option<u32>,
+}
+
+// The token is only ever shown here
+#[derive(Debug, Serialize)]
+pub struct CreateApiKeyResponse {
+    pub key: ApiKeyInfo,
+    pub token: String,
+}
+
+// A private feed: the key behind it and where podcast apps find it
+#[derive(Debug, Serialize)]
+pub struct PrivateFeedResponse {
+    pub key: ApiKeyInfo,
+    pub rss: String,
+    pub atom: String,
+    pub json: String,
+}
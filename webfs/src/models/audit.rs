@@ -0,0 +1,220 @@
+use axum::http::header::{HeaderMap, USER_AGENT};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use super::auth::{AuthRequest, Claims};
+
+// Audit logging from the `audit` section of config.yaml:
+//
+//   audit:
+//     retention_days: 90
+//     admin_groups: ["webfs-admins"]
+//     admin_roles: ["admin"]
+//
+// Events older than `retention_days` are purged; 0 keeps them forever.
+// Members of the admin groups or holders of the admin roles may query them.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditConfig {
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+    #[serde(default)]
+    pub admin_groups: Vec<String>,
+    #[serde(default = "default_admin_roles")]
+    pub admin_roles: Vec<String>,
+}
+
+fn default_retention_days() -> u32 {
+    90
+}
+
+fn default_admin_roles() -> Vec<String> {
+    vec!["admin".to_string()]
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            retention_days: default_retention_days(),
+            admin_groups: Vec::new(),
+            admin_roles: default_admin_roles(),
+        }
+    }
+}
+
+impl AuditConfig {
+    pub fn is_admin(&self, claims: &Claims) -> bool {
+        let in_group = claims.groups.iter().flatten()
+            .any(|g| self.admin_groups.iter().any(|a| a.trim_start_matches('/') == g.trim_start_matches('/')));
+        let mut roles: Vec<&String> = claims.roles.iter().flatten().collect();
+        if let Some(access) = &claims.resource_access {
+            roles.extend(access.clients.values().flat_map(|c| c.roles.iter()));
+        }
+        in_group || roles.iter().any(|r| self.admin_roles.contains(r))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditKind {
+    Login,
+    LoginFailed,
+    AuthFailed,
+    Listing,
+    Download,
+    SignUrl,
+}
+
+impl AuditKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditKind::Login => "login",
+            AuditKind::LoginFailed => "login_failed",
+            AuditKind::AuthFailed => "auth_failed",
+            AuditKind::Listing => "listing",
+            AuditKind::Download => "download",
+            AuditKind::SignUrl => "sign_url",
+        }
+    }
+}
+
+// One audited request. Stored with bincode, so fields are never skipped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub time: DateTime<Utc>,
+    pub kind: AuditKind,
+    pub sub: Option<String>,
+    pub username: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub path: String,
+    pub status: u16,
+    // Downloads: bytes sent, and whether the client took all it asked for
+    pub bytes: Option<u64>,
+    pub completed: Option<bool>,
+    pub detail: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(kind: AuditKind, path: &str, status: u16) -> AuditEvent {
+        AuditEvent {
+            time: Utc::now(),
+            kind,
+            sub: None,
+            username: None,
+            ip: None,
+            user_agent: None,
+            path: path.to_string(),
+            status,
+            bytes: None,
+            completed: None,
+            detail: None,
+        }
+    }
+
+    // The client address as resolved by the rate limiter, and its user agent
+    pub fn client(mut self, headers: &HeaderMap) -> AuditEvent {
+        let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok()).map(|h| h.to_string());
+        self.ip = header("x-real-ip");
+        self.user_agent = header(USER_AGENT.as_str());
+        self
+    }
+
+    pub fn from_request(kind: AuditKind, request: &AuthRequest, status: u16) -> AuditEvent {
+        let path = request.url.as_deref().unwrap_or_default();
+        let mut event = AuditEvent::new(kind, path.split('?').next().unwrap_or_default(), status);
+        event.ip = request.client_ip.clone();
+        event.user_agent = request.user_agent.clone();
+        event.username = request.username.clone();
+        event
+    }
+
+    pub fn identity(mut self, claims: &Claims) -> AuditEvent {
+        self.sub = Some(claims.sub.clone());
+        self.username = claims.preferred_username.clone();
+        self
+    }
+
+    pub fn status(mut self, status: axum::http::StatusCode) -> AuditEvent {
+        self.status = status.as_u16();
+        self
+    }
+
+    pub fn detail(mut self, detail: &str) -> AuditEvent {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    pub const CSV_HEADER: &'static str = "time,kind,sub,username,ip,user_agent,path,status,bytes,completed,detail";
+
+    pub fn csv_row(&self) -> String {
+        let opt = |v: &Option<String>| csv_field(v.as_deref().unwrap_or_default());
+        [
+            self.time.to_rfc3339(),
+            self.kind.as_str().to_string(),
+            opt(&self.sub),
+            opt(&self.username),
+            opt(&self.ip),
+            opt(&self.user_agent),
+            csv_field(&self.path),
+            self.status.to_string(),
+            self.bytes.map(|b| b.to_string()).unwrap_or_default(),
+            self.completed.map(|c| c.to_string()).unwrap_or_default(),
+            opt(&self.detail),
+        ].join(",")
+    }
+}
+
+// Quoted when it holds a separator, quote or line break. A leading = + - @
+// is prefixed with ' so spreadsheets don't run it as a formula.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+// From and to, either open
+pub type TimeRange = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+// Filters of /auth/v1/audit and /auth/v1/audit.csv. Times are RFC 3339 or
+// plain dates; `from` is inclusive, `to` is not, but a `to` date includes
+// the whole day.
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    // sub or username
+    pub user: Option<String>,
+    // Path prefix
+    pub path: Option<String>,
+    pub kind: Option<AuditKind>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    pub fn range(&self) -> Result<TimeRange, String> {
+        let parse = |value: &Option<String>, end_of_day: bool| -> Result<Option<DateTime<Utc>>, String> {
+            let Some(value) = value.as_deref() else { return Ok(None) };
+            if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+                return Ok(Some(time.with_timezone(&Utc)));
+            }
+            let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| format!("Invalid time '{}'", value))?;
+            let date = if end_of_day { date.succ_opt().unwrap_or(date) } else { date };
+            Ok(Some(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()))
+        };
+        Ok((parse(&self.from, false)?, parse(&self.to, true)?))
+    }
+
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        if let Some(user) = self.user.as_deref() {
+            if event.sub.as_deref() != Some(user) && event.username.as_deref() != Some(user) {
+                return false;
+            }
+        }
+        if self.path.as_deref().is_some_and(|path| !event.path.starts_with(path)) {
+            return false;
+        }
+        self.kind.is_none_or(|kind| kind == event.kind)
+    }
+}
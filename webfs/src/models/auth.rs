@@ -2,6 +2,8 @@ use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde_json;
 use axum::{http::{Uri, header::HeaderMap}};
+use super::acl::normalize_path;
+use super::apikey::{ApiKeyScope, API_KEY_PREFIX};
 use super::files::FolderShare;
 use hmac::{Hmac, Mac};
 use nanoid::nanoid;
@@ -9,12 +11,13 @@ use sha2::Sha256;
 use base64;
 use url::Url;
 use std::time::{SystemTime, UNIX_EPOCH};
+use crate::auth::keyring::KeyringFile;
 use chrono::{DateTime, Local, Utc};
 use rand::TryRngCore;
 use rand::rngs::OsRng;
-use moka::future::Cache;
 use std::time::{Duration, Instant};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use base64::{Engine, engine::general_purpose};
 type HmacSha256 = Hmac<Sha256>;
 
@@ -22,10 +25,11 @@ type HmacSha256 = Hmac<Sha256>;
 pub struct TokenResponse {
     pub access_token: String,
     pub expires_in: u32,
+    #[serde(default)]
     pub refresh_expires_in: u32,
     pub refresh_token: Option<String>,
     pub token_type: String,
-    #[serde(rename = "not-before-policy")]
+    #[serde(rename = "not-before-policy", default)]
     pub not_before_policy: u32,
     pub session_state: Option<String>,
     pub scope: Option<String>,
@@ -39,6 +43,14 @@ pub struct AuthRequest {
     pub jwt_token: Option<String>,
     pub method: Option<String>,
     pub url: Option<String>,
+    // From X-Real-IP or X-Forwarded-For, as set by nginx
+    #[serde(default)]
+    pub client_ip: Option<String>,
+    // A personal API key, as a bearer token or the `api_key` query parameter
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
 }
 
 impl AuthRequest {
@@ -49,6 +61,9 @@ impl AuthRequest {
             jwt_token: None,
             method: Some(method.to_string()),
             url: Some(uri.to_string()),
+            client_ip: None,
+            api_key: None,
+            user_agent: headers.get("user-agent").and_then(|h| h.to_str().ok()).map(|h| h.to_string()),
         };
 
         let headers_map: std::collections::HashMap<String, String> = headers.iter().filter_map(|(k, v)| {
@@ -57,9 +72,25 @@ impl AuthRequest {
         let headers_json = serde_json::to_string(&headers_map).unwrap_or_default();
         tracing::debug!("Headers JSON: {}", headers_json);
 
+        auth.client_ip = headers.get("x-real-ip")
+            .or(headers.get("x-forwarded-for"))
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty());
         let auth_header = headers.get("authorization").and_then(|h| h.to_str().ok());
         tracing::debug!("Authorization: {} / {}", &auth_header.unwrap_or(""), &method);
         auth.jwt_token = auth_header.clone().and_then(|h| h.strip_prefix("Bearer ").map(|s| s.to_string()));
+        if auth.jwt_token.as_ref().is_some_and(|t| t.starts_with(API_KEY_PREFIX)) {
+            auth.api_key = auth.jwt_token.take();
+        }
+        if auth.api_key.is_none() {
+            auth.api_key = uri.query().and_then(|query| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .find(|(k, _)| k == "api_key")
+                    .map(|(_, v)| v.into_owned())
+            });
+        }
         if auth.jwt_token.is_none() {
             let cookie_header = headers.get("cookie").and_then(|h| h.to_str().ok());
             if let Some(cookie_str) = cookie_header {
@@ -79,10 +110,21 @@ impl AuthRequest {
             if let Some((user, pass)) = decoded.split_once(':') {
                 auth.username = Some(user.to_string());
                 auth.password = Some(pass.to_string());
+                // Podcast apps only know usernames and passwords
+                if pass.starts_with(API_KEY_PREFIX) && auth.api_key.is_none() {
+                    auth.api_key = Some(pass.to_string());
+                }
             }
         }
         auth
     }
+
+    // Whether the request tried to authenticate at all
+    pub fn has_credentials(&self) -> bool {
+        self.jwt_token.is_some() || self.api_key.is_some() || self.username.is_some()
+            || self.url.as_ref().is_some_and(|url| url.contains("key_id="))
+    }
+
     pub fn basic_auth(&self) -> Option<BasicAuthRequest> {
         if let (Some(username), Some(password)) = (&self.username, &self.password) {
             Some(BasicAuthRequest { username: username.clone(), password: password.clone(), use_cache: true })
@@ -105,18 +147,31 @@ pub struct RefreshRequest {
     pub refresh_token: String,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LogoutRequest {
+    // Also ends the provider session when given
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct AuthInfo {
     pub claims: Claims,
     pub folder: Option<FolderShare>,
+    // Set when the request came with a signed URL instead of a login
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grant: Option<SignedGrant>,
+    // Set when the request came with an API key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<ApiKeyScope>,
 }
 
 impl  AuthInfo{
     pub fn new(claims: Claims, folder: Option<FolderShare>) -> Self {
-        AuthInfo { claims, folder }
+        AuthInfo { claims, folder, grant: None, scope: None }
     }
     pub fn FromAuth(response: AuthResponse) -> Self {
-        AuthInfo { claims: response.claims, folder: response.folder }
+        AuthInfo { claims: response.claims, folder: response.folder, grant: None, scope: None }
     }
 }
 
@@ -151,11 +206,15 @@ pub struct JWKS {
 #[derive(Debug, Deserialize, Clone)]
 pub struct JWK {
     pub kid: String,
+    // RSA modulus and exponent; empty for other key types
+    #[serde(default)]
     pub n: String,
+    #[serde(default)]
     pub e: String,
-    #[serde(rename = "use")]
+    #[serde(rename = "use", default)]
     pub use_: String,
     pub kty: String,
+    #[serde(default)]
     pub alg: String,
 }
 
@@ -186,6 +245,7 @@ pub struct Claims {
     pub address: Option<Address>,
     #[serde(rename = "allowed-origins")]
     pub allowed_origins: Option<Vec<String>>,
+    #[serde(deserialize_with = "string_or_first")]
     pub aud: String,
     pub azp: Option<String>,
     pub default_webdavfs: Option<String>,
@@ -213,6 +273,71 @@ pub struct Claims {
     pub typ: Option<String>,
 }
 
+impl Claims {
+    // No user: what public routes are checked as
+    pub fn anonymous() -> Claims {
+        Claims {
+            acr: None,
+            address: None,
+            allowed_origins: None,
+            aud: String::new(),
+            azp: None,
+            default_webdavfs: None,
+            email_verified: None,
+            exp: 0,
+            family_name: None,
+            given_name: None,
+            groups: None,
+            iat: 0,
+            iss: String::new(),
+            jti: None,
+            preferred_username: None,
+            resource_access: None,
+            roles: None,
+            scope: None,
+            session_state: None,
+            sid: None,
+            sub: String::new(),
+            typ: None,
+        }
+    }
+}
+
+// "aud" is a string or, with several audiences, an array
+fn string_or_first<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(aud) => aud,
+        OneOrMany::Many(auds) => auds.into_iter().next().unwrap_or_default(),
+    })
+}
+
+// What a signed URL may be used for besides its own path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignScope {
+    // Only the signed path
+    #[default]
+    File,
+    // The signed path and anything below it; the signed query is appended
+    // to the paths under it as is
+    Prefix,
+}
+
+impl SignScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignScope::File => "file",
+            SignScope::Prefix => "prefix",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SignUrlRequest {
     #[serde(default)]
@@ -222,6 +347,29 @@ pub struct SignUrlRequest {
     pub fs_id: String,
     #[serde(default)]
     pub method: String,
+    // Whose access the URL carries; set by the server from the signer's token
+    #[serde(default, skip_deserializing)]
+    pub sub: String,
+    // The signer's session, so logging out also ends the URL
+    #[serde(default, skip_deserializing)]
+    pub sid: Option<String>,
+    #[serde(default)]
+    pub scope: SignScope,
+    // GET and HEAD only, whatever `method` is
+    #[serde(default)]
+    pub read_only: bool,
+    // Optional limits: authorized requests (range requests count too),
+    // the only client address allowed, and a start time
+    #[serde(default)]
+    pub max_uses: Option<u64>,
+    #[serde(default)]
+    pub ip: Option<String>,
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    // Seconds the URL is good for, up to the keyring's grace period; the
+    // key's own lifetime when unset
+    #[serde(default)]
+    pub expires_in: Option<u64>,
 }
 
 impl SignUrlRequest {
@@ -231,6 +379,47 @@ impl SignUrlRequest {
             url: url.to_string(),
             fs_id: String::new(),
             method: method.to_string(),
+            sub: String::new(),
+            sid: None,
+            scope: SignScope::File,
+            read_only: false,
+            max_uses: None,
+            ip: None,
+            not_before: None,
+            expires_in: None,
+        }
+    }
+}
+
+// What a verified signed URL grants, read back from its signed query
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SignedGrant {
+    pub id: String,
+    pub sub: String,
+    pub sid: Option<String>,
+    pub fs_id: String,
+    pub scope: SignScope,
+    // The signed file, or the prefix
+    pub path: String,
+    pub read_only: bool,
+    pub max_uses: Option<u64>,
+    pub ip: Option<String>,
+    pub not_before: Option<u64>,
+    pub expires: u64,
+}
+
+impl SignedGrant {
+    // Whether the URL path `path` is within the grant, both as the file
+    // server resolves them
+    pub fn covers(&self, path: &str) -> bool {
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        let (Some(path), Some(granted)) = (normalize_path(path), normalize_path(&self.path)) else { return false };
+        match self.scope {
+            SignScope::File => path == granted,
+            SignScope::Prefix => {
+                let prefix = granted.trim_end_matches('/');
+                path == prefix || path.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+            }
         }
     }
 }
@@ -239,7 +428,6 @@ impl SignUrlRequest {
 pub struct SignUrlResponse {
     pub id: String,
     pub url: String,
-    pub tid: String,
     pub method: String,
     pub key_id: String,
     pub signature: String,
@@ -251,7 +439,6 @@ impl SignUrlResponse {
         SignUrlResponse{
             id: req.id.clone(),
             url: req.url.clone(),
-            tid: String::new(),
             method: req.method.clone(),
             key_id: String::new(),
             signature: String::new(),
@@ -264,7 +451,6 @@ impl SignUrlResponse {
         let mut resp = SignUrlResponse{
             id: String::new(),
             url: url.to_string(),
-            tid: String::new(),
             method: method.to_string(),
             key_id: String::new(),
             signature: String::new(),
@@ -280,8 +466,6 @@ impl SignUrlResponse {
                 resp.expires_at = DateTime::<Utc>::from_timestamp(expires, 0).ok_or(anyhow!("Invalid timestamp"))?;
             } else if key == "id" {
                 resp.id = value;
-            } else if key == "tid" {
-                resp.tid = value;
             } else if key == "key_id" {
                 resp.key_id = value;
             }
@@ -290,14 +474,26 @@ impl SignUrlResponse {
     }
 }
 
+// Unknown key ids and revocations from other instances are picked up from
+// the keyring file at most this long after they happen
+const KEYRING_RELOAD: Duration = Duration::from_secs(10);
+// The longest lifetime a signed URL may ask for
+pub const MAX_SIGNED_URL_SECS: u64 = 3600 * 24 * 7;
+
 #[derive(Debug)]
 pub struct SigningKeys {
-    pub keys: Cache<String, HmacSigningKey>,
+    pub keys: HashMap<String, HmacSigningKey>,
     pub cur_key: Option<HmacSigningKey>,
     pub last_create: DateTime<Local>,
     pub domain: String,
     pub key_expires_in_secs: u64,
     pub sig_expires_in_secs: u64,
+    // How long a key still verifies after it stops signing
+    pub grace_secs: u64,
+    // Without a keyring file keys live in memory and die with the process
+    pub store: Option<KeyringFile>,
+    loaded: Option<SystemTime>,
+    checked: Instant,
 }
 
 impl SigningKeys {
@@ -311,37 +507,155 @@ impl SigningKeys {
             key_expires_in_secs = 60;
         }
         SigningKeys{
-            keys: Cache::builder().max_capacity(10_000)
-            .time_to_live(Duration::from_secs(900))  // 15 minutes
-            .build(),
+            keys: HashMap::new(),
             cur_key: None,
             domain: String::new(),
             last_create: Local::now().checked_sub_days(chrono::Days::new(365)).unwrap(),
             key_expires_in_secs: key_expires_in_secs,
+            // A URL signed just before its key rotates lives out its full lifetime
+            grace_secs: sig_expires_in_secs.max(MAX_SIGNED_URL_SECS),
             sig_expires_in_secs: sig_expires_in_secs,
+            store: None,
+            loaded: None,
+            checked: Instant::now(),
+        }
+    }
+
+    // Keys rotate every `key_expires_in_secs`; the keyring file and
+    // SIGNING_KEY_GRACE_SECS come from the environment
+    pub fn from_env(key_expires_in_secs: u64, sig_expires_in_secs: u64) -> SigningKeys {
+        let mut keys = SigningKeys::new(key_expires_in_secs, sig_expires_in_secs);
+        if let Some(grace) = std::env::var("SIGNING_KEY_GRACE_SECS").ok().and_then(|v| v.parse().ok()) {
+            keys.grace_secs = grace;
+        }
+        match KeyringFile::from_env() {
+            Some(store) => {
+                tracing::info!("Signing keyring: {}", store.path().display());
+                keys.store = Some(store);
+                if let Err(e) = keys.reload() {
+                    tracing::error!("Failed to load signing keyring: {}", e);
+                }
+            }
+            None => tracing::warn!("SIGNING_MASTER_KEY not set; signed URLs are invalidated on restart"),
+        }
+        keys
+    }
+
+    // Whether the keyring file should be read again before verifying with `key_id`
+    pub fn stale(&self, key_id: &str) -> bool {
+        self.store.is_some() && (!self.keys.contains_key(key_id) || self.checked.elapsed() > KEYRING_RELOAD)
+    }
+
+    // Read the keyring file again if it changed since it was loaded
+    pub fn reload(&mut self) -> Result<()> {
+        self.checked = Instant::now();
+        let Some(store) = &self.store else { return Ok(()) };
+        let modified = store.modified();
+        if modified.is_some() && modified == self.loaded {
+            return Ok(());
         }
+        let keys = store.load()?;
+        self.set_keys(keys);
+        self.loaded = modified;
+        Ok(())
+    }
+
+    fn set_keys(&mut self, keys: Vec<HmacSigningKey>) {
+        self.keys = keys.into_iter().map(|k| (k.key_id.clone(), k)).collect();
+        // The newest key signs; older ones only verify
+        self.cur_key = self.keys.values()
+            .filter(|k| k.can_sign())
+            .max_by_key(|k| k.created_at)
+            .cloned();
     }
-    fn create_new_key(&mut self) {
+
+    fn new_key(&self) -> HmacSigningKey {
         let mut key = HmacSigningKey::new(self.sig_expires_in_secs);
         key.set_domain(self.domain.clone());
         key.set_expires_at(Local::now().checked_add_signed(chrono::Duration::seconds(self.key_expires_in_secs as i64)).unwrap());
-        self.keys.insert(key.key_id.clone(), key.clone());
-        self.cur_key = Some(key);
+        key
+    }
+
+    // Add a key and make it current. Unless `force`, another instance's key
+    // created in the meantime is used instead.
+    fn create_new_key(&mut self, force: bool) -> Result<()> {
+        let key = self.new_key();
+        self.last_create = Local::now();
+        let Some(store) = &self.store else {
+            self.keys.insert(key.key_id.clone(), key.clone());
+            self.cur_key = Some(key);
+            return Ok(());
+        };
+        let grace = self.grace_secs;
+        let keys = store.update(|keys| {
+            keys.retain(|k| !k.is_retired(grace));
+            if force || !keys.iter().any(|k| k.can_sign()) {
+                keys.push(key);
+            }
+            Ok(())
+        })?;
+        self.loaded = store.modified();
+        self.set_keys(keys);
+        Ok(())
+    }
+
+    fn current(&mut self) -> Result<HmacSigningKey> {
+        if !self.cur_key.as_ref().is_some_and(|k| k.can_sign()) {
+            self.reload()?;
+        }
+        if !self.cur_key.as_ref().is_some_and(|k| k.can_sign()) {
+            self.create_new_key(false)?;
+        }
+        self.cur_key.clone().ok_or(anyhow!("No signing key"))
+    }
+
+    // Start signing with a new key; the old ones keep verifying through the grace period
+    pub fn rotate(&mut self) -> Result<HmacSigningKey> {
+        self.reload()?;
+        self.create_new_key(true)?;
+        self.cur_key.clone().ok_or(anyhow!("No signing key"))
     }
-    fn current(&mut self) -> HmacSigningKey {
-        if self.cur_key.is_none() || self.cur_key.as_ref().unwrap().is_expired() {
-            self.create_new_key();
+
+    // URLs signed with `key_id` stop verifying, on every instance sharing the keyring
+    pub fn revoke(&mut self, key_id: &str) -> Result<()> {
+        match &self.store {
+            Some(store) => {
+                let keys = store.update(|keys| {
+                    let key = keys.iter_mut().find(|k| k.key_id == key_id).ok_or(anyhow!("Key {} not found", key_id))?;
+                    key.revoked = true;
+                    Ok(())
+                })?;
+                self.loaded = store.modified();
+                self.set_keys(keys);
+            }
+            None => {
+                let key = self.keys.get_mut(key_id).ok_or(anyhow!("Key {} not found", key_id))?;
+                key.revoked = true;
+                let keys = self.keys.values().cloned().collect();
+                self.set_keys(keys);
+            }
         }
-        self.cur_key.as_ref().unwrap().clone()
+        Ok(())
     }
+
     pub fn generate_signed_url(&mut self, request: &SignUrlRequest) -> Result<SignUrlResponse> {
-        let key = self.current();
+        let grace = self.grace_secs;
+        let key = self.current()?;
+        if request.expires_in.is_some_and(|secs| secs > grace) {
+            let mut request = request.clone();
+            request.expires_in = Some(grace);
+            return key.generate_signed_url(&request);
+        }
         key.generate_signed_url(request)
     }
-    pub async fn verify_signed_url(&self, request: &SignUrlResponse) -> Result<url::Url> {
-        match self.keys.get(&request.key_id).await{
+
+    pub fn verify_signed_url(&self, request: &SignUrlResponse) -> Result<SignedGrant> {
+        match self.keys.get(&request.key_id) {
             Some(key) => {
-                if key.is_expired() {
+                if key.revoked {
+                    return Err(anyhow!("Key is revoked"));
+                }
+                if key.is_retired(self.grace_secs) {
                     return Err(anyhow!("Key is expired"));
                 }
                 key.verify_signed_url(request)
@@ -358,8 +672,12 @@ pub struct HmacSigningKey {
     pub key_id: String,
     pub secret: [u8; 32],
     pub domain: String,
+    // Signs new URLs until then
     pub expires_at: DateTime<Local>,
     pub expires_in_secs: u64,
+    pub created_at: DateTime<Local>,
+    #[serde(default)]
+    pub revoked: bool,
 }
 
 impl HmacSigningKey {
@@ -372,12 +690,21 @@ impl HmacSigningKey {
             domain: String::new(),
             expires_at: Local::now(),
             expires_in_secs: sig_exp_secs,
+            created_at: Local::now(),
+            revoked: false,
         }
     }
     pub fn is_expired(&self) -> bool {
         let now = Local::now();
         now > self.expires_at
     }
+    pub fn can_sign(&self) -> bool {
+        !self.revoked && !self.is_expired()
+    }
+    // Past expiry and the grace period: URLs it signed no longer verify
+    pub fn is_retired(&self, grace_secs: u64) -> bool {
+        Local::now() > self.expires_at + chrono::Duration::seconds(grace_secs as i64)
+    }
 }
 
 impl HmacSigningKey {
@@ -403,6 +730,9 @@ impl HmacSigningKey {
             let schema = if url.starts_with("localhost") { "http" } else { "https" }; // http or https schema
             if url.starts_with("//") {
                 url = format!("{}:{}", schema, url);
+            } else if url.starts_with('/') {
+                // A bare path, as nginx passes it; only path and query are signed
+                url = format!("http://localhost{}", url);
             } else {
                 url = format!("{}://{}", schema, url);
             }
@@ -433,27 +763,54 @@ impl HmacSigningKey {
         }
         let cleaned_url = HmacSigningKey::clean_url(&request.url);
         let url = Url::parse(&cleaned_url)?;
+        let mut seen = HashSet::new();
+        for (key, _) in url.query_pairs() {
+            if SIGNED_PARAMS.contains(&key.as_ref()) || !seen.insert(key.clone()) {
+                return Err(anyhow!("Parameter {} can't be signed", key));
+            }
+        }
         
         // Build canonical string: method + path + sorted query + expires
-        let method = request.method.to_uppercase();
+        let method = if request.read_only { "GET".to_string() } else { request.method.to_uppercase() };
         let path = url.path();
-        let expires = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + self.expires_in_secs;
+        let expires = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() + request.expires_in.unwrap_or(self.expires_in_secs);
         
-        // Add expires to query params
+        // Identity, scope and limits go in the query, so the signature covers
+        // them and verifying needs nothing but the key
         let mut url_with_expires = url.clone();
-        url_with_expires.query_pairs_mut()
-            .append_pair("expires", &expires.to_string())
-            .append_pair("id", &request.id)
-            .append_pair("key_id", &self.key_id)
-            .append_pair("tid", &self.key_id);
+        {
+            let mut query = url_with_expires.query_pairs_mut();
+            if !request.sub.is_empty() {
+                query.append_pair("sub", &request.sub);
+            }
+            if let Some(sid) = &request.sid {
+                query.append_pair("sid", sid);
+            }
+            if !request.fs_id.is_empty() {
+                query.append_pair("fs", &request.fs_id);
+            }
+            if request.scope == SignScope::Prefix {
+                query.append_pair("scope", request.scope.as_str()).append_pair("path", path);
+            }
+            if request.read_only {
+                query.append_pair("ro", "1");
+            }
+            if let Some(max_uses) = request.max_uses {
+                query.append_pair("uses", &max_uses.to_string());
+            }
+            if let Some(ip) = &request.ip {
+                query.append_pair("ip", ip);
+            }
+            if let Some(not_before) = request.not_before {
+                query.append_pair("nbf", &not_before.timestamp().to_string());
+            }
+            query.append_pair("expires", &expires.to_string())
+                .append_pair("id", &request.id)
+                .append_pair("key_id", &self.key_id);
+        }
         
         // Canonical string (deterministic order matters!)
-        let canonical_query = url_with_expires
-            .query_pairs()
-            .filter(|(k, _)| k != "signature")  // Exclude signature itself
-            .map(|(k, v)| format!("{}={}", k, v))
-            .collect::<Vec<_>>()
-            .join("&");
+        let canonical_query = canonical_query(url_with_expires.query_pairs());
         
         let full_query = if !canonical_query.is_empty() {
             format!("?{}", canonical_query)
@@ -483,72 +840,104 @@ impl HmacSigningKey {
         Ok(resp)
     }
 
-    pub fn verify_signed_url(&self, request: &SignUrlResponse) -> Result<url::Url> {
+    pub fn verify_signed_url(&self, request: &SignUrlResponse) -> Result<SignedGrant> {
         let engine = general_purpose::STANDARD;
         let cleaned_url = HmacSigningKey::clean_url(&request.url);
         let url = url::Url::parse(&cleaned_url)?;
         // Extract and validate signature
-        let pairs = url.query_pairs();
         let mut signature_b64: Option<String> = None;
         if request.signature != "" {
             signature_b64 = Some(request.signature.clone());
         }
         let mut expires: Option<u64> = None;
-        if request.expires_at != DateTime::<Utc>::from_timestamp(0, 0).unwrap() {
-            expires = Some(request.expires_at.timestamp() as u64);
-        }
-        let mut canonical_parts: Vec<String> = Vec::new();
+        let mut grant = SignedGrant::default();
+        let mut seen = HashSet::new();
+        let mut signed_pairs = Vec::new();
         
-        for (key, value) in pairs {
-            let key = key.into_owned();
-            let value = value.into_owned();
-            
-            if key == "signature" {
-                signature_b64 = Some(value);
-            } else if key == "expires" {
-                expires = Some(value.parse::<u64>()?);
-                canonical_parts.push(format!("{}={}", key, value));
-            } else {
-                canonical_parts.push(format!("{}={}", key, value));
+        for (key, value) in url.query_pairs() {
+            if !seen.insert(key.clone()) {
+                return Err(anyhow!("Duplicate parameter {}", key));
+            }
+            // Everything the server signed comes before the signature
+            if seen.contains("signature") && key != "signature" {
+                return Err(anyhow!("Unsigned parameter {}", key));
+            }
+            match key.as_ref() {
+                "signature" => {
+                    signature_b64 = Some(value.into_owned());
+                    continue;
+                }
+                "expires" => expires = Some(value.parse::<u64>()?),
+                "id" => grant.id = value.to_string(),
+                "sub" => grant.sub = value.to_string(),
+                "sid" => grant.sid = Some(value.to_string()),
+                "fs" => grant.fs_id = value.to_string(),
+                "scope" if value == "prefix" => grant.scope = SignScope::Prefix,
+                "scope" => return Err(anyhow!("Invalid scope")),
+                "path" => grant.path = value.to_string(),
+                "ro" => grant.read_only = value == "1",
+                "uses" => grant.max_uses = Some(value.parse::<u64>()?),
+                "ip" => grant.ip = Some(value.to_string()),
+                "nbf" => grant.not_before = Some(value.parse::<u64>()?),
+                _ => {}
             }
+            signed_pairs.push((key, value));
         }
         
         let signature_b64 = signature_b64.ok_or(anyhow!("Missing signature"))?;
         let signature = engine.decode(&signature_b64)?;
         
         // Check expiration
-        if let Some(exp) = expires {
-            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-            if now > exp {
-                return Err(anyhow!("URL expired"));
-            }
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let expires = expires.ok_or(anyhow!("Missing expiry"))?;
+        if now > expires {
+            return Err(anyhow!("URL expired"));
         }
+        grant.expires = expires;
         
-        // Rebuild exact same string_to_sign
-        let method = request.method.to_uppercase();
-        let path = url.path();
-        let query_string = canonical_parts.join("&");
+        // Rebuild exact same string_to_sign; a prefix URL was signed for the prefix
+        let method = if grant.read_only { "GET".to_string() } else { request.method.to_uppercase() };
+        if grant.scope == SignScope::File {
+            grant.path = url.path().to_string();
+        }
+        let query_string = canonical_query(signed_pairs.into_iter());
         let full_query = if !query_string.is_empty() {
             format!("?{}", query_string)
         } else {
             String::new()
         };
         
-        let string_to_sign = format!("{}{}{}", method, path, full_query);
+        let string_to_sign = format!("{}{}{}", method, grant.path, full_query);
         
         // Verify HMAC
         let mut mac = HmacSha256::new_from_slice(&self.secret)?;
         mac.update(string_to_sign.as_bytes());
-        let expected_signature = mac.finalize().into_bytes();
-        
-        if signature != expected_signature.as_slice() {
-            return Err(anyhow!("Invalid signature"));
+        mac.verify_slice(&signature).map_err(|_| anyhow!("Invalid signature"))?;
+        if grant.read_only && !matches!(request.method.to_uppercase().as_str(), "GET" | "HEAD") {
+            return Err(anyhow!("URL is read-only"));
+        }
+        if !grant.covers(url.path()) {
+            return Err(anyhow!("Path outside the signed scope"));
+        }
+        if grant.not_before.is_some_and(|nbf| now < nbf) {
+            return Err(anyhow!("URL not yet valid"));
         }
         
-        Ok(url)
+        Ok(grant)
     }
 }
 
+// Query parameters carrying a signed URL's grant
+const SIGNED_PARAMS: &[&str] = &["sub", "sid", "fs", "scope", "path", "ro", "uses", "ip", "nbf", "expires", "id", "key_id", "signature"];
+
+// The signed query: every parameter but the signature, form-urlencoded in
+// URL order, so no value can pass for parameters of its own
+fn canonical_query<'a>(pairs: impl Iterator<Item = (Cow<'a, str>, Cow<'a, str>)>) -> String {
+    url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(pairs.filter(|(k, _)| k != "signature"))
+        .finish()
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub enum AuthIdentity {
     Claims(Claims),
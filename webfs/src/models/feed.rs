@@ -0,0 +1,235 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
+use quick_xml::Writer;
+use chrono::{DateTime, NaiveDate, Utc};
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use super::files::{write_element, Channel, MediaEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+    Json,
+}
+
+impl FeedFormat {
+    pub const ALL: [FeedFormat; 3] = [FeedFormat::Rss, FeedFormat::Atom, FeedFormat::Json];
+
+    pub fn parse(s: &str) -> Result<FeedFormat> {
+        match s.trim().to_lowercase().as_str() {
+            "rss" | "xml" => Ok(FeedFormat::Rss),
+            "atom" => Ok(FeedFormat::Atom),
+            "json" | "jsonfeed" => Ok(FeedFormat::Json),
+            _ => Err(anyhow!("Unknown feed format '{}'", s)),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "rss",
+            FeedFormat::Atom => "atom",
+            FeedFormat::Json => "json",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FeedFormat::Rss => "application/rss+xml; charset=utf-8",
+            FeedFormat::Atom => "application/atom+xml; charset=utf-8",
+            FeedFormat::Json => "application/feed+json; charset=utf-8",
+        }
+    }
+
+    // Write the channel in this format, returning the number of items written
+    pub fn write<W: Write>(&self, channel: &mut Channel, out: W, start_date: Option<NaiveDate>) -> Result<usize> {
+        match self {
+            FeedFormat::Rss => {
+                let mut writer = Writer::new(out);
+                channel.write_rss(&mut writer, start_date)
+            }
+            FeedFormat::Atom => {
+                let mut writer = Writer::new(out);
+                write_atom(channel, &mut writer, start_date)
+            }
+            FeedFormat::Json => write_json_feed(channel, out, start_date),
+        }
+    }
+
+    // Write to a temp file next to `output` and rename it into place, so
+    // readers never see a half-written feed
+    pub fn write_tofile(&self, channel: &mut Channel, start_date: Option<NaiveDate>, output: &str) -> Result<usize> {
+        let tmp_path = format!("{}.tmp", output);
+        let file = File::create(&tmp_path).map_err(|e| {
+            tracing::error!("Failed to create output file '{}': {}", tmp_path, e);
+            e
+        }).context("Failed to create output file")?;
+        let mut buf_writer = BufWriter::new(file);
+        let count = self.write(channel, &mut buf_writer, start_date).map_err(|e| {
+            tracing::error!("Failed to write {} content to '{}': {}", self.extension(), output, e);
+            e
+        })?;
+        buf_writer.flush()?;
+        drop(buf_writer);
+        std::fs::rename(&tmp_path, output).with_context(|| format!("Failed to move feed into place at {}", output))?;
+        Ok(count)
+    }
+}
+
+fn rfc3339(dt: &chrono::NaiveDateTime) -> String {
+    DateTime::<Utc>::from_naive_utc_and_offset(*dt, Utc).to_rfc3339()
+}
+
+fn mime_type(entry: &MediaEntry) -> &'static str {
+    let ext = entry.file_name.rsplit('.').next().unwrap_or("").to_lowercase();
+    super::formatter::MIME_TYPE_MAP.get(ext.as_str()).copied().unwrap_or("application/octet-stream")
+}
+
+pub fn write_atom<W: Write>(channel: &Channel, writer: &mut Writer<W>, start_date: Option<NaiveDate>) -> Result<usize> {
+    let files = channel.feed_entries(start_date);
+    let updated = files.iter().map(|e| e.pub_date).max()
+        .map(|d| rfc3339(&d))
+        .unwrap_or_else(|| Utc::now().to_rfc3339());
+
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))?;
+    let mut feed = BytesStart::new("feed");
+    feed.push_attribute(("xmlns", "http://www.w3.org/2005/Atom"));
+    if !channel.language.is_empty() {
+        feed.push_attribute(("xml:lang", channel.language.as_str()));
+    }
+    writer.write_event(Event::Start(feed))?;
+
+    write_element(writer, "id", &format!("urn:uuid:{}", channel.podcast.podcast_guid(channel)))?;
+    write_element(writer, "title", &channel.title)?;
+    if !channel.description.is_empty() {
+        write_element(writer, "subtitle", &channel.description)?;
+    }
+    write_element(writer, "updated", &updated)?;
+    write_link(writer, "alternate", &channel.link, None, None)?;
+    writer.write_event(Event::Start(BytesStart::new("author")))?;
+    write_element(writer, "name", channel.podcast.author(channel))?;
+    writer.write_event(Event::End(BytesEnd::new("author")))?;
+    write_element(writer, "generator", &channel.generator)?;
+    let image_url = channel.image_url();
+    if !image_url.is_empty() {
+        write_element(writer, "icon", &image_url)?;
+        write_element(writer, "logo", &image_url)?;
+    }
+    if !channel.category.is_empty() {
+        let mut category = BytesStart::new("category");
+        category.push_attribute(("term", channel.category.as_str()));
+        writer.write_event(Event::Empty(category))?;
+    }
+
+    for entry in &files {
+        let url = entry.enclosure_url(channel);
+        let date = rfc3339(&entry.pub_date);
+        writer.write_event(Event::Start(BytesStart::new("entry")))?;
+        write_element(writer, "id", &url)?;
+        write_element(writer, "title", &entry.title)?;
+        write_element(writer, "updated", &date)?;
+        write_element(writer, "published", &date)?;
+        if !entry.description.is_empty() {
+            write_element(writer, "summary", &entry.description)?;
+        }
+        write_link(writer, "alternate", &url, None, None)?;
+        write_link(writer, "enclosure", &url, Some(mime_type(entry)), Some(entry.size))?;
+        writer.write_event(Event::End(BytesEnd::new("entry")))?;
+    }
+
+    writer.write_event(Event::End(BytesEnd::new("feed")))?;
+    Ok(files.len())
+}
+
+fn write_link<W: Write>(writer: &mut Writer<W>, rel: &str, href: &str, mime_type: Option<&str>, length: Option<u64>) -> Result<()> {
+    let length = length.map(|l| l.to_string());
+    let mut link = BytesStart::new("link");
+    link.push_attribute(("rel", rel));
+    link.push_attribute(("href", href));
+    if let Some(mime_type) = mime_type {
+        link.push_attribute(("type", mime_type));
+    }
+    if let Some(length) = &length {
+        link.push_attribute(("length", length.as_str()));
+    }
+    writer.write_event(Event::Empty(link))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    home_page_url: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    description: &'a str,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    icon: String,
+    authors: Vec<JsonFeedAuthor<'a>>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    language: &'a str,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedAuthor<'a> {
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonFeedItem<'a> {
+    id: &'a str,
+    url: String,
+    title: &'a str,
+    content_text: &'a str,
+    date_published: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<&'a str>,
+    attachments: Vec<JsonFeedAttachment>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: &'static str,
+    size_in_bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_in_seconds: Option<u64>,
+}
+
+pub fn write_json_feed<W: Write>(channel: &Channel, out: W, start_date: Option<NaiveDate>) -> Result<usize> {
+    let files = channel.feed_entries(start_date);
+    let items: Vec<JsonFeedItem> = files.iter().map(|entry| {
+        let url = entry.enclosure_url(channel);
+        JsonFeedItem {
+            id: &entry.guid,
+            url: url.clone(),
+            title: &entry.title,
+            content_text: &entry.description,
+            date_published: rfc3339(&entry.pub_date),
+            tags: [entry.event_code.as_str(), entry.location.as_str()].into_iter().filter(|t| !t.is_empty()).collect(),
+            attachments: vec![JsonFeedAttachment {
+                url,
+                mime_type: mime_type(entry),
+                size_in_bytes: entry.size,
+                duration_in_seconds: Some(entry.media.duration.round() as u64).filter(|d| *d > 0),
+            }],
+        }
+    }).collect();
+    let count = items.len();
+    let feed = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: &channel.title,
+        home_page_url: &channel.link,
+        description: &channel.description,
+        icon: channel.image_url(),
+        authors: vec![JsonFeedAuthor { name: channel.podcast.author(channel) }],
+        language: &channel.language,
+        items,
+    };
+    serde_json::to_writer_pretty(out, &feed)?;
+    Ok(count)
+}
@@ -1,8 +1,6 @@
 use std::fs;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::io::BufWriter;
-use regex::Regex;
 use lazy_static::lazy_static;
 use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Writer;
@@ -10,6 +8,13 @@ use chrono::{DateTime, Utc, Local, NaiveDate, NaiveDateTime};
 use anyhow::{Context, Result};
 use std::collections::HashMap;
 use tracing;
+use super::acl::AclConfig;
+use super::audit::AuditConfig;
+use super::ratelimit::RateLimitConfig;
+use super::forwardauth::ForwardAuthConfig;
+use super::parser::ParserRule;
+use super::podcast::{self, PodcastInfo};
+use super::feed::FeedFormat;
 
 // The Config structure contains a hashmap of another hashmap of channels.
 // The first key is language - "en", "zh"
@@ -21,6 +26,21 @@ pub struct Config {
     pub paths: HashMap<String, HashMap<String, Channel>>,
     pub default: ChannelDefaults,
     pub folders: HashMap<String, FolderShare>,
+    // Named filename parser sets; channels pick one with `parser`
+    #[serde(default)]
+    pub parsers: HashMap<String, Vec<ParserRule>>,
+    // Group and role based read access to channels, folder shares and paths
+    #[serde(default)]
+    pub acl: AclConfig,
+    // Throttling and lockouts of logins, Basic credentials and signed URLs
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    // Bypass rules and login redirect of /auth/v1/forward and /auth/v1/nginx
+    #[serde(default)]
+    pub forward_auth: ForwardAuthConfig,
+    // Retention of the audit log and who may read it
+    #[serde(default)]
+    pub audit: AuditConfig,
 }
 
 impl Config {
@@ -135,11 +155,34 @@ pub struct Channel {
     #[serde(default)]
     pub filter_extension: String,
     #[serde(default)]
+    pub filter_extensions: Vec<String>,
+    // How many levels of subfolders to scan; 0 reads only file_path itself
+    #[serde(default)]
+    pub max_depth: u32,
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub min_date: Option<NaiveDate>,
+    #[serde(default)]
+    pub max_date: Option<NaiveDate>,
+    #[serde(default)]
     pub output_path: String,
+    // Feed formats to write and where; an empty path derives one from output_path
+    #[serde(default)]
+    pub feeds: HashMap<FeedFormat, String>,
     #[serde(default)]
     pub image: String,
     #[serde(default)]
     pub image_path: String,
+    #[serde(default)]
+    pub parser: String,
+    #[serde(default)]
+    pub podcast: PodcastInfo,
+    // Serve /feeds/v1 without login even when the acl denies anonymous reads
+    #[serde(default)]
+    pub public: bool,
     #[serde(default)]
 	pub entries: Vec<MediaEntry>,
 }
@@ -161,9 +204,19 @@ impl Default for Channel {
             server_name: "localhost".to_string(),
             file_path: String::new(),
             filter_extension: String::new(),
+            filter_extensions: Vec::new(),
+            max_depth: 0,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            min_date: None,
+            max_date: None,
             output_path: String::new(),
+            feeds: HashMap::new(),
             image: String::new(),
             image_path: String::new(),
+            parser: String::new(),
+            podcast: PodcastInfo::default(),
+            public: false,
             entries: Vec::new(),
         }
     }
@@ -184,6 +237,14 @@ impl Channel {
     pub fn read_config(path: &str) -> Result<Config> {
         let file = File::open(path)?;
         let mut config: Config = serde_yaml::from_reader(file)?;
+        super::parser::register_parser_sets(&config.parsers)?;
+        for (lang, channels) in &config.channels {
+            for (name, channel) in channels {
+                if !super::parser::has_parser_set(&channel.parser) {
+                    return Err(anyhow::anyhow!("Channel {}/{} uses unknown parser set '{}'", lang, name, channel.parser));
+                }
+            }
+        }
         // Populate paths from channels
         for (lang, channels) in &config.channels {
             let mut path_map = HashMap::new();
@@ -259,6 +320,7 @@ impl Channel {
                 if channel.output_path.is_empty() {
                     channel.output_path = format!("{}/{}.rss", config.default.base_output_path.clone(), _name.to_lowercase());
                 }
+                super::scan::ScanFilter::new(channel).with_context(|| format!("Invalid scan rules for channel {}/{}", _lang, _name))?;
             }
         }
         let mut folders: HashMap<String, FolderShare> = HashMap::new();
@@ -287,17 +349,84 @@ impl Channel {
     // Sequential version (FASTER for ≤35k files)
     fn read_dir_sequential(channel: &Channel) -> std::io::Result<Vec<MediaEntry>> {
         let path = Path::new(&channel.file_path);
-        let files: Vec<MediaEntry> = fs::read_dir(path)?
-            .flatten()
-            .filter_map(|entry| MediaEntry::from_entry(entry, channel).ok())
-            .collect();
+        if channel.max_depth == 0 || channel.source == "explorer" {
+            let files: Vec<MediaEntry> = fs::read_dir(path)?
+                .flatten()
+                .filter_map(|entry| MediaEntry::from_entry(entry, channel, "").ok())
+                .collect();
+            return Ok(files);
+        }
+        let filter = super::scan::ScanFilter::new(channel).map_err(std::io::Error::other)?;
+        let mut files = Vec::new();
+        Self::walk_dir(channel, &filter, path, "", 0, &mut files)?;
         Ok(files)
     }
+
+    fn walk_dir(channel: &Channel, filter: &super::scan::ScanFilter, dir: &Path, sub_path: &str, depth: u32, files: &mut Vec<MediaEntry>) -> std::io::Result<()> {
+        for entry in fs::read_dir(dir)?.flatten() {
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            if !is_dir {
+                if let Ok(fi) = MediaEntry::from_entry(entry, channel, sub_path) {
+                    files.push(fi);
+                }
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.starts_with('.') || depth >= channel.max_depth {
+                continue;
+            }
+            let child = if sub_path.is_empty() { name } else { format!("{}/{}", sub_path, name) };
+            if filter.is_excluded(&child) {
+                continue;
+            }
+            if let Err(e) = Self::walk_dir(channel, filter, &entry.path(), &child, depth + 1, files) {
+                tracing::error!("Error reading directory {}: {}", entry.path().display(), e);
+            }
+        }
+        Ok(())
+    }
+
+    // The sub_path read_dir would list `path` under, None when the scan never
+    // reaches it (outside the channel, too deep, hidden or excluded folder)
+    pub fn scan_sub_path(&self, path: &Path) -> Option<String> {
+        let rel = path.strip_prefix(&self.file_path).ok()?;
+        let folders: Vec<String> = rel.parent()?.components().map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+        if folders.is_empty() {
+            return Some(String::new());
+        }
+        if self.max_depth == 0 || self.source == "explorer" || folders.len() > self.max_depth as usize {
+            return None;
+        }
+        let filter = super::scan::ScanFilter::new(self).ok()?;
+        let mut sub_path = String::new();
+        for folder in folders {
+            if folder.starts_with('.') {
+                return None;
+            }
+            sub_path = if sub_path.is_empty() { folder } else { format!("{}/{}", sub_path, folder) };
+            if filter.is_excluded(&sub_path) {
+                return None;
+            }
+        }
+        Some(sub_path)
+    }
+
+    // filter_extension plus filter_extensions, "*" meaning any
+    pub fn extensions(&self) -> Vec<String> {
+        let mut exts: Vec<String> = self.filter_extensions.iter().filter(|e| !e.is_empty() && *e != "*").cloned().collect();
+        if !self.filter_extension.is_empty() && self.filter_extension != "*" {
+            exts.push(self.filter_extension.clone());
+        }
+        exts
+    }
+
     pub fn set_entries(&mut self, entries: Vec<MediaEntry>) {
-        let mut files: Vec<MediaEntry> = if self.filter_extension.is_empty() || self.filter_extension == "*" {
-            entries
-        } else {
-            entries.into_iter().filter(|e| e.file_name.ends_with(&self.filter_extension)).collect()
+        let mut files: Vec<MediaEntry> = match super::scan::ScanFilter::new(self) {
+            Ok(filter) => entries.into_iter().filter(|e| filter.accepts(e)).collect(),
+            Err(e) => {
+                tracing::error!("Invalid scan rules for channel {}: {}", self.cache_id(), e);
+                entries
+            }
         };
 
         // Deduplicate entries based on normalized_entry_id("zsv")
@@ -406,28 +535,54 @@ impl Channel {
     }
 
     pub fn write_rss_tofile(&mut self, start_date: NaiveDate, output: &str) -> Result<()> {
+        self.write_feed_tofile(FeedFormat::Rss, start_date, output)
+    }
+
+    pub fn write_feed_tofile(&mut self, format: FeedFormat, start_date: NaiveDate, output: &str) -> Result<()> {
         let cache_id = self.cache_id();
-        tracing::info!("Writing RSS for channel {} to {}", cache_id, output);
-
-        // Create output file and XML writer
-        let file = File::create(output).map_err(|e| {
-            tracing::error!("Failed to create output file '{}': {}", output, e);
-            e
-        }).context("Failed to create output file")?;
-        let buf_writer = BufWriter::new(file);
-        let mut writer = Writer::new(buf_writer);
-
-        // Write RSS
-        let count = self.write_rss(&mut writer, Some(start_date)).map_err(|e| {
-            tracing::error!("Failed to write RSS content to '{}': {}", output, e);
-            e
-        })?;
-
-        tracing::info!("RSS feed written to {} with {} entries", output, count);
+        tracing::info!("Writing {} for channel {} to {}", format.extension(), cache_id, output);
+        let count = format.write_tofile(self, Some(start_date), output)?;
+        tracing::info!("{} feed written to {} with {} entries", format.extension(), output, count);
         Ok(())
     }
 
+    // Configured feed outputs; just RSS to output_path when `feeds` is not set
+    pub fn feed_outputs(&self) -> Vec<(FeedFormat, String)> {
+        if self.feeds.is_empty() {
+            return vec![(FeedFormat::Rss, self.output_path.clone())];
+        }
+        FeedFormat::ALL.iter().filter_map(|format| {
+            let path = self.feeds.get(format)?;
+            if !path.is_empty() {
+                return Some((*format, path.clone()));
+            }
+            let base = Path::new(&self.output_path).with_extension(format.extension());
+            Some((*format, base.to_string_lossy().to_string()))
+        }).collect()
+    }
+
+    // Entries published on or after start_date; French channels keep everything
+    pub fn feed_entries(&self, start_date: Option<NaiveDate>) -> Vec<MediaEntry> {
+        match start_date {
+            Some(start_date) if !self.language.starts_with("fr") => {
+                self.entries.iter().filter(|entry| entry.pub_date.date() >= start_date).cloned().collect()
+            }
+            _ => self.entries.clone(),
+        }
+    }
+
+
 
+    // Channel artwork: `image` as an absolute url, or `image_path` under media_link
+    pub fn image_url(&self) -> String {
+        if !self.image.is_empty() {
+            self.image.clone()
+        } else if !self.image_path.is_empty() {
+            format!("{}/{}", self.media_link.trim_end_matches('/'), self.image_path.trim_start_matches('/'))
+        } else {
+            String::new()
+        }
+    }
 
     pub fn write_rss<W: std::io::Write>(&mut self, writer: &mut Writer<W>, start_date: Option<NaiveDate>) -> Result<usize> {
 
@@ -435,11 +590,16 @@ impl Channel {
         let mut rss_start = BytesStart::new("rss");
         rss_start.push_attribute(("version", "2.0"));
         rss_start.push_attribute(("xmlns:itunes", "http://www.itunes.com/dtds/podcast-1.0.dtd"));
+        rss_start.push_attribute(("xmlns:podcast", "https://podcastindex.org/namespace/1.0"));
+        rss_start.push_attribute(("xmlns:atom", "http://www.w3.org/2005/Atom"));
         writer.write_event(Event::Start(rss_start))?;
 
         // Start channel element
         writer.write_event(Event::Start(BytesStart::new("channel")))?;
 
+        let info = &self.podcast;
+        let image_url = self.image_url();
+
         // Add channel metadata
         write_element(writer, "title", &self.title)?;
         write_element(writer, "link", &self.link)?;
@@ -449,33 +609,54 @@ impl Channel {
         let now = Local::now();
         let last_build_date = now.to_rfc2822();
         write_element(writer, "lastBuildDate", &last_build_date)?;
-        let mut category = BytesStart::new("category");
-        category.push_attribute(("text", "Christianity"));
-        writer.write_event(Event::Empty(category))?;
+        if !info.copyright.is_empty() {
+            write_element(writer, "copyright", &info.copyright)?;
+        }
+        if !info.feed_url.is_empty() {
+            podcast::write_empty(writer, "atom:link", &[("href", info.feed_url.as_str()), ("rel", "self"), ("type", "application/rss+xml")])?;
+        }
+        if !self.category.is_empty() {
+            write_element(writer, "category", &self.category)?;
+        }
+        if !image_url.is_empty() {
+            writer.write_event(Event::Start(BytesStart::new("image")))?;
+            write_element(writer, "url", &image_url)?;
+            write_element(writer, "title", &self.title)?;
+            write_element(writer, "link", &self.link)?;
+            writer.write_event(Event::End(BytesEnd::new("image")))?;
+        }
 
         // iTunes channel elements
-        write_element(writer, "itunes:author", "GJCC")?;
-        write_element(writer, "itunes:explicit", "no")?;
-        let mut category = BytesStart::new("itunes:category");
-        category.push_attribute(("text", "Christianity"));
-        writer.write_event(Event::Empty(category))?;
+        write_element(writer, "itunes:author", info.author(self))?;
+        podcast::write_itunes_owner(writer, info)?;
+        if !image_url.is_empty() {
+            podcast::write_empty(writer, "itunes:image", &[("href", image_url.as_str())])?;
+        }
+        podcast::write_itunes_categories(writer, info)?;
+        write_element(writer, "itunes:explicit", if info.explicit { "true" } else { "false" })?;
+        write_element(writer, "itunes:type", if info.itunes_type.is_empty() { "episodic" } else { &info.itunes_type })?;
         let now = Local::now();
         let subtitle = format!("{} Pub: {}", &self.title, now.format("%a %b %d %H:%M:%S %Z %Y"));
         write_element(writer, "itunes:subtitle", &subtitle)?;
 
-        let mut files = self.entries.clone();
-
-        if !self.language.starts_with("fr"){
-            if let Some(start_date) = start_date {
-                files = files.into_iter().filter(|entry| {
-                    entry.pub_date.date() >= start_date
-                }).collect();
-            }
+        // Podcasting 2.0 channel elements
+        write_element(writer, "podcast:guid", &info.podcast_guid(self))?;
+        let mut locked = BytesStart::new("podcast:locked");
+        if !info.owner_email.is_empty() {
+            locked.push_attribute(("owner", info.owner_email.as_str()));
+        }
+        writer.write_event(Event::Start(locked))?;
+        writer.write_event(Event::Text(BytesText::new(if info.locked { "yes" } else { "no" })))?;
+        writer.write_event(Event::End(BytesEnd::new("podcast:locked")))?;
+        if !info.medium.is_empty() {
+            write_element(writer, "podcast:medium", &info.medium)?;
         }
 
+        let files = self.feed_entries(start_date);
+
         // Add items for each entry
         for entry in &files {
-            entry.write_rss_item(writer, &self.media_link)?;
+            entry.write_rss_item(writer, self)?;
         }
 
         // End channel and RSS
@@ -507,6 +688,18 @@ pub struct MediaEntry {
     pub size: u64,
     pub pub_date: NaiveDateTime,
     pub modified: std::time::SystemTime,
+    // Folder under the channel's file_path holding the file, "" for the top level
+    #[serde(default)]
+    pub sub_path: String,
+    // Duration, bitrate, codecs and tags probed from the file
+    #[serde(default)]
+    pub media: super::probe::MediaInfo,
+    // Transcripts, chapters and artwork next to the file
+    #[serde(default)]
+    pub sidecars: super::podcast::SidecarFiles,
+    // Replaces the media_link URL in feeds, e.g. with a signed URL; never stored
+    #[serde(skip)]
+    pub enclosure: Option<String>,
 }
 
 impl Default for MediaEntry {
@@ -531,6 +724,10 @@ impl Default for MediaEntry {
             size: 0,
             pub_date: NaiveDate::from_ymd_opt(1970, 1, 1).expect("Invalid default date").and_hms_opt(0,0,0).unwrap(),
             modified: std::time::UNIX_EPOCH,
+            sub_path: String::new(),
+            media: super::probe::MediaInfo::default(),
+            sidecars: super::podcast::SidecarFiles::default(),
+            enclosure: None,
         }
     }
 }
@@ -591,25 +788,45 @@ impl MediaEntry {
         format!("{}{}-{}-{}", prefix, self.file_date_stamp, event_part, index_part)
     }
 
-    pub fn from_entry(entry: std::fs::DirEntry, channel: &Channel) -> std::io::Result<Self> {
-        let metadata = entry.metadata()?;
+    // Path relative to the channel root
+    pub fn rel_path(&self) -> String {
+        if self.sub_path.is_empty() {
+            self.file_name.clone()
+        } else {
+            format!("{}/{}", self.sub_path, self.file_name)
+        }
+    }
+
+    // Location of the file on disk
+    pub fn file_path(&self, channel: &Channel) -> PathBuf {
+        Path::new(&channel.file_path).join(self.rel_path())
+    }
+
+    pub fn from_entry(entry: std::fs::DirEntry, channel: &Channel, sub_path: &str) -> std::io::Result<Self> {
+        Self::from_path(&entry.path(), channel, sub_path)
+    }
+
+    pub fn from_path(path: &Path, channel: &Channel, sub_path: &str) -> std::io::Result<Self> {
+        let metadata = fs::symlink_metadata(path)?;
         if !metadata.is_file() {
             if metadata.is_dir() && channel.source == "explorer" {
-                let fname = entry.file_name().to_string_lossy().to_string();
-                let folder = MediaEntry::new_folder(&entry.path().to_string_lossy(), &fname, metadata.modified()?);
+                let fname = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                let folder = MediaEntry::new_folder(&path.to_string_lossy(), &fname, metadata.modified()?);
                 return Ok(folder);
             }
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "not a file"));
         }
 
-        let path_str = entry.path().to_string_lossy().to_string();
-        let mut fi = parse_file_name(&path_str);
+        let path_str = path.to_string_lossy().to_string();
+        let mut fi = parse_file_name_with(&path_str, &channel.parser);
         if fi.file_name.is_empty() {
-            fi.file_name = entry.file_name().to_string_lossy().to_string();
+            fi.file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
         }
         fi.event = fi.event.replace("&", "");
+        fi.sub_path = sub_path.to_string();
         fi.size = metadata.len();
         fi.modified = metadata.modified()?;
+        fi.sidecars = super::podcast::SidecarFiles::scan(path);
         // Set pub_date based on file_date_stamp if valid, otherwise use modified time
         fi.pub_date = if let Ok(date) = NaiveDate::parse_from_str(&fi.file_date_stamp, "%y%m%d") {
             date.and_hms_opt(0,0,0).unwrap()
@@ -623,7 +840,7 @@ impl MediaEntry {
             let event_str = fi.event.clone();
             fi.normalize_date_range(&event_str);
         }
-        fi.guid = format!("{}/{}", channel.server_name, fi.file_name);
+        fi.guid = format!("{}/{}", channel.server_name, fi.rel_path());
         fi.fill_rss_fields(channel);
         Ok(fi)
     }
@@ -634,7 +851,7 @@ impl MediaEntry {
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "not a file"));
         }
         let path_str = entry.path().to_string_lossy().to_string();
-        let mut fi = parse_photo_archive_name(&path_str);
+        let mut fi = parse_file_name_with(&path_str, &channel.parser);
         if fi.file_name.is_empty() {
             fi.file_name = entry.file_name().to_string_lossy().to_string();
         }
@@ -719,15 +936,23 @@ impl MediaEntry {
             self.description = self.construct_description();
         }
         if self.link.is_empty(){
-            self.link = format!("{}/{}", channel.media_link.trim_end_matches('/'), self.file_name);
+            self.link = format!("{}/{}", channel.media_link.trim_end_matches('/'), self.rel_path());
         }
         //self.pub_date = self.modified;
     }
 
-    pub fn write_rss_item<W: std::io::Write>(&self, writer: &mut Writer<W>, media_link: &str) -> Result<()> {
-        let url = format!("{}/{}", media_link.trim_end_matches('/'), self.file_name);
+    // Where feeds point podcast apps for the file
+    pub fn enclosure_url(&self, channel: &Channel) -> String {
+        self.enclosure.clone()
+            .unwrap_or_else(|| format!("{}/{}", channel.media_link.trim_end_matches('/'), self.rel_path()))
+    }
+
+    pub fn write_rss_item<W: std::io::Write>(&self, writer: &mut Writer<W>, channel: &Channel) -> Result<()> {
+        let url = self.enclosure_url(channel);
         let datetime = self.pub_date;
-        let pub_date: String = DateTime::<Utc>::from_naive_utc_and_offset(datetime, Utc).to_rfc3339();
+        let pub_date: String = DateTime::<Utc>::from_naive_utc_and_offset(datetime, Utc).to_rfc2822();
+        let info = &channel.podcast;
+        let sidecars = podcast::find_sidecars(channel, self);
 
         // Start item
         writer.write_event(Event::Start(BytesStart::new("item")))?;
@@ -751,10 +976,49 @@ impl MediaEntry {
         write_element(writer, "pubDate", &pub_date)?;
 
         // GUID
-        write_element(writer, "guid", &self.guid)?;
+        let mut guid = BytesStart::new("guid");
+        guid.push_attribute(("isPermaLink", "false"));
+        writer.write_event(Event::Start(guid))?;
+        writer.write_event(Event::Text(BytesText::new(&self.guid)))?;
+        writer.write_event(Event::End(BytesEnd::new("guid")))?;
+
+        // iTunes item elements
+        write_element(writer, "itunes:title", &self.title)?;
+        write_element(writer, "itunes:author", info.author(channel))?;
+        write_element(writer, "itunes:explicit", if info.explicit { "true" } else { "false" })?;
+        write_element(writer, "itunes:episodeType", "full")?;
+        if self.media.duration > 0.0 {
+            write_element(writer, "itunes:duration", &self.media.duration_hms())?;
+        }
+        let (season, episode) = podcast::season_episode(self);
+        if let Some(season) = season {
+            write_element(writer, "itunes:season", &season.to_string())?;
+        }
+        if let Some(episode) = episode {
+            write_element(writer, "itunes:episode", &episode.to_string())?;
+        }
+        let image = sidecars.image.clone().unwrap_or_else(|| channel.image_url());
+        if !image.is_empty() {
+            podcast::write_empty(writer, "itunes:image", &[("href", image.as_str())])?;
+        }
 
-        // iTunes Author
-        write_element(writer, "itunes:author", "GJCC")?;
+        // Podcasting 2.0 item elements
+        if let Some(season) = season {
+            write_element(writer, "podcast:season", &season.to_string())?;
+        }
+        if let Some(episode) = episode {
+            write_element(writer, "podcast:episode", &episode.to_string())?;
+        }
+        for transcript in &sidecars.transcripts {
+            let mut attrs = vec![("url", transcript.url.as_str()), ("type", transcript.mime_type.as_str())];
+            if !channel.language.is_empty() {
+                attrs.push(("language", channel.language.as_str()));
+            }
+            podcast::write_empty(writer, "podcast:transcript", &attrs)?;
+        }
+        if let Some(chapters) = &sidecars.chapters {
+            podcast::write_empty(writer, "podcast:chapters", &[("url", chapters.url.as_str()), ("type", chapters.mime_type.as_str())])?;
+        }
 
         // End item
         writer.write_event(Event::End(BytesEnd::new("item")))?;
@@ -763,7 +1027,7 @@ impl MediaEntry {
     }
 }
 
-fn write_element<W: std::io::Write>(
+pub(crate) fn write_element<W: std::io::Write>(
     writer: &mut Writer<W>,
     tag: &str,
     content: &str,
@@ -774,172 +1038,16 @@ fn write_element<W: std::io::Write>(
     Ok(())
 }
 
-fn parse_photo_archive_name(filename: &str) -> MediaEntry {
-    let base = std::path::Path::new(filename).file_name().unwrap_or_default().to_string_lossy().to_string();
-    let mut fi = MediaEntry {
-        media_type: super::formatter::parse_media_type(filename),
-        file_name: base.to_string(),
-        location: base.to_string(),
-        content_type: "photos".to_string(),
-        ..Default::default()
-    };
-    if let Some(caps) = RE_PHOTOS_ARCHIVE.captures(&base) {
-        fi.location = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        fi.location = crate::models::formatter::normalize_location(&fi.location);
-        fi.file_date_stamp = caps.get(2).map_or("", |m| m.as_str()).to_string();
-        fi.event = caps.get(3).map_or("", |m| m.as_str()).to_string();
-        fi.event_code = caps.get(4).map_or("", |m| m.as_str()).to_string();
-        fi.event_desc = caps.get(5).map_or("", |m| m.as_str()).to_string();
-        fi.event_desc = crate::models::formatter::format_eng_descr(&fi.event_desc);
-    }
-    fi
-}
-
+// Parse a file name with the built-in rule set
 pub fn parse_file_name(filename: &str) -> MediaEntry {
-    let base = std::path::Path::new(filename).file_name().unwrap_or_default().to_string_lossy().to_string();
-    let mut fi = MediaEntry {
-        mime_type: super::formatter::parse_mime_type(filename),
-        file_name: base.to_string(),
-        ..Default::default()
-    };
-    fi.media_type = super::formatter::parse_media_type_from_mime(&fi.mime_type);
-    if (filename.contains("/Pictures/") || filename.contains("/Photos/")) && fi.file_name.ends_with(".zip") {
-        let me = parse_photo_archive_name(filename);
-        if !me.location.is_empty() {
-            return me;
-        }
-    }
-    if let Some(caps) = RE_ZSV_PATTERN.captures(&base) {
-        fi.content_type = "zsf".to_string();
-        fi.file_date_stamp = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        fi.day_night = caps.get(2).map_or("", |m| m.as_str()).to_string();
-        fi.event = caps.get(3).map_or("", |m| m.as_str()).to_string();
-        if fi.event == "List" {
-            fi.event_desc = fi.event.clone();
-            fi.event = "".to_string();
-        }
-        if fi.event.len() > 1 {
-            fi.event_code = fi.event.chars().last().expect("len > 1").to_string();
-        }
-        fi.index = caps.get(4).map_or("", |m| m.as_str()).to_string();
-        fi.event_desc = caps.get(5).map_or("", |m| m.as_str()).trim_matches('-').to_string();
-        if !fi.event_desc.is_empty() {
-            if let Some(caps_desc) = RE_ZSV_DESC_DATED.captures(&fi.event_desc) {
-                fi.location = caps_desc.get(1).map_or("", |m| m.as_str()).to_string();
-                fi.event_date_stamp = caps_desc.get(2).map_or("", |m| m.as_str()).to_string();
-                fi.day_night = caps_desc.get(3).map_or("", |m| m.as_str()).to_string();
-                fi.event_desc = caps_desc.get(4).map_or("", |m| m.as_str()).to_string();
-            } else if let Some(caps_desc) = RE_ZSV_DESC_PATTERN.captures(&fi.event_desc) {
-                fi.location = caps_desc.get(1).map_or("", |m| m.as_str()).to_string();
-                fi.event_date_stamp = caps_desc.get(2).map_or("", |m| m.as_str()).to_string();
-                fi.event_desc = caps_desc.get(3).map_or("", |m| m.as_str()).to_string();
-            }
-            fi.event_desc = crate::models::formatter::format_eng_descr(&fi.event_desc);
-        }
-        return fi;
-    }
-
-    if let Some(caps) = RE_ZS_PATTERN.captures(&base) {
-        fi.content_type = "zs".to_string();
-        fi.file_date_stamp = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        fi.day_night = caps.get(2).map_or("", |m| m.as_str()).to_string();
-        fi.event = caps.get(4).map_or("", |m| m.as_str()).to_string();
-        if fi.event.starts_with("e") && fi.event.len() > 2 {
-            fi.day_night = "e".to_string();
-            fi.event = fi.event[1..].to_string();
-        }
-        if fi.event.len() > 1 {
-            fi.event_code = fi.event.chars().last().expect("len > 1").to_string();
-        }
-        fi.event_desc = caps.get(5).map_or("", |m| m.as_str()).trim_matches('-').to_string();
-        if fi.event_desc.is_empty() {
-            fi.event_desc = content_desc(&fi.event_code, "");
-        } else if let Some(caps_desc) = RE_ZSV_DESC_PATTERN.captures(&fi.event_desc) {
-            fi.location = caps_desc.get(1).map_or("", |m| m.as_str()).to_string();
-            fi.event_date_stamp = caps_desc.get(2).map_or("", |m| m.as_str()).to_string();
-            fi.event_desc = caps_desc.get(3).map_or("", |m| m.as_str()).to_string();
-        }
-        if !fi.event_desc.is_empty() {
-            fi.event_desc = crate::models::formatter::format_eng_descr(&fi.event_desc);
-        }
-        return fi;
-    }
-
-    if let Some(caps) = RE_THABOR_PATTERN.captures(&base) {
-        fi.content_type = "thabor".to_string();
-        fi.file_date_stamp = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        fi.day_night = caps.get(2).map_or("", |m| m.as_str()).to_string();
-        fi.index = caps.get(3).map_or("", |m| m.as_str()).to_string();
-        fi.event_desc = caps.get(4).map_or("", |m| m.as_str()).trim_matches('-').to_string();
-        if !fi.event_desc.is_empty() {
-            if fi.event_desc.contains("-R"){
-                fi.event = "1r".to_string();
-            }
-            if fi.event_desc.contains("Soir"){
-                fi.day_night = "e".to_string();
-            }
-            fi.event_desc = crate::models::formatter::format_eng_descr(&fi.event_desc);
-        }
-        if fi.event.is_empty() {
-            fi.event = "1c".to_string();
-        }
-        fi.event_code = fi.event.chars().last().expect("len > 1").to_string();
-        if fi.index.is_empty() {
-            fi.index = "1".to_string();
-        }
-        fi.event_date_stamp = fi.file_date_stamp.clone();
-        fi.location = "Mt Thabor".to_string();
-        return fi;
-    }
+    super::parser::parser_set(super::parser::DEFAULT_PARSER_SET).parse(filename)
+}
 
-    if let Some(caps) = RE_ANY_FULL_PATTERN.captures(&base) {
-        fi.content_type = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        fi.file_date_stamp = caps.get(2).map_or("", |m| m.as_str()).to_string();
-        fi.day_night = caps.get(3).map_or("", |m| m.as_str()).to_string();
-        fi.event = caps.get(4).map_or("", |m| m.as_str()).to_string();
-        if fi.event == "List" {
-            fi.event_desc = fi.event.clone();
-            fi.event = "".to_string();
-        }
-        if fi.event.len() > 1 {
-            fi.event_code = fi.event.chars().last().expect("len > 1").to_string();
-        }
-        fi.event_desc = caps.get(5).map_or("", |m| m.as_str()).trim_matches('-').to_string();
-        if !fi.event_desc.is_empty() {
-            if let Some(caps_desc) = RE_ZSV_DESC_DATED.captures(&fi.event_desc) {
-                fi.location = caps_desc.get(1).map_or("", |m| m.as_str()).to_string();
-                fi.event_date_stamp = caps_desc.get(2).map_or("", |m| m.as_str()).to_string();
-                fi.day_night = caps_desc.get(3).map_or("", |m| m.as_str()).to_string();
-                fi.event_desc = caps_desc.get(4).map_or("", |m| m.as_str()).to_string();
-            } else if let Some(caps_desc) = RE_ZSV_DESC_PATTERN.captures(&fi.event_desc) {
-                fi.location = caps_desc.get(1).map_or("", |m| m.as_str()).to_string();
-                fi.event_date_stamp = caps_desc.get(2).map_or("", |m| m.as_str()).to_string();
-                fi.event_desc = caps_desc.get(3).map_or("", |m| m.as_str()).to_string();
-            }
-            fi.event_desc = crate::models::formatter::format_eng_descr(&fi.event_desc);
-        }
-        return fi;
-    }
-   
-    if let Some(caps) = RE_HYMN_PATTERN.captures(&base) {
-        fi.content_type = "zs".to_string();
-        fi.file_date_stamp = caps.get(1).map_or("", |m| m.as_str()).to_string();
-        fi.event = caps.get(2).map_or("", |m| m.as_str()).to_string();
-        if fi.event.len() > 1 {
-            fi.event_code = fi.event.chars().next().expect("len > 1").to_string();
-            fi.index = fi.event[1..].to_string();
-        }
-        fi.event_desc = caps.get(3).map_or("", |m| m.as_str()).trim_matches('-').to_string();
-        if !fi.event_desc.is_empty() {
-            fi.event_desc = format!("{}_{}", content_desc(&fi.event_code, ""), fi.event_desc);
-            fi.event_desc = crate::models::formatter::format_eng_descr(&fi.event_desc);
-        }
-    }
-    fi.title = Path::new(&fi.file_name).file_stem().unwrap_or_default().to_string_lossy().into_owned();
-    fi
+pub fn parse_file_name_with(filename: &str, parser: &str) -> MediaEntry {
+    super::parser::parser_set(parser).parse(filename)
 }
 
-fn content_desc(content_type: &str, event_desc: &str) -> String {
+pub(crate) fn content_desc(content_type: &str, event_desc: &str) -> String {
     match content_type {
         "r" => "Report".to_string(),
         "v" => "Video".to_string(),
@@ -995,14 +1103,6 @@ fn default_base_output_path() -> String {
 //const PARALLEL_THRESHOLD: usize = 35000;
 
 lazy_static! {
-    static ref RE_ZSV_PATTERN: Regex = Regex::new(r"^zsv(\d{6})(e?)-(\d{1,2}[a-z]{1,2}(?:&[a-z])?|\w+)(?:-(\d{1,2}z?)(?:-([^(.]+))?)?").expect("Invalid regex RE_ZSV_PATTERN");
-    static ref RE_ANY_FULL_PATTERN: Regex = Regex::new(r"^([A-Za-z]+)(\d{8})(e?)-(\d{1,2}[a-z]{1,2}(?:&[a-z])?|\w+)(?:-(.+))?.mp4").expect("Invalid regex RE_ANY_FULL_PATTERN");
-    static ref RE_ZSV_DESC_PATTERN: Regex = Regex::new(r"^([\w][\w\d]+)(?:[-]?(\d{6}|\d{2}\.\d{2}\.\d{4}))?-([^(.]+)").expect("Invalid regex RE_ZSV_DESC_PATTERN");
-    static ref RE_ZSV_DESC_DATED: Regex = Regex::new(r"(.*?)(?:[-_])?(\d{6}|\d{2}\.\d{2}\.\d{4})(e)?(?:-([^(.]+))?").expect("Invalid regex RE_ZSV_DESC_DATED");
-    static ref RE_ZS_PATTERN: Regex = Regex::new(r"^zs(\d{6})(e?)(?:-?([a-z]{1,3}))?-(e?\d{1,2}[a-z]{1,2}(?:&[a-z])?)(?:-?([^(.]+))?").expect("Invalid regex RE_ZS_PATTERN");
-    static ref RE_HYMN_PATTERN: Regex = Regex::new(r"^zs(\d{6})-(s\d{1,2})-h(\d{4})(?:-?([^(.]+))?").expect("Invalid regex RE_HYMN_PATTERN");
-    static ref RE_PHOTOS_ARCHIVE: Regex = Regex::new(r"^([\d]*[A-Za-z\'\-\_]+)(\d{6})(?:~(\d{1,4}))?([a-z])?\-([^(]+)").expect("Invalid regex RE_PHOTOS_ARCHIVE");
-    static ref RE_THABOR_PATTERN: Regex = Regex::new(r"^Thabor(\d{8})(e?)-(\d{1,2}[a-z]{1,2}(?:&[a-z])?)(?:-(.+))?-fra-lbr.mp4").expect("Invalid regex RE_ANY_FULL_PATTERN");
     static ref MIME_TYPE_MAP: HashMap<&'static str, &'static str> = {
         let mut map = HashMap::new();
         // Video formats
@@ -1071,6 +1171,15 @@ pub struct FolderShare {
     pub base_file_path: String,
     pub group: String,
     pub secret: String,
+    // Serve files whose symlinks resolve outside base_file_path
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    // List and serve dotfiles
+    #[serde(default)]
+    pub show_hidden: bool,
+    // Glob patterns of names never listed or served: "*.part", "@eaDir"
+    #[serde(default)]
+    pub hidden: Vec<String>,
 }
 
 impl FolderShare{
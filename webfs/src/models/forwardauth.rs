@@ -0,0 +1,98 @@
+use regex::Regex;
+use serde::Deserialize;
+use super::acl::normalize_path;
+use super::scan::glob_to_regex;
+
+// Forward auth for a proxy in front of dufs, from the `forward_auth` section
+// of config.yaml:
+//
+//   forward_auth:
+//     login_url: "https://media.example/login?rd={url}"
+//     bypass:
+//       - paths: ["/", "/*.*", "/assets/**"]
+//       - paths: ["/public/**"]
+//         methods: ["GET", "HEAD"]
+//         webdav: true
+//
+// Requests matching a bypass rule pass without credentials. Rules apply to
+// browsers only unless `webdav` is set. Leaving `bypass` out keeps the web
+// UI's assets and the /auth and /fs APIs open, as before.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForwardAuthConfig {
+    #[serde(default = "default_bypass")]
+    pub bypass: Vec<BypassRule>,
+    // Where browsers without a login are sent; "{url}" becomes the
+    // requested URL, encoded. Without it they get a 401.
+    #[serde(default)]
+    pub login_url: Option<String>,
+    // X-Webdav-Socket, naming the dufs instance behind the proxy
+    #[serde(default = "default_socket")]
+    pub socket: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BypassRule {
+    // Globs from the root: "*" within a segment, "**" across them
+    #[serde(default)]
+    pub paths: Vec<String>,
+    // Any method when empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub webdav: bool,
+}
+
+fn default_bypass() -> Vec<BypassRule> {
+    let paths = ["/", "/*.*", "/css/**", "/images/**", "/js/**", "/fonts/**", "/assets/**", "/auth/**", "/fs/**"];
+    vec![BypassRule { paths: paths.iter().map(|p| p.to_string()).collect(), ..Default::default() }]
+}
+
+fn default_socket() -> String {
+    "media".to_string()
+}
+
+impl Default for ForwardAuthConfig {
+    fn default() -> Self {
+        ForwardAuthConfig {
+            bypass: default_bypass(),
+            login_url: None,
+            socket: default_socket(),
+        }
+    }
+}
+
+impl BypassRule {
+    pub fn matches(&self, method: &str, path: &str, is_webdav: bool) -> bool {
+        if is_webdav && !self.webdav {
+            return false;
+        }
+        if !self.methods.is_empty() && !self.methods.iter().any(|m| m.eq_ignore_ascii_case(method)) {
+            return false;
+        }
+        self.paths.iter().any(|pattern| {
+            let pattern = format!("/{}", pattern.trim_start_matches('/'));
+            match Regex::new(&glob_to_regex(&pattern)) {
+                Ok(re) => re.is_match(path),
+                Err(e) => {
+                    tracing::error!("Invalid bypass pattern '{}': {}", pattern, e);
+                    false
+                }
+            }
+        })
+    }
+}
+
+impl ForwardAuthConfig {
+    // Whether `path` (without its query) passes without credentials. Rules
+    // match the path as the backend resolves it.
+    pub fn bypasses(&self, method: &str, path: &str, is_webdav: bool) -> bool {
+        let Some(path) = normalize_path(path) else { return false };
+        self.bypass.iter().any(|rule| rule.matches(method, &path, is_webdav))
+    }
+
+    // The login page for a browser that asked for `url`
+    pub fn login_redirect(&self, url: &str) -> Option<String> {
+        let encoded: String = url::form_urlencoded::byte_serialize(url.as_bytes()).collect();
+        self.login_url.as_ref().map(|login| login.replace("{url}", &encoded))
+    }
+}
@@ -1,4 +1,15 @@
+pub mod acl;
+pub mod apikey;
+pub mod audit;
 pub mod auth;
 pub mod file_desc;
+pub mod feed;
 pub mod files;
-pub mod formatter;
\ No newline at end of file
+pub mod formatter;
+pub mod forwardauth;
+pub mod parser;
+pub mod podcast;
+pub mod probe;
+pub mod ratelimit;
+pub mod scan;
+pub mod stats;
\ No newline at end of file
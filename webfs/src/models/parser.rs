@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use regex::Regex;
+use lazy_static::lazy_static;
+use anyhow::{anyhow, Context, Result};
+use tracing;
+
+use super::files::{content_desc, MediaEntry};
+
+pub const DEFAULT_PARSER_SET: &str = "default";
+
+// MediaEntry fields a rule may capture into or target from a post step.
+const ENTRY_FIELDS: &[&str] = &[
+    "file_date_stamp", "day_night", "event", "event_code", "index", "event_desc",
+    "location", "event_date_stamp", "content_type", "title", "description",
+];
+
+lazy_static! {
+    static ref RE_ZSV_DESC_PATTERN: Regex = Regex::new(r"^([\w][\w\d]+)(?:[-]?(\d{6}|\d{2}\.\d{2}\.\d{4}))?-([^(.]+)").expect("Invalid regex RE_ZSV_DESC_PATTERN");
+    static ref RE_ZSV_DESC_DATED: Regex = Regex::new(r"(.*?)(?:[-_])?(\d{6}|\d{2}\.\d{2}\.\d{4})(e)?(?:-([^(.]+))?").expect("Invalid regex RE_ZSV_DESC_DATED");
+    static ref PARSER_SETS: RwLock<HashMap<String, Arc<ParserSet>>> = {
+        let mut sets = HashMap::new();
+        let rules = builtin_rules().expect("Invalid built-in parser rules");
+        sets.insert(DEFAULT_PARSER_SET.to_string(), Arc::new(ParserSet::new(DEFAULT_PARSER_SET, rules).expect("Invalid built-in parser rules")));
+        RwLock::new(sets)
+    };
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ParserRule {
+    pub name: String,
+    pub pattern: String,
+    #[serde(default)]
+    pub priority: i32,
+    // Content type assigned on match; a `content_type` capture group overrides it
+    #[serde(default)]
+    pub content_type: String,
+    // Only try the rule when the full path contains one of these
+    #[serde(default)]
+    pub path_contains: Vec<String>,
+    // Only try the rule for files ending with one of these
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    // Use the file stem as the title instead of building one from the fields
+    #[serde(default)]
+    pub stem_title: bool,
+    #[serde(default)]
+    pub post: Vec<PostStep>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PostStep {
+    Trim { field: String, chars: String },
+    Set { field: String, value: String },
+    SetIfEmpty { field: String, value: String },
+    SetIfContains { field: String, contains: String, target: String, value: String },
+    ClearIf { field: String, equals: String },
+    Copy { from: String, to: String },
+    FormatDescr { field: String },
+    NormalizeLocation,
+    // event_code = last char of event
+    EventCodeLast,
+    // event_code = first char of event, index = the rest
+    EventCodeFirst,
+    // "e12c" -> day_night "e", event "12c"
+    EveningPrefix,
+    // Split event_desc into location, event date and description
+    SplitDesc { #[serde(default)] dated: bool },
+    // Fill an empty event_desc from the event code ("Report", "Hymn", ...)
+    DescFromCode,
+    // Prefix event_desc with the event code description
+    PrefixDescWithCode,
+}
+
+impl PostStep {
+    fn fields(&self) -> Vec<&str> {
+        match self {
+            PostStep::Trim { field, .. } | PostStep::Set { field, .. } | PostStep::SetIfEmpty { field, .. }
+            | PostStep::ClearIf { field, .. } | PostStep::FormatDescr { field } => vec![field],
+            PostStep::SetIfContains { field, target, .. } => vec![field, target],
+            PostStep::Copy { from, to } => vec![from, to],
+            _ => vec![],
+        }
+    }
+
+    fn apply(&self, fi: &mut MediaEntry) {
+        match self {
+            PostStep::Trim { field, chars } => {
+                if let Some(v) = field_mut(fi, field) {
+                    *v = v.trim_matches(|c| chars.contains(c)).to_string();
+                }
+            }
+            PostStep::Set { field, value } => {
+                if let Some(v) = field_mut(fi, field) {
+                    *v = value.clone();
+                }
+            }
+            PostStep::SetIfEmpty { field, value } => {
+                if let Some(v) = field_mut(fi, field) {
+                    if v.is_empty() {
+                        *v = value.clone();
+                    }
+                }
+            }
+            PostStep::SetIfContains { field, contains, target, value } => {
+                let found = field_mut(fi, field).map(|v| v.contains(contains.as_str())).unwrap_or(false);
+                if found {
+                    if let Some(v) = field_mut(fi, target) {
+                        *v = value.clone();
+                    }
+                }
+            }
+            PostStep::ClearIf { field, equals } => {
+                if let Some(v) = field_mut(fi, field) {
+                    if v == equals {
+                        v.clear();
+                    }
+                }
+            }
+            PostStep::Copy { from, to } => {
+                let value = field_mut(fi, from).map(|v| v.clone()).unwrap_or_default();
+                if let Some(v) = field_mut(fi, to) {
+                    *v = value;
+                }
+            }
+            PostStep::FormatDescr { field } => {
+                if let Some(v) = field_mut(fi, field) {
+                    if !v.is_empty() {
+                        *v = super::formatter::format_eng_descr(v);
+                    }
+                }
+            }
+            PostStep::NormalizeLocation => {
+                fi.location = super::formatter::normalize_location(&fi.location);
+            }
+            PostStep::EventCodeLast => {
+                if fi.event.len() > 1 {
+                    fi.event_code = fi.event.chars().last().expect("len > 1").to_string();
+                }
+            }
+            PostStep::EventCodeFirst => {
+                if fi.event.len() > 1 {
+                    fi.event_code = fi.event.chars().next().expect("len > 1").to_string();
+                    fi.index = fi.event[1..].to_string();
+                }
+            }
+            PostStep::EveningPrefix => {
+                if fi.event.starts_with('e') && fi.event.len() > 2 {
+                    fi.day_night = "e".to_string();
+                    fi.event = fi.event[1..].to_string();
+                }
+            }
+            PostStep::SplitDesc { dated } => {
+                if fi.event_desc.is_empty() {
+                    return;
+                }
+                let caps_dated = if *dated { RE_ZSV_DESC_DATED.captures(&fi.event_desc) } else { None };
+                if let Some(caps) = caps_dated {
+                    fi.location = caps.get(1).map_or("", |m| m.as_str()).to_string();
+                    fi.event_date_stamp = caps.get(2).map_or("", |m| m.as_str()).to_string();
+                    fi.day_night = caps.get(3).map_or("", |m| m.as_str()).to_string();
+                    fi.event_desc = caps.get(4).map_or("", |m| m.as_str()).to_string();
+                } else if let Some(caps) = RE_ZSV_DESC_PATTERN.captures(&fi.event_desc) {
+                    fi.location = caps.get(1).map_or("", |m| m.as_str()).to_string();
+                    fi.event_date_stamp = caps.get(2).map_or("", |m| m.as_str()).to_string();
+                    fi.event_desc = caps.get(3).map_or("", |m| m.as_str()).to_string();
+                }
+            }
+            PostStep::DescFromCode => {
+                if fi.event_desc.is_empty() {
+                    fi.event_desc = content_desc(&fi.event_code, "");
+                }
+            }
+            PostStep::PrefixDescWithCode => {
+                if !fi.event_desc.is_empty() {
+                    fi.event_desc = format!("{}_{}", content_desc(&fi.event_code, ""), fi.event_desc);
+                }
+            }
+        }
+    }
+}
+
+fn field_mut<'a>(fi: &'a mut MediaEntry, name: &str) -> Option<&'a mut String> {
+    match name {
+        "file_date_stamp" => Some(&mut fi.file_date_stamp),
+        "day_night" => Some(&mut fi.day_night),
+        "event" => Some(&mut fi.event),
+        "event_code" => Some(&mut fi.event_code),
+        "index" => Some(&mut fi.index),
+        "event_desc" => Some(&mut fi.event_desc),
+        "location" => Some(&mut fi.location),
+        "event_date_stamp" => Some(&mut fi.event_date_stamp),
+        "content_type" => Some(&mut fi.content_type),
+        "title" => Some(&mut fi.title),
+        "description" => Some(&mut fi.description),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    rule: ParserRule,
+    regex: Regex,
+}
+
+impl CompiledRule {
+    fn applies_to(&self, path: &str, file_name: &str) -> bool {
+        if !self.rule.path_contains.is_empty() && !self.rule.path_contains.iter().any(|p| path.contains(p.as_str())) {
+            return false;
+        }
+        if !self.rule.extensions.is_empty() && !self.rule.extensions.iter().any(|e| file_name.ends_with(e.as_str())) {
+            return false;
+        }
+        true
+    }
+}
+
+#[derive(Debug)]
+pub struct ParserSet {
+    pub name: String,
+    rules: Vec<CompiledRule>,
+}
+
+impl ParserSet {
+    pub fn new(name: &str, rules: Vec<ParserRule>) -> Result<ParserSet> {
+        let mut compiled = Vec::new();
+        for rule in rules {
+            let regex = Regex::new(&rule.pattern)
+                .with_context(|| format!("Invalid pattern for parser rule {}/{}", name, rule.name))?;
+            for group in regex.capture_names().flatten() {
+                if !ENTRY_FIELDS.contains(&group) {
+                    return Err(anyhow!("Parser rule {}/{}: unknown capture group '{}'", name, rule.name, group));
+                }
+            }
+            for field in rule.post.iter().flat_map(|s| s.fields()) {
+                if !ENTRY_FIELDS.contains(&field) {
+                    return Err(anyhow!("Parser rule {}/{}: unknown field '{}'", name, rule.name, field));
+                }
+            }
+            compiled.push(CompiledRule { rule, regex });
+        }
+        // Stable sort keeps declaration order for equal priorities
+        compiled.sort_by_key(|c| std::cmp::Reverse(c.rule.priority));
+        Ok(ParserSet { name: name.to_string(), rules: compiled })
+    }
+
+    pub fn rule_names(&self) -> Vec<&str> {
+        self.rules.iter().map(|r| r.rule.name.as_str()).collect()
+    }
+
+    pub fn parse(&self, filename: &str) -> MediaEntry {
+        let base = Path::new(filename).file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut fi = MediaEntry {
+            mime_type: super::formatter::parse_mime_type(filename),
+            file_name: base.to_string(),
+            ..Default::default()
+        };
+        fi.media_type = super::formatter::parse_media_type_from_mime(&fi.mime_type);
+        for compiled in &self.rules {
+            if !compiled.applies_to(filename, &base) {
+                continue;
+            }
+            let Some(caps) = compiled.regex.captures(&base) else {
+                continue;
+            };
+            fi.content_type = compiled.rule.content_type.clone();
+            for group in compiled.regex.capture_names().flatten() {
+                if let (Some(m), Some(v)) = (caps.name(group), field_mut(&mut fi, group)) {
+                    *v = m.as_str().to_string();
+                }
+            }
+            for step in &compiled.rule.post {
+                step.apply(&mut fi);
+            }
+            if compiled.rule.stem_title {
+                fi.title = file_stem(&fi.file_name);
+            }
+            return fi;
+        }
+        fi.title = file_stem(&fi.file_name);
+        fi
+    }
+}
+
+fn file_stem(file_name: &str) -> String {
+    Path::new(file_name).file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+pub fn builtin_rules() -> Result<Vec<ParserRule>> {
+    let rules: Vec<ParserRule> = serde_yaml::from_str(include_str!("parsers.yaml"))?;
+    Ok(rules)
+}
+
+// Compile and register the parser sets declared in config.yaml. A config
+// without its own "default" set keeps the built-in rules.
+pub fn register_parser_sets(sets: &HashMap<String, Vec<ParserRule>>) -> Result<()> {
+    let mut compiled = HashMap::new();
+    for (name, rules) in sets {
+        compiled.insert(name.clone(), Arc::new(ParserSet::new(name, rules.clone())?));
+    }
+    let mut registry = PARSER_SETS.write().map_err(|_| anyhow!("Parser registry poisoned"))?;
+    for (name, set) in compiled {
+        tracing::info!("Registered parser set {}: {:?}", name, set.rule_names());
+        registry.insert(name, set);
+    }
+    Ok(())
+}
+
+pub fn has_parser_set(name: &str) -> bool {
+    name.is_empty() || PARSER_SETS.read().expect("Parser registry poisoned").contains_key(name)
+}
+
+// Channel parser names are checked by read_config, so an unknown one only
+// falls back to the default set
+pub fn parser_set(name: &str) -> Arc<ParserSet> {
+    let registry = PARSER_SETS.read().expect("Parser registry poisoned");
+    let name = if name.is_empty() { DEFAULT_PARSER_SET } else { name };
+    match registry.get(name) {
+        Some(set) => set.clone(),
+        None => registry.get(DEFAULT_PARSER_SET).expect("Default parser set missing").clone(),
+    }
+}
@@ -0,0 +1,212 @@
+use std::path::Path;
+use quick_xml::events::{BytesEnd, BytesStart, Event};
+use quick_xml::Writer;
+use anyhow::Result;
+use uuid::Uuid;
+use super::files::{Channel, MediaEntry};
+
+// Podcast Index namespace for deriving podcast:guid from the feed url
+const PODCAST_GUID_NAMESPACE: Uuid = uuid::uuid!("ead4c236-bf58-58c6-a2c6-a6b28d128cb6");
+
+const TRANSCRIPT_TYPES: &[(&str, &str)] = &[
+    ("vtt", "text/vtt"),
+    ("srt", "application/x-subrip"),
+    ("json", "application/json"),
+    ("html", "text/html"),
+    ("txt", "text/plain"),
+];
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+// Per-channel iTunes / Podcasting 2.0 settings
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct PodcastInfo {
+    #[serde(default)]
+    pub owner_name: String,
+    #[serde(default)]
+    pub owner_email: String,
+    #[serde(default)]
+    pub explicit: bool,
+    // "episodic" or "serial"
+    #[serde(default)]
+    pub itunes_type: String,
+    // "Religion & Spirituality/Christianity" for a category with a subcategory
+    #[serde(default)]
+    pub categories: Vec<String>,
+    // Public url of the feed, written as atom:link rel="self"
+    #[serde(default)]
+    pub feed_url: String,
+    // podcast:guid; derived from feed_url (or link) when empty
+    #[serde(default)]
+    pub guid: String,
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default)]
+    pub copyright: String,
+    // podcast:medium - podcast, music, video, audiobook...
+    #[serde(default)]
+    pub medium: String,
+}
+
+impl PodcastInfo {
+    pub fn podcast_guid(&self, channel: &Channel) -> String {
+        if !self.guid.is_empty() {
+            return self.guid.clone();
+        }
+        let url = if self.feed_url.is_empty() { &channel.link } else { &self.feed_url };
+        let url = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url).trim_end_matches('/');
+        Uuid::new_v5(&PODCAST_GUID_NAMESPACE, url.as_bytes()).to_string()
+    }
+
+    pub fn itunes_categories(&self) -> Vec<(String, Option<String>)> {
+        let categories = if self.categories.is_empty() {
+            vec!["Religion & Spirituality/Christianity".to_string()]
+        } else {
+            self.categories.clone()
+        };
+        categories.iter().map(|c| match c.split_once('/') {
+            Some((main, sub)) => (main.trim().to_string(), Some(sub.trim().to_string())),
+            None => (c.trim().to_string(), None),
+        }).collect()
+    }
+
+    pub fn author<'a>(&'a self, channel: &'a Channel) -> &'a str {
+        if channel.author.is_empty() { &self.owner_name } else { &channel.author }
+    }
+}
+
+// Leading digits of an event or index code: "02r" -> 2
+pub fn code_number(code: &str) -> Option<u32> {
+    let digits: String = code.chars().take_while(|c| c.is_ascii_digit()).collect();
+    digits.parse::<u32>().ok().filter(|n| *n > 0)
+}
+
+// itunes:season / itunes:episode from the entry's event and index codes
+pub fn season_episode(entry: &MediaEntry) -> (Option<u32>, Option<u32>) {
+    if entry.index.is_empty() {
+        (None, code_number(&entry.event))
+    } else {
+        (code_number(&entry.event), code_number(&entry.index))
+    }
+}
+
+pub struct Sidecar {
+    pub url: String,
+    pub mime_type: String,
+}
+
+pub struct Sidecars {
+    pub transcripts: Vec<Sidecar>,
+    pub chapters: Option<Sidecar>,
+    pub image: Option<String>,
+}
+
+// Names of the transcripts (stem.vtt, stem.srt...), chapters
+// (stem.chapters.json) and artwork (stem.jpg) stored next to a media file,
+// looked up once when the file is scanned
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct SidecarFiles {
+    pub transcripts: Vec<String>,
+    pub chapters: Option<String>,
+    pub image: Option<String>,
+}
+
+impl SidecarFiles {
+    pub fn scan(path: &Path) -> SidecarFiles {
+        let mut files = SidecarFiles::default();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let Some(dir) = path.parent() else { return files };
+        if sidecar_stem(&file_name).is_some() {
+            return files;
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        let chapters = format!("{}.chapters.json", stem);
+        if dir.join(&chapters).is_file() {
+            files.chapters = Some(chapters);
+        }
+        for (ext, _) in TRANSCRIPT_TYPES {
+            let name = format!("{}.{}", stem, ext);
+            if dir.join(&name).is_file() {
+                files.transcripts.push(name);
+            }
+        }
+        files.image = IMAGE_EXTENSIONS.iter()
+            .map(|ext| format!("{}.{}", stem, ext))
+            .find(|name| dir.join(name).is_file());
+        files
+    }
+}
+
+// The media file stem a sidecar file belongs to, None for other files
+pub fn sidecar_stem(file_name: &str) -> Option<&str> {
+    if let Some(stem) = file_name.strip_suffix(".chapters.json") {
+        return Some(stem);
+    }
+    let (stem, ext) = file_name.rsplit_once('.')?;
+    let ext = ext.to_lowercase();
+    let known = TRANSCRIPT_TYPES.iter().any(|(t, _)| *t == ext) || IMAGE_EXTENSIONS.contains(&ext.as_str());
+    known.then_some(stem)
+}
+
+// Feed URLs of the sidecars found when the entry was scanned
+pub fn find_sidecars(channel: &Channel, entry: &MediaEntry) -> Sidecars {
+    let url_for = |name: &str| {
+        let base = channel.media_link.trim_end_matches('/');
+        if entry.sub_path.is_empty() {
+            format!("{}/{}", base, name)
+        } else {
+            format!("{}/{}/{}", base, entry.sub_path, name)
+        }
+    };
+    let files = &entry.sidecars;
+    let transcripts = files.transcripts.iter().map(|name| {
+        let ext = name.rsplit('.').next().unwrap_or_default().to_lowercase();
+        let mime_type = TRANSCRIPT_TYPES.iter().find(|(t, _)| *t == ext).map(|(_, m)| *m).unwrap_or("text/plain");
+        Sidecar { url: url_for(name), mime_type: mime_type.to_string() }
+    }).collect();
+    Sidecars {
+        transcripts,
+        chapters: files.chapters.as_deref().map(|name| Sidecar { url: url_for(name), mime_type: "application/json+chapters".to_string() }),
+        image: files.image.as_deref().map(url_for),
+    }
+}
+
+pub fn write_empty<W: std::io::Write>(writer: &mut Writer<W>, tag: &str, attrs: &[(&str, &str)]) -> Result<()> {
+    let mut elem = BytesStart::new(tag);
+    for attr in attrs {
+        elem.push_attribute(*attr);
+    }
+    writer.write_event(Event::Empty(elem))?;
+    Ok(())
+}
+
+pub fn write_itunes_categories<W: std::io::Write>(writer: &mut Writer<W>, info: &PodcastInfo) -> Result<()> {
+    for (main, sub) in info.itunes_categories() {
+        match sub {
+            Some(sub) => {
+                let mut elem = BytesStart::new("itunes:category");
+                elem.push_attribute(("text", main.as_str()));
+                writer.write_event(Event::Start(elem))?;
+                write_empty(writer, "itunes:category", &[("text", sub.as_str())])?;
+                writer.write_event(Event::End(BytesEnd::new("itunes:category")))?;
+            }
+            None => write_empty(writer, "itunes:category", &[("text", main.as_str())])?,
+        }
+    }
+    Ok(())
+}
+
+pub fn write_itunes_owner<W: std::io::Write>(writer: &mut Writer<W>, info: &PodcastInfo) -> Result<()> {
+    if info.owner_name.is_empty() && info.owner_email.is_empty() {
+        return Ok(());
+    }
+    writer.write_event(Event::Start(BytesStart::new("itunes:owner")))?;
+    if !info.owner_name.is_empty() {
+        super::files::write_element(writer, "itunes:name", &info.owner_name)?;
+    }
+    if !info.owner_email.is_empty() {
+        super::files::write_element(writer, "itunes:email", &info.owner_email)?;
+    }
+    writer.write_event(Event::End(BytesEnd::new("itunes:owner")))?;
+    Ok(())
+}
@@ -0,0 +1,384 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Result};
+
+// Largest moov atom we are willing to load into memory
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+// How far past the ID3 tag we look for the first MPEG audio frame
+const MP3_SYNC_WINDOW: usize = 64 * 1024;
+
+const MP4_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov", "m4a", "m4b", "3gp"];
+const MP3_EXTENSIONS: &[&str] = &["mp3"];
+
+// Technical details and embedded tags read from the media file itself
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MediaInfo {
+    // Seconds
+    pub duration: f64,
+    // Kilobits per second
+    pub bitrate: u32,
+    // Track codecs, "avc1,mp4a" or "mp3"
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+    pub artist: String,
+}
+
+impl MediaInfo {
+    // "1:02:03" / "02:03", as used by itunes:duration
+    pub fn duration_hms(&self) -> String {
+        let secs = self.duration.round() as u64;
+        let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+        if h > 0 { format!("{}:{:02}:{:02}", h, m, s) } else { format!("{:02}:{:02}", m, s) }
+    }
+
+    pub fn resolution(&self) -> Option<String> {
+        if self.width > 0 && self.height > 0 { Some(format!("{}x{}", self.width, self.height)) } else { None }
+    }
+}
+
+// Whether probe_file knows how to read files with this name
+pub fn is_probeable(file_name: &str) -> bool {
+    let ext = extension(file_name);
+    MP4_EXTENSIONS.contains(&ext.as_str()) || MP3_EXTENSIONS.contains(&ext.as_str())
+}
+
+// Probe (cache key, path) pairs from Storage::fill_media_info. Failures are
+// kept as empty info, so a bad file is not re-read on every refresh.
+pub fn probe_files(files: &[(String, PathBuf)]) -> Vec<(String, MediaInfo)> {
+    files.iter().map(|(key, path)| {
+        let info = probe_file(path).unwrap_or_else(|e| {
+            tracing::warn!("Failed to probe {}: {}", path.display(), e);
+            MediaInfo::default()
+        });
+        (key.clone(), info)
+    }).collect()
+}
+
+pub fn probe_file(path: &Path) -> Result<MediaInfo> {
+    let ext = extension(&path.to_string_lossy());
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if MP4_EXTENSIONS.contains(&ext.as_str()) {
+        probe_mp4(&mut file, size)
+    } else if MP3_EXTENSIONS.contains(&ext.as_str()) {
+        probe_mp3(&mut file, size)
+    } else {
+        Err(anyhow!("Unsupported media type '{}'", ext))
+    }
+}
+
+fn extension(file_name: &str) -> String {
+    file_name.rsplit_once('.').map(|(_, e)| e.to_lowercase()).unwrap_or_default()
+}
+
+fn be_u32(buf: &[u8], off: usize) -> Option<u32> {
+    buf.get(off..off + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn be_u64(buf: &[u8], off: usize) -> Option<u64> {
+    buf.get(off..off + 8).map(|b| u64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+}
+
+// ---------------------------------------------------------------------
+// MP4 / MOV / M4A
+// ---------------------------------------------------------------------
+
+// Iterate the child boxes of an in-memory box body as (type, body)
+fn mp4_boxes(buf: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let size = be_u32(buf, pos)? as u64;
+        let kind = buf.get(pos + 4..pos + 8)?;
+        let (header, size) = match size {
+            0 => (8, (buf.len() - pos) as u64),
+            1 => (16, be_u64(buf, pos + 8)?),
+            s => (8, s),
+        };
+        if size < header as u64 || size > (buf.len() - pos) as u64 {
+            return None;
+        }
+        let body = &buf[pos + header..pos + size as usize];
+        pos += size as usize;
+        Some((kind, body))
+    })
+}
+
+fn mp4_child<'a>(buf: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    mp4_boxes(buf).find(|(k, _)| *k == kind).map(|(_, b)| b)
+}
+
+fn probe_mp4(file: &mut File, file_size: u64) -> Result<MediaInfo> {
+    let moov = read_moov(file, file_size)?;
+    let mut info = MediaInfo::default();
+
+    if let Some(mvhd) = mp4_child(&moov, b"mvhd") {
+        let (timescale, duration) = if mvhd.first() == Some(&1) {
+            (be_u32(mvhd, 20), be_u64(mvhd, 24))
+        } else {
+            (be_u32(mvhd, 12), be_u32(mvhd, 16).map(|d| d as u64))
+        };
+        if let (Some(timescale), Some(duration)) = (timescale, duration) {
+            if timescale > 0 {
+                info.duration = duration as f64 / timescale as f64;
+            }
+        }
+    }
+
+    let mut codecs = Vec::new();
+    for (_, trak) in mp4_boxes(&moov).filter(|(k, _)| *k == b"trak") {
+        let Some(mdia) = mp4_child(trak, b"mdia") else { continue };
+        let handler = mp4_child(mdia, b"hdlr").and_then(|h| h.get(8..12)).unwrap_or_default();
+        let codec = mp4_child(mdia, b"minf")
+            .and_then(|minf| mp4_child(minf, b"stbl"))
+            .and_then(|stbl| mp4_child(stbl, b"stsd"))
+            .and_then(|stsd| stsd.get(12..16))
+            .map(|fourcc| String::from_utf8_lossy(fourcc).trim().to_string());
+        if handler == b"vide" && info.width == 0 {
+            // tkhd ends with width and height as 16.16 fixed point
+            if let Some(tkhd) = mp4_child(trak, b"tkhd") {
+                let len = tkhd.len();
+                if len >= 8 {
+                    info.width = be_u32(tkhd, len - 8).unwrap_or(0) >> 16;
+                    info.height = be_u32(tkhd, len - 4).unwrap_or(0) >> 16;
+                }
+            }
+        }
+        if handler == b"vide" || handler == b"soun" {
+            if let Some(codec) = codec.filter(|c| !c.is_empty()) {
+                codecs.push(codec);
+            }
+        }
+    }
+    info.codec = codecs.join(",");
+
+    if let Some(ilst) = mp4_child(&moov, b"udta").and_then(|udta| mp4_child(udta, b"meta")).and_then(mp4_ilst) {
+        for (kind, item) in mp4_boxes(ilst) {
+            let value = mp4_child(item, b"data").and_then(|d| d.get(8..)).map(|v| String::from_utf8_lossy(v).trim().to_string());
+            match (kind, value) {
+                (b"\xa9nam", Some(v)) => info.title = v,
+                (b"\xa9ART", Some(v)) => info.artist = v,
+                (b"aART", Some(v)) if info.artist.is_empty() => info.artist = v,
+                _ => {}
+            }
+        }
+    }
+
+    if info.duration > 0.0 {
+        info.bitrate = (file_size as f64 * 8.0 / info.duration / 1000.0).round() as u32;
+    }
+    Ok(info)
+}
+
+// ISO meta is a full box (4 bytes of version/flags), QuickTime meta is not
+fn mp4_ilst(meta: &[u8]) -> Option<&[u8]> {
+    mp4_child(meta, b"ilst").or_else(|| meta.get(4..).and_then(|m| mp4_child(m, b"ilst")))
+}
+
+// Walk the top level boxes to find moov, wherever it is in the file
+fn read_moov(file: &mut File, file_size: u64) -> Result<Vec<u8>> {
+    let mut pos = 0u64;
+    let mut header = [0u8; 16];
+    while pos.checked_add(8).is_some_and(|end| end <= file_size) {
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header[..8])?;
+        let (mut size, mut header_len) = (u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64, 8u64);
+        if size == 1 {
+            file.read_exact(&mut header[8..16])?;
+            size = u64::from_be_bytes([header[8], header[9], header[10], header[11], header[12], header[13], header[14], header[15]]);
+            header_len = 16;
+        } else if size == 0 {
+            size = file_size - pos;
+        }
+        if size < header_len || size > file_size - pos {
+            return Err(anyhow!("Invalid box size {} at offset {}", size, pos));
+        }
+        if &header[4..8] == b"moov" {
+            let body_len = size - header_len;
+            if body_len > MAX_MOOV_SIZE {
+                return Err(anyhow!("moov box too large ({} bytes)", body_len));
+            }
+            let mut moov = vec![0u8; body_len as usize];
+            file.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+        pos += size;
+    }
+    Err(anyhow!("No moov box found"))
+}
+
+// ---------------------------------------------------------------------
+// MP3
+// ---------------------------------------------------------------------
+
+// Kbps by bitrate index for MPEG-1 and MPEG-2/2.5 Layer III
+const MP3_BITRATES_V1: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MP3_BITRATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+const MP3_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+struct Mp3Frame {
+    mpeg1: bool,
+    mono: bool,
+    bitrate: u32,
+    sample_rate: u32,
+}
+
+impl Mp3Frame {
+    fn parse(header: u32) -> Option<Mp3Frame> {
+        if header >> 21 != 0x7ff {
+            return None;
+        }
+        let version = (header >> 19) & 0x3;
+        let layer = (header >> 17) & 0x3;
+        let bitrate_index = ((header >> 12) & 0xf) as usize;
+        let rate_index = ((header >> 10) & 0x3) as usize;
+        // Layer III only; version 1 is reserved
+        if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+            return None;
+        }
+        let mpeg1 = version == 3;
+        let bitrate = if mpeg1 { MP3_BITRATES_V1[bitrate_index] } else { MP3_BITRATES_V2[bitrate_index] };
+        let divisor = match version { 3 => 1, 2 => 2, _ => 4 };
+        Some(Mp3Frame {
+            mpeg1,
+            mono: (header >> 6) & 0x3 == 3,
+            bitrate,
+            sample_rate: MP3_SAMPLE_RATES[rate_index] / divisor,
+        })
+    }
+
+    fn samples(&self) -> u32 {
+        if self.mpeg1 { 1152 } else { 576 }
+    }
+
+    // Offset of the Xing/Info header from the frame start
+    fn xing_offset(&self) -> usize {
+        4 + match (self.mpeg1, self.mono) {
+            (true, false) => 32,
+            (true, true) | (false, false) => 17,
+            (false, true) => 9,
+        }
+    }
+}
+
+fn probe_mp3(file: &mut File, file_size: u64) -> Result<MediaInfo> {
+    let mut info = MediaInfo { codec: "mp3".to_string(), ..Default::default() };
+
+    let mut head = [0u8; 10];
+    file.read_exact(&mut head)?;
+    let mut audio_start = 0u64;
+    if &head[0..3] == b"ID3" {
+        let tag_size = synchsafe(&head[6..10]) as u64;
+        let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+        let mut tag = vec![0u8; tag_size.min(file_size.saturating_sub(10)) as usize];
+        file.read_exact(&mut tag)?;
+        read_id3v2(&tag, head[3], &mut info);
+        audio_start = 10 + tag_size + footer;
+    }
+
+    let mut tail_size = 0u64;
+    if file_size >= 128 {
+        let mut v1 = [0u8; 128];
+        file.seek(SeekFrom::Start(file_size - 128))?;
+        file.read_exact(&mut v1)?;
+        if &v1[0..3] == b"TAG" {
+            tail_size = 128;
+            if info.title.is_empty() {
+                info.title = latin1(&v1[3..33]);
+            }
+            if info.artist.is_empty() {
+                info.artist = latin1(&v1[33..63]);
+            }
+        }
+    }
+
+    file.seek(SeekFrom::Start(audio_start))?;
+    let mut buf = Vec::with_capacity(MP3_SYNC_WINDOW);
+    file.by_ref().take(MP3_SYNC_WINDOW as u64).read_to_end(&mut buf)?;
+    let (offset, frame) = (0..buf.len().saturating_sub(4))
+        .find_map(|i| Mp3Frame::parse(be_u32(&buf, i)?).map(|f| (i, f)))
+        .ok_or_else(|| anyhow!("No MPEG audio frame found"))?;
+
+    let audio_bytes = file_size.saturating_sub(audio_start + offset as u64 + tail_size);
+    let frames = vbr_frame_count(&buf[offset..], &frame);
+    match frames {
+        Some(frames) if frames > 0 => {
+            info.duration = frames as f64 * frame.samples() as f64 / frame.sample_rate as f64;
+            info.bitrate = (audio_bytes as f64 * 8.0 / info.duration / 1000.0).round() as u32;
+        }
+        _ => {
+            info.bitrate = frame.bitrate;
+            info.duration = audio_bytes as f64 * 8.0 / (frame.bitrate as f64 * 1000.0);
+        }
+    }
+    Ok(info)
+}
+
+// Total frame count from a Xing/Info or VBRI header in the first frame
+fn vbr_frame_count(frame_buf: &[u8], frame: &Mp3Frame) -> Option<u32> {
+    let xing = frame.xing_offset();
+    match frame_buf.get(xing..xing + 4) {
+        Some(b"Xing") | Some(b"Info") => {
+            let flags = be_u32(frame_buf, xing + 4)?;
+            return if flags & 1 != 0 { be_u32(frame_buf, xing + 8) } else { None };
+        }
+        _ => {}
+    }
+    if frame_buf.get(36..40) == Some(b"VBRI") {
+        return be_u32(frame_buf, 36 + 14);
+    }
+    None
+}
+
+fn synchsafe(b: &[u8]) -> u32 {
+    b.iter().fold(0u32, |acc, &x| (acc << 7) | (x & 0x7f) as u32)
+}
+
+fn read_id3v2(tag: &[u8], version: u8, info: &mut MediaInfo) {
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut pos = 0usize;
+    while pos + header_len <= tag.len() {
+        let id = &tag[pos..pos + id_len];
+        if id[0] == 0 {
+            break;
+        }
+        let size = match version {
+            2 => u32::from_be_bytes([0, tag[pos + 3], tag[pos + 4], tag[pos + 5]]) as usize,
+            3 => be_u32(tag, pos + 4).unwrap_or(0) as usize,
+            _ => synchsafe(&tag[pos + 4..pos + 8]) as usize,
+        };
+        let body_start = pos + header_len;
+        let Some(body) = tag.get(body_start..body_start + size) else { break };
+        match id {
+            b"TIT2" | b"TT2" => info.title = id3_text(body),
+            b"TPE1" | b"TP1" => info.artist = id3_text(body),
+            _ => {}
+        }
+        pos = body_start + size;
+    }
+}
+
+fn id3_text(body: &[u8]) -> String {
+    let Some((&encoding, text)) = body.split_first() else { return String::new() };
+    let value = match encoding {
+        1 | 2 => {
+            let units: Vec<u16> = text.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+            // UTF-16 with BOM: swap when the BOM says little-endian
+            let units: Vec<u16> = match units.first() {
+                Some(0xfffe) => units[1..].iter().map(|u| u.swap_bytes()).collect(),
+                Some(0xfeff) => units[1..].to_vec(),
+                _ => units,
+            };
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(text).to_string(),
+        _ => latin1(text),
+    };
+    value.trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string()
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect::<String>().trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string()
+}
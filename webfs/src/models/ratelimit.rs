@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use ipnet::IpNet;
+use serde::Deserialize;
+
+// Throttling of credential checks, from the `rate_limit` section of config.yaml:
+//
+//   rate_limit:
+//     trusted_proxies: ["127.0.0.1", "10.0.0.0/8"]
+//     routes:
+//       basic:
+//         requests_per_minute: 120
+//         max_failures: 5
+//         lockout_secs: 60
+//
+// Routes are "login" (/auth/v1/login), "basic" (Basic credentials on any
+// request) and "signurl" (signed URLs). Routes left out keep their own
+// defaults; fields left out take the general ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RateLimitConfig {
+    // Peers whose X-Forwarded-For / X-Real-IP are believed; loopback when empty
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    #[serde(default)]
+    pub routes: HashMap<String, RouteLimit>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct RouteLimit {
+    // Per client address; 0 for no limit
+    pub requests_per_minute: u32,
+    // Failed attempts per address or username before a lockout
+    pub max_failures: u32,
+    // Failures older than this are forgotten
+    pub window_secs: u64,
+    // The first lockout; each further failure doubles it up to max_lockout_secs
+    pub lockout_secs: u64,
+    pub max_lockout_secs: u64,
+}
+
+impl Default for RouteLimit {
+    fn default() -> Self {
+        RouteLimit {
+            requests_per_minute: 60,
+            max_failures: 5,
+            window_secs: 900,
+            lockout_secs: 60,
+            max_lockout_secs: 3600,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn route(&self, route: &str) -> RouteLimit {
+        if let Some(limit) = self.routes.get(route) {
+            return *limit;
+        }
+        match route {
+            "login" => RouteLimit { requests_per_minute: 30, ..Default::default() },
+            // WebDAV clients send their credentials with every request
+            "basic" => RouteLimit { requests_per_minute: 600, ..Default::default() },
+            // As does every range request of a shared media link
+            "signurl" => RouteLimit { requests_per_minute: 1200, max_failures: 20, ..Default::default() },
+            _ => RouteLimit::default(),
+        }
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        if self.trusted_proxies.is_empty() {
+            return ip.is_loopback();
+        }
+        self.trusted_proxies.iter().any(|proxy| {
+            match proxy.parse::<IpNet>() {
+                Ok(net) => net.contains(ip),
+                Err(_) => match proxy.parse::<IpAddr>() {
+                    Ok(addr) => addr == *ip,
+                    Err(_) => {
+                        tracing::error!("Invalid trusted proxy '{}'", proxy);
+                        false
+                    }
+                },
+            }
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use regex::Regex;
+use anyhow::{Context, Result};
+use super::files::{Channel, MediaEntry};
+
+// Include/exclude rules, extensions and date bounds for a channel scan.
+// Patterns are globs matched against the path relative to the channel root
+// ("2024/01/zsv240101-1c.mp4"); prefix a pattern with "re:" to use a regex.
+pub struct ScanFilter {
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+    extensions: Vec<String>,
+    min_date: Option<chrono::NaiveDate>,
+    max_date: Option<chrono::NaiveDate>,
+}
+
+impl ScanFilter {
+    pub fn new(channel: &Channel) -> Result<ScanFilter> {
+        Ok(ScanFilter {
+            include: compile_patterns(&channel.include)?,
+            exclude: compile_patterns(&channel.exclude)?,
+            extensions: channel.extensions(),
+            min_date: channel.min_date,
+            max_date: channel.max_date,
+        })
+    }
+
+    pub fn is_excluded(&self, rel_path: &str) -> bool {
+        self.exclude.iter().any(|re| re.is_match(rel_path))
+    }
+
+    pub fn accepts(&self, entry: &MediaEntry) -> bool {
+        if !self.extensions.is_empty() && !self.extensions.iter().any(|ext| entry.file_name.ends_with(ext.as_str())) {
+            return false;
+        }
+        let rel_path = entry.rel_path();
+        if self.is_excluded(&rel_path) {
+            return false;
+        }
+        if entry.content_type == "folder" {
+            return true;
+        }
+        if !self.include.is_empty() && !self.include.iter().any(|re| re.is_match(&rel_path)) {
+            return false;
+        }
+        let date = entry.pub_date.date();
+        if self.min_date.is_some_and(|min| date < min) || self.max_date.is_some_and(|max| date > max) {
+            return false;
+        }
+        true
+    }
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns.iter().map(|p| {
+        let source = match p.strip_prefix("re:") {
+            Some(re) => re.to_string(),
+            None => glob_to_regex(p),
+        };
+        Regex::new(&source).with_context(|| format!("Invalid scan pattern '{}'", p))
+    }).collect()
+}
+
+// "*" matches within a path segment, "**" across segments, "?" one character.
+// A glob without "/" matches the file name at any depth.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut re = if glob.contains('/') { String::from("^") } else { String::from("(?:^|/)") };
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if i + 1 < chars.len() && chars[i + 1] == '*' => {
+                re.push_str(".*");
+                i += 1;
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    re.push('$');
+    re
+}
@@ -0,0 +1,67 @@
+use std::collections::{BTreeMap, HashMap};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+// Requests by one user within this long of their last count as the same
+// download; players fetch a file in many ranges
+pub const PLAY_GAP_SECS: i64 = 30 * 60;
+// Daily counts are kept this long, for "most popular" windows
+pub const DAILY_DAYS: i64 = 90;
+
+// Download counters of one MediaEntry, by normalized_entry_id, as stored
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntryStats {
+    pub downloads: u64,
+    pub last_accessed: Option<DateTime<Utc>>,
+    // Last access by user (sub)
+    pub users: HashMap<String, DateTime<Utc>>,
+    pub daily: BTreeMap<NaiveDate, u64>,
+}
+
+impl EntryStats {
+    pub fn record(&mut self, user: &str, time: DateTime<Utc>) {
+        let counted = self.users.get(user).is_none_or(|last| (time - *last).num_seconds() >= PLAY_GAP_SECS);
+        if counted {
+            self.downloads += 1;
+            *self.daily.entry(time.date_naive()).or_insert(0) += 1;
+        }
+        self.users.insert(user.to_string(), time);
+        self.last_accessed = self.last_accessed.max(Some(time));
+        let cutoff = (time - Duration::days(DAILY_DAYS)).date_naive();
+        self.daily.retain(|day, _| *day >= cutoff);
+    }
+
+    // Downloads over the last `days` days, today included
+    pub fn since(&self, days: i64) -> u64 {
+        let start = (Utc::now() - Duration::days(days - 1)).date_naive();
+        self.daily.range(start..).map(|(_, count)| count).sum()
+    }
+
+    pub fn counters(&self) -> EntryCounters {
+        EntryCounters {
+            downloads: self.downloads,
+            unique_users: self.users.len() as u64,
+            last_accessed: self.last_accessed,
+            week: self.since(7),
+        }
+    }
+}
+
+// What listings show of EntryStats
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct EntryCounters {
+    pub downloads: u64,
+    pub unique_users: u64,
+    pub last_accessed: Option<DateTime<Utc>>,
+    // Downloads in the last 7 days
+    pub week: u64,
+}
+
+// One download of a catalogued entry, as taken from the audit log
+#[derive(Debug, Clone)]
+pub struct EntryAccess {
+    pub cache_id: String,
+    pub entry_id: String,
+    pub user: String,
+    pub time: DateTime<Utc>,
+}
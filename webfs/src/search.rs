@@ -0,0 +1,215 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, NaiveDate, Utc};
+use lazy_static::lazy_static;
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::Serialize;
+use crate::models::file_desc::FileDesc;
+use crate::models::files::{Channel, MediaEntry};
+use crate::models::formatter::is_chinese;
+
+// Per-field weights for ranking
+const TITLE_WEIGHT: f32 = 3.0;
+const EVENT_DESC_WEIGHT: f32 = 2.0;
+const DESCRIPTION_WEIGHT: f32 = 1.5;
+const LOCATION_WEIGHT: f32 = 1.0;
+// Single CJK characters are indexed too, but rank well below bigrams
+const CJK_UNIGRAM_FACTOR: f32 = 0.3;
+
+const STOP_WORDS: &[&str] = &["a", "an", "and", "at", "by", "for", "in", "of", "on", "or", "the", "to", "with"];
+
+lazy_static! {
+    static ref STEMMER: Stemmer = Stemmer::create(Algorithm::English);
+    // Search index per channel cache_id
+    static ref SEARCH_INDEX: RwLock<HashMap<String, Arc<ChannelIndex>>> = RwLock::new(HashMap::new());
+}
+
+// Lower-cased terms of `text` with a weight factor: stemmed English words,
+// and CJK bigrams plus single characters. A query only uses bigrams, unless
+// the CJK run is a single character.
+pub fn tokenize(text: &str, query: bool) -> Vec<(String, f32)> {
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut cjk: Vec<char> = Vec::new();
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if is_chinese(c) {
+            flush_word(&mut word, &mut terms);
+            cjk.push(c);
+        } else if c.is_alphanumeric() {
+            flush_cjk(&mut cjk, query, &mut terms);
+            word.extend(c.to_lowercase());
+        } else {
+            flush_word(&mut word, &mut terms);
+            flush_cjk(&mut cjk, query, &mut terms);
+        }
+    }
+    terms
+}
+
+fn flush_word(word: &mut String, terms: &mut Vec<(String, f32)>) {
+    if !word.is_empty() && !STOP_WORDS.contains(&word.as_str()) {
+        terms.push((STEMMER.stem(word).to_string(), 1.0));
+    }
+    word.clear();
+}
+
+fn flush_cjk(run: &mut Vec<char>, query: bool, terms: &mut Vec<(String, f32)>) {
+    if run.len() == 1 {
+        terms.push((run[0].to_string(), 1.0));
+    } else if run.len() > 1 {
+        terms.extend(run.windows(2).map(|pair| (pair.iter().collect(), 1.0)));
+        if !query {
+            terms.extend(run.iter().map(|c| (c.to_string(), CJK_UNIGRAM_FACTOR)));
+        }
+    }
+    run.clear();
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub channel: String,
+    pub lang: String,
+    pub title: String,
+    pub description: String,
+    pub event_desc: String,
+    pub event_code: String,
+    pub location: String,
+    pub pub_date: chrono::NaiveDateTime,
+    pub link: String,
+    pub file_name: String,
+    pub score: f32,
+}
+
+pub struct ChannelIndex {
+    // channel_cache timestamp of the channel this index was built from
+    pub updated_at: DateTime<Utc>,
+    docs: Vec<SearchHit>,
+    postings: HashMap<String, Vec<(u32, f32)>>,
+}
+
+impl ChannelIndex {
+    // `descs` are the description records keyed by FileDesc.id; both the
+    // English and Chinese text are indexed whatever the channel language
+    pub fn build(channel: &Channel, updated_at: DateTime<Utc>, descs: &HashMap<String, FileDesc>) -> ChannelIndex {
+        let cache_id = channel.cache_id();
+        let lang = cache_id.split('/').next().unwrap_or_default().to_string();
+        let mut docs = Vec::new();
+        let mut postings: HashMap<String, Vec<(u32, f32)>> = HashMap::new();
+        for entry in channel.entries.iter().filter(|e| e.content_type != "folder") {
+            let doc_id = docs.len() as u32;
+            let mut weights: HashMap<String, f32> = HashMap::new();
+            let mut add = |text: &str, weight: f32| {
+                for (term, factor) in tokenize(text, false) {
+                    *weights.entry(term).or_insert(0.0) += weight * factor;
+                }
+            };
+            add(&entry.title, TITLE_WEIGHT);
+            add(&entry.event_desc, EVENT_DESC_WEIGHT);
+            add(&entry.description, DESCRIPTION_WEIGHT);
+            add(&entry.location, LOCATION_WEIGHT);
+            if let Some(desc) = descs.get(&entry.normalized_event_id("zsv")) {
+                add(&desc.eng_descr, DESCRIPTION_WEIGHT);
+                add(&desc.chi_descr, DESCRIPTION_WEIGHT);
+            }
+            for (term, weight) in weights {
+                postings.entry(term).or_default().push((doc_id, weight));
+            }
+            docs.push(hit(entry, &cache_id, &lang));
+        }
+        ChannelIndex { updated_at, docs, postings }
+    }
+
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    fn doc_freq(&self, term: &str) -> usize {
+        self.postings.get(term).map(|p| p.len()).unwrap_or(0)
+    }
+
+    // Documents containing every term, scored by weight * idf
+    fn matches(&self, terms: &[(String, f32)], filter: &SearchFilter) -> Vec<SearchHit> {
+        let mut scores: HashMap<u32, (usize, f32)> = HashMap::new();
+        for (term, idf) in terms {
+            for (doc_id, weight) in self.postings.get(term).map(|p| p.as_slice()).unwrap_or_default() {
+                let score = scores.entry(*doc_id).or_insert((0, 0.0));
+                score.0 += 1;
+                score.1 += weight * idf;
+            }
+        }
+        scores.into_iter()
+            .filter(|(_, (count, _))| *count == terms.len())
+            .filter_map(|(doc_id, (_, score))| {
+                let doc = &self.docs[doc_id as usize];
+                filter.accepts(doc).then(|| SearchHit { score, ..doc.clone() })
+            })
+            .collect()
+    }
+}
+
+fn hit(entry: &MediaEntry, cache_id: &str, lang: &str) -> SearchHit {
+    SearchHit {
+        channel: cache_id.to_string(),
+        lang: lang.to_string(),
+        title: entry.title.clone(),
+        description: entry.description.clone(),
+        event_desc: entry.event_desc.clone(),
+        event_code: entry.event_code.clone(),
+        location: entry.location.clone(),
+        pub_date: entry.pub_date,
+        link: entry.link.clone(),
+        file_name: entry.file_name.clone(),
+        score: 0.0,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SearchFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    // Lower-cased event codes, empty for any
+    pub event_codes: Vec<String>,
+}
+
+impl SearchFilter {
+    fn accepts(&self, doc: &SearchHit) -> bool {
+        let date = doc.pub_date.date();
+        if self.from.is_some_and(|from| date < from) || self.to.is_some_and(|to| date > to) {
+            return false;
+        }
+        self.event_codes.is_empty() || self.event_codes.contains(&doc.event_code.to_lowercase())
+    }
+}
+
+// Cached index of a channel, if built from the channel as of `updated_at`
+pub fn channel_index(cache_id: &str, updated_at: &DateTime<Utc>) -> Option<Arc<ChannelIndex>> {
+    let index = SEARCH_INDEX.read().unwrap();
+    index.get(cache_id).filter(|i| i.updated_at == *updated_at).cloned()
+}
+
+pub fn set_channel_index(cache_id: &str, index: ChannelIndex) -> Arc<ChannelIndex> {
+    let index = Arc::new(index);
+    SEARCH_INDEX.write().unwrap().insert(cache_id.to_string(), index.clone());
+    index
+}
+
+// Ranked hits across `indexes`, best first, then newest first
+pub fn search(indexes: &[Arc<ChannelIndex>], query: &str, filter: &SearchFilter) -> Vec<SearchHit> {
+    let terms: HashSet<String> = tokenize(query, true).into_iter().map(|(t, _)| t).collect();
+    if terms.is_empty() {
+        return Vec::new();
+    }
+    let total_docs: usize = indexes.iter().map(|i| i.len()).sum();
+    let terms: Vec<(String, f32)> = terms.into_iter().map(|term| {
+        let df: usize = indexes.iter().map(|i| i.doc_freq(&term)).sum();
+        let idf = (1.0 + (total_docs as f32 + 1.0) / (df as f32 + 1.0)).ln();
+        (term, idf)
+    }).collect();
+    let mut hits: Vec<SearchHit> = indexes.iter().flat_map(|i| i.matches(&terms, filter)).collect();
+    hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.pub_date.cmp(&a.pub_date)));
+    hits
+}
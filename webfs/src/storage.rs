@@ -1,17 +1,54 @@
 use anyhow::Result;
-use redb::{Database, TableDefinition};
+use redb::{Database, ReadableTable, TableDefinition};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
 use bincode;
 use chrono::{Utc, DateTime};
+use crate::models::apikey::ApiKey;
+use crate::models::audit::AuditEvent;
 use crate::models::file_desc::FileDesc;
 use crate::models::files::{Channel, MediaEntry};
+use crate::models::probe::{self, MediaInfo};
+use crate::models::stats::{EntryAccess, EntryStats};
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 const CHANNEL_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("channel");
 const FILENAMES_TABLE: TableDefinition<&str, ()> = TableDefinition::new("filenames");
 const FILEDESC_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("filedesc");
+// Probed MediaInfo keyed by "path|size|mtime"
+const MEDIAINFO_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("mediainfo");
+// Parsed MediaEntry records per channel, keyed "{cache_id}\t{rel_path}"
+const CATALOG_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("catalog");
+// When each channel's catalog was last rebuilt from a full directory read
+const CATALOG_SYNC_TABLE: TableDefinition<&str, i64> = TableDefinition::new("catalog_sync");
+// Logged out session and token ids ("sid:..", "jti:..") with the unix time they expire
+const REVOKED_TABLE: TableDefinition<&str, u64> = TableDefinition::new("revoked");
+// Uses of signed URLs with a use limit, by URL id: (uses, unix time the URL expires)
+const SIGNED_USES_TABLE: TableDefinition<&str, (u64, u64)> = TableDefinition::new("signed_uses");
+// Users' API keys by key id
+const API_KEYS_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("api_keys");
+// Audit events keyed by (unix time in microseconds, sequence number); only
+// appended to, and trimmed to the retention period
+const AUDIT_TABLE: TableDefinition<(u64, u64), Vec<u8>> = TableDefinition::new("audit");
+// Download counters per catalogued entry, keyed "{cache_id}\t{normalized_entry_id}"
+const ENTRY_STATS_TABLE: TableDefinition<&str, Vec<u8>> = TableDefinition::new("entry_stats");
+
+static AUDIT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+pub type AuditKey = (u64, u64);
+pub type AuditRange = (Bound<AuditKey>, Bound<AuditKey>);
+// Events read from an AuditRange with their keys, and the range left to read
+pub type AuditPage = (Vec<(AuditKey, AuditEvent)>, Option<AuditRange>);
+
+// The audit table's keys for events in [from, to)
+pub fn audit_range(from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> AuditRange {
+    let from = from.map(|t| t.timestamp_micros().max(0) as u64).unwrap_or(0);
+    let to = to.map(|t| t.timestamp_micros().max(0) as u64).unwrap_or(u64::MAX);
+    (Bound::Included((from, 0)), Bound::Excluded((to, 0)))
+}
 
 pub struct Storage {
     db: Database,
@@ -50,6 +87,38 @@ impl Storage {
                 tracing::error!("Failed to open filedesc table: {}", e);
                 e
             })?;
+            txn.open_table(MEDIAINFO_TABLE).map_err(|e| {
+                tracing::error!("Failed to open mediainfo table: {}", e);
+                e
+            })?;
+            txn.open_table(CATALOG_TABLE).map_err(|e| {
+                tracing::error!("Failed to open catalog table: {}", e);
+                e
+            })?;
+            txn.open_table(CATALOG_SYNC_TABLE).map_err(|e| {
+                tracing::error!("Failed to open catalog_sync table: {}", e);
+                e
+            })?;
+            txn.open_table(REVOKED_TABLE).map_err(|e| {
+                tracing::error!("Failed to open revoked table: {}", e);
+                e
+            })?;
+            txn.open_table(SIGNED_USES_TABLE).map_err(|e| {
+                tracing::error!("Failed to open signed_uses table: {}", e);
+                e
+            })?;
+            txn.open_table(API_KEYS_TABLE).map_err(|e| {
+                tracing::error!("Failed to open api_keys table: {}", e);
+                e
+            })?;
+            txn.open_table(AUDIT_TABLE).map_err(|e| {
+                tracing::error!("Failed to open audit table: {}", e);
+                e
+            })?;
+            txn.open_table(ENTRY_STATS_TABLE).map_err(|e| {
+                tracing::error!("Failed to open entry_stats table: {}", e);
+                e
+            })?;
             txn.commit().map_err(|e| {
                 tracing::error!("Failed to commit transaction: {}", e);
                 e
@@ -143,7 +212,126 @@ impl Storage {
         Ok(entities)
     }
 
-    pub fn channel_descriptions(&self, ch: Channel, cache: Arc<Mutex<HashMap<String, (Channel, chrono::DateTime<chrono::Utc>)>>>) -> Result<(Channel, bool)> {
+    // Catalogued entries of a channel, None until the channel has been synced once
+    pub fn catalog_entries(&self, cache_id: &str) -> Result<Option<Vec<MediaEntry>>> {
+        let txn = self.db.begin_read()?;
+        if txn.open_table(CATALOG_SYNC_TABLE)?.get(cache_id)?.is_none() {
+            return Ok(None);
+        }
+        let table = txn.open_table(CATALOG_TABLE)?;
+        let (start, end) = catalog_range(cache_id);
+        let mut entries = Vec::new();
+        for item in table.range(start.as_str()..end.as_str())? {
+            let (_, v) = item?;
+            match bincode::deserialize::<MediaEntry>(v.value().as_slice()) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    // Written by an older MediaEntry layout; rebuild from the directory
+                    tracing::warn!("Stale catalog for {}: {}", cache_id, e);
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(entries))
+    }
+
+    pub fn catalog_entry(&self, cache_id: &str, rel_path: &str) -> Result<Option<MediaEntry>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(CATALOG_TABLE)?;
+        match table.get(catalog_key(cache_id, rel_path).as_str())? {
+            Some(v) => Ok(bincode::deserialize(v.value().as_slice()).ok()),
+            None => Ok(None),
+        }
+    }
+
+    // Replace a channel's catalog with a full directory read
+    pub fn replace_catalog(&self, cache_id: &str, entries: &[MediaEntry]) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(CATALOG_TABLE)?;
+            let (start, end) = catalog_range(cache_id);
+            table.retain_in(start.as_str()..end.as_str(), |_, _| false)?;
+            for entry in entries {
+                table.insert(catalog_key(cache_id, &entry.rel_path()).as_str(), bincode::serialize(entry)?)?;
+            }
+            txn.open_table(CATALOG_SYNC_TABLE)?.insert(cache_id, Utc::now().timestamp())?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Apply changed files to a channel's catalog; removing a path also
+    // removes everything catalogued under it
+    pub fn update_catalog(&self, cache_id: &str, upserts: &[MediaEntry], removed: &[String]) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(CATALOG_TABLE)?;
+            for rel_path in removed {
+                table.remove(catalog_key(cache_id, rel_path).as_str())?;
+                let prefix = catalog_key(cache_id, &format!("{}/", rel_path));
+                let end = format!("{}\u{10ffff}", prefix);
+                table.retain_in(prefix.as_str()..end.as_str(), |_, _| false)?;
+            }
+            for entry in upserts {
+                table.insert(catalog_key(cache_id, &entry.rel_path()).as_str(), bincode::serialize(entry)?)?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Channel entries from the catalog, reading the directory on first use
+    pub fn channel_entries(&self, channel: &Channel) -> Result<Vec<MediaEntry>> {
+        let cache_id = channel.cache_id();
+        if let Some(entries) = self.catalog_entries(&cache_id)? {
+            return Ok(entries);
+        }
+        let entries = Channel::read_dir(channel)?;
+        self.replace_catalog(&cache_id, &entries)?;
+        Ok(entries)
+    }
+
+    // Fill entry.media from the cache. Returns the files that are new or
+    // changed as (cache key, path), for probe::probe_files to read without
+    // holding the storage lock.
+    pub fn fill_media_info(&self, channel: &mut Channel) -> Result<Vec<(String, PathBuf)>> {
+        let root = Path::new(&channel.file_path).to_path_buf();
+        let mut unprobed = Vec::new();
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(MEDIAINFO_TABLE)?;
+        for entry in channel.entries.iter_mut() {
+            if entry.content_type == "folder" || !probe::is_probeable(&entry.file_name) {
+                continue;
+            }
+            let path = root.join(entry.rel_path());
+            let mtime = entry.modified.duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let key = format!("{}|{}|{}", path.display(), entry.size, mtime);
+            match table.get(key.as_str())? {
+                Some(v) => entry.media = bincode::deserialize(v.value().as_slice())?,
+                None => unprobed.push((key, path)),
+            }
+        }
+        Ok(unprobed)
+    }
+
+    pub fn store_media_info(&self, probed: &[(String, MediaInfo)]) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(MEDIAINFO_TABLE)?;
+            for (key, info) in probed {
+                table.insert(key.as_str(), bincode::serialize(info)?)?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Files not probed yet are left without media info; the file monitor
+    // probes them in the background
+    pub fn channel_descriptions(&self, mut ch: Channel, cache: Arc<Mutex<HashMap<String, (Channel, chrono::DateTime<chrono::Utc>)>>>) -> Result<(Channel, bool)> {
+        if let Err(e) = self.fill_media_info(&mut ch) {
+            tracing::error!("Error reading media info for {}: {}", ch.cache_id(), e);
+        }
         let cached_ch_option = {
             let _cache: std::sync::MutexGuard<'_, HashMap<String, (Channel, chrono::DateTime<Utc>)>> = cache.lock().unwrap();
             _cache.get(&ch.cache_id()).cloned()
@@ -155,8 +343,8 @@ impl Storage {
             Ok(filled_ch) => {
                 // Check if channel has changed
                 let changed = if let Some((ref cached_ch, _)) = cached_ch_option {
-                    let current_info: Vec<_> = filled_ch.entries.iter().map(|e| (&e.file_name, &e.description, &e.pub_date)).collect();
-                    let cached_info: Vec<_> = cached_ch.entries.iter().map(|e| (&e.file_name, &e.description, &e.pub_date)).collect();
+                    let current_info: Vec<_> = filled_ch.entries.iter().map(|e| (&e.file_name, &e.description, &e.pub_date, &e.media)).collect();
+                    let cached_info: Vec<_> = cached_ch.entries.iter().map(|e| (&e.file_name, &e.description, &e.pub_date, &e.media)).collect();
                     current_info != cached_info
                 } else {
                     true
@@ -215,4 +403,185 @@ impl Storage {
         channel.set_entries(entries);
         Ok(channel)
     }
+
+    pub fn revoke(&self, id: &str, expires: u64) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        txn.open_table(REVOKED_TABLE)?.insert(id, expires)?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Revoked ids still in force; expired ones are dropped
+    pub fn revoked(&self) -> Result<HashMap<String, u64>> {
+        let now = Utc::now().timestamp() as u64;
+        let txn = self.db.begin_write()?;
+        let mut revoked = HashMap::new();
+        {
+            let mut table = txn.open_table(REVOKED_TABLE)?;
+            table.retain(|_, expires| expires > now)?;
+            for item in table.iter()? {
+                let (k, v) = item?;
+                revoked.insert(k.value().to_string(), v.value());
+            }
+        }
+        txn.commit()?;
+        Ok(revoked)
+    }
+
+    // Count one use of the signed URL `id`; returns the uses so far
+    pub fn use_signed_url(&self, id: &str, expires: u64) -> Result<u64> {
+        let txn = self.db.begin_write()?;
+        let uses = {
+            let mut table = txn.open_table(SIGNED_USES_TABLE)?;
+            let uses = table.get(id)?.map(|v| v.value().0).unwrap_or(0) + 1;
+            if uses == 1 {
+                // Expired URLs can't be used anyway
+                let now = Utc::now().timestamp() as u64;
+                table.retain(|_, (_, expires)| expires > now)?;
+            }
+            table.insert(id, (uses, expires))?;
+            uses
+        };
+        txn.commit()?;
+        Ok(uses)
+    }
+
+    pub fn insert_api_key(&self, key: &ApiKey) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(API_KEYS_TABLE)?;
+            let serialized = bincode::serialize(key)?;
+            table.insert(key.id.as_str(), serialized)?;
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    pub fn get_api_key(&self, id: &str) -> Result<Option<ApiKey>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(API_KEYS_TABLE)?;
+        match table.get(id)? {
+            Some(value) => Ok(Some(bincode::deserialize(&value.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn api_keys_of(&self, owner: &str) -> Result<Vec<ApiKey>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(API_KEYS_TABLE)?;
+        let mut keys = Vec::new();
+        for item in table.iter()? {
+            let (_, value) = item?;
+            let key: ApiKey = bincode::deserialize(&value.value())?;
+            if key.owner == owner {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    // Returns whether there was such a key
+    pub fn delete_api_key(&self, id: &str) -> Result<bool> {
+        let txn = self.db.begin_write()?;
+        let removed = txn.open_table(API_KEYS_TABLE)?.remove(id)?.is_some();
+        txn.commit()?;
+        Ok(removed)
+    }
+
+    pub fn append_audit(&self, events: &[AuditEvent]) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(AUDIT_TABLE)?;
+            for event in events {
+                let time = event.time.timestamp_micros().max(0) as u64;
+                table.insert((time, AUDIT_SEQ.fetch_add(1, Ordering::Relaxed)), bincode::serialize(event)?)?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    // Up to `chunk` events of `range`, newest or oldest first, and the part
+    // of the range still to read (None once it is all read). Only the events
+    // passing `filter` are returned, with their keys.
+    pub fn audit_page(&self, range: AuditRange, newest_first: bool, chunk: usize, filter: impl Fn(&AuditEvent) -> bool) -> Result<AuditPage> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(AUDIT_TABLE)?;
+        let rows = table.range::<AuditKey>(range)?;
+        let rows: Box<dyn Iterator<Item = _>> = if newest_first { Box::new(rows.rev()) } else { Box::new(rows) };
+        let mut events = Vec::new();
+        let mut last = None;
+        for item in rows.take(chunk) {
+            let (key, value) = item?;
+            let key = key.value();
+            let event: AuditEvent = bincode::deserialize(&value.value())?;
+            if filter(&event) {
+                events.push((key, event));
+            }
+            last = Some(key);
+        }
+        let rest = match last {
+            Some(key) if newest_first => Some((range.0, Bound::Excluded(key))),
+            Some(key) => Some((Bound::Excluded(key), range.1)),
+            None => None,
+        };
+        Ok((events, rest))
+    }
+
+    // Count downloads into the entries' stats
+    pub fn record_downloads(&self, accesses: &[EntryAccess]) -> Result<()> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(ENTRY_STATS_TABLE)?;
+            for access in accesses {
+                let key = catalog_key(&access.cache_id, &access.entry_id);
+                let mut stats: EntryStats = match table.get(key.as_str())? {
+                    Some(v) => bincode::deserialize(v.value().as_slice())?,
+                    None => EntryStats::default(),
+                };
+                stats.record(&access.user, access.time);
+                table.insert(key.as_str(), bincode::serialize(&stats)?)?;
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    // A channel's entry stats by normalized_entry_id
+    pub fn entry_stats(&self, cache_id: &str) -> Result<HashMap<String, EntryStats>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(ENTRY_STATS_TABLE)?;
+        let (start, end) = catalog_range(cache_id);
+        let mut stats = HashMap::new();
+        for item in table.range(start.as_str()..end.as_str())? {
+            let (k, v) = item?;
+            let entry_id = k.value().split_once('\t').map(|(_, id)| id.to_string()).unwrap_or_default();
+            stats.insert(entry_id, bincode::deserialize(v.value().as_slice())?);
+        }
+        Ok(stats)
+    }
+
+    // Drop events from before `cutoff`; returns how many
+    pub fn purge_audit(&self, cutoff: DateTime<Utc>) -> Result<usize> {
+        let cutoff = cutoff.timestamp_micros().max(0) as u64;
+        let txn = self.db.begin_write()?;
+        let mut purged = 0;
+        {
+            let mut table = txn.open_table(AUDIT_TABLE)?;
+            table.retain_in((0, 0)..(cutoff, 0), |_, _| {
+                purged += 1;
+                false
+            })?;
+        }
+        txn.commit()?;
+        Ok(purged)
+    }
+}
+
+fn catalog_key(cache_id: &str, rel_path: &str) -> String {
+    format!("{}\t{}", cache_id, rel_path)
+}
+
+fn catalog_range(cache_id: &str) -> (String, String) {
+    (format!("{}\t", cache_id), format!("{}\t\u{10ffff}", cache_id))
 }
@@ -0,0 +1,219 @@
+use axum::{
+    Json, body::Body,
+    extract::{Path, Query, State},
+    http::{StatusCode, header::{self, HeaderMap, HeaderValue}},
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use crate::auth::{acl, apikey, keycloak};
+use crate::models::auth::{AuthInfo, Claims, SignUrlRequest};
+use crate::models::feed::FeedFormat;
+use crate::models::files::Channel;
+
+// Enclosures of private feeds stay good this long, and are signed afresh
+// every FEED_RENEW_SECS so an app polling the feed never holds stale ones
+pub const FEED_ENCLOSURE_SECS: u64 = 3600 * 24 * 7;
+const FEED_RENEW_SECS: i64 = 3600 * 24;
+const MAX_FEED_DAYS: i64 = 3650;
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    pub days: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+// GET /feeds/v1/{lang}/{channel}.{rss|atom|json}
+pub async fn feed_handler(
+    State(state): State<crate::AppState>,
+    Path((lang, feed)): Path<(String, String)>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let (name, format) = feed_format(&feed)?;
+    let channel = state.config.channels.get(&lang)
+        .and_then(|m| m.get(name))
+        .cloned()
+        .ok_or((StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Channel not found"}))))?;
+    // Channels the acl keeps from anonymous readers only have private feeds
+    let anonymous = AuthInfo::new(Claims::anonymous(), None);
+    if !channel.public && !acl::permits(&state, &anonymous, &format!("/fs/v1/{}/{}/", lang, name), None) {
+        return Err((StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Channel not found"}))));
+    }
+
+    let (mut channel, updated_at) = cached_channel(&state, channel)?;
+
+    let (days, start_date) = feed_days(&state, &query)?;
+    if let Some(limit) = query.limit {
+        channel.entries.truncate(limit);
+    }
+
+    let etag = feed_etag(&channel.cache_id(), format, days, query.limit, &updated_at);
+    let last_modified = updated_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    if is_not_modified(&headers, &etag, &updated_at) {
+        return Ok(feed_response(StatusCode::NOT_MODIFIED, Body::empty(), format, &etag, &last_modified, "public, max-age=60"));
+    }
+
+    let body = render(&mut channel, format, start_date)?;
+    Ok(feed_response(StatusCode::OK, Body::from(body), format, &etag, &last_modified, "public, max-age=60"))
+}
+
+// GET /feeds/v1/private/{token}/{channel}.{rss|atom|json}: a user's feed of a
+// channel, its enclosures signed with the user's access
+pub async fn private_feed_handler(
+    State(state): State<crate::AppState>,
+    Path((token, feed)): Path<(String, String)>,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let (name, format) = feed_format(&feed)?;
+    let key = apikey::lookup(&state, &token)?;
+    let not_found = || (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Channel not found"})));
+    let (lang, _) = key.feed.as_deref()
+        .and_then(|f| f.split_once('/'))
+        .filter(|(_, n)| *n == name)
+        .ok_or_else(not_found)?;
+    let channel = state.config.channels.get(lang)
+        .and_then(|m| m.get(name))
+        .cloned()
+        .ok_or_else(not_found)?;
+    // The owner may have lost access since
+    let mut auth = AuthInfo::new(key.claims(), None);
+    auth.scope = Some(key.scope.clone());
+    acl::authorize(&state, &auth, &format!("/fs/v1/{}/{}/", lang, name), None)?;
+
+    let (mut channel, updated_at) = cached_channel(&state, channel)?;
+
+    let (days, start_date) = feed_days(&state, &query)?;
+    if let Some(limit) = query.limit {
+        channel.entries.truncate(limit);
+    }
+
+    let now = Utc::now().timestamp();
+    let renewed = DateTime::from_timestamp(now - now % FEED_RENEW_SECS, 0).unwrap_or(updated_at);
+    let updated_at = updated_at.max(renewed);
+    let etag = feed_etag(&format!("{}|{}", channel.cache_id(), key.id), format, days, query.limit, &updated_at);
+    let last_modified = updated_at.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    if is_not_modified(&headers, &etag, &updated_at) {
+        return Ok(feed_response(StatusCode::NOT_MODIFIED, Body::empty(), format, &etag, &last_modified, "private, max-age=60"));
+    }
+
+    // Only the entries the feed shows get signed
+    channel.entries = channel.feed_entries(Some(start_date));
+    let urls: Vec<String> = channel.entries.iter().map(|entry| entry.enclosure_url(&channel)).collect();
+    {
+        let mut signing_keys = keycloak::SIGNING_KEYS.write().await;
+        for (entry, url) in channel.entries.iter_mut().zip(urls) {
+            let mut request = SignUrlRequest::new("GET", &url);
+            request.sub = key.username.clone();
+            request.sid = Some(apikey::feed_session(&key));
+            request.fs_id = key.default_webdavfs.clone().unwrap_or_default();
+            request.read_only = true;
+            request.expires_in = Some(FEED_ENCLOSURE_SECS);
+            let signed = signing_keys.generate_signed_url(&request).map_err(|e| {
+                tracing::error!("Failed to sign enclosure {}: {}", request.url, e);
+                (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to render feed"})))
+            })?;
+            entry.enclosure = Some(signed.url);
+        }
+    }
+
+    let body = render(&mut channel, format, start_date)?;
+    Ok(feed_response(StatusCode::OK, Body::from(body), format, &etag, &last_modified, "private, max-age=60"))
+}
+
+// ?days= as a window of 1 to MAX_FEED_DAYS days, and the date it starts on
+fn feed_days(state: &crate::AppState, query: &FeedQuery) -> Result<(i64, chrono::NaiveDate), (StatusCode, Json<serde_json::Value>)> {
+    let days = query.days.unwrap_or(if state.rss_days > 0 { state.rss_days as i64 } else { 7 });
+    let days = days.unsigned_abs().clamp(1, MAX_FEED_DAYS as u64) as i64;
+    let start_date = chrono::TimeDelta::try_days(days)
+        .and_then(|window| Utc::now().date_naive().checked_sub_signed(window))
+        .ok_or((StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "Invalid days"}))))?;
+    Ok((days, start_date))
+}
+
+// "name.ext" into the name and its format
+fn feed_format(feed: &str) -> Result<(&str, FeedFormat), (StatusCode, Json<serde_json::Value>)> {
+    let (name, ext) = feed.rsplit_once('.')
+        .ok_or((StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "Feed format missing"}))))?;
+    let format = FeedFormat::parse(ext)
+        .map_err(|e| (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": e.to_string()}))))?;
+    Ok((name, format))
+}
+
+fn render(channel: &mut Channel, format: FeedFormat, start_date: chrono::NaiveDate) -> Result<Vec<u8>, (StatusCode, Json<serde_json::Value>)> {
+    let mut body = Vec::new();
+    format.write(channel, &mut body, Some(start_date)).map_err(|e| {
+        tracing::error!("Error rendering {} feed for {}: {}", format.extension(), channel.cache_id(), e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to render feed"})))
+    })?;
+    Ok(body)
+}
+
+// The description-filled channel from channel_cache, filled from the catalog on a miss.
+// The timestamp is when the cached channel last changed.
+pub fn cached_channel(state: &crate::AppState, channel: Channel) -> Result<(Channel, DateTime<Utc>), (StatusCode, Json<serde_json::Value>)> {
+    let cache_id = channel.cache_id();
+    {
+        let cache = state.channel_cache.lock().unwrap();
+        if let Some((cached_channel, timestamp)) = cache.get(&cache_id) {
+            return Ok((cached_channel.clone(), *timestamp));
+        }
+    }
+    let storage = state.storage.lock().unwrap();
+    let entries = storage.channel_entries(&channel).map_err(|e| {
+        tracing::error!("Error reading entries for {}: {}", cache_id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to read directory"})))
+    })?;
+    let mut channel = channel;
+    channel.set_entries(entries);
+    match storage.channel_descriptions(channel, state.channel_cache.clone()) {
+        Ok((ch, _changed)) => {
+            let cache = state.channel_cache.lock().unwrap();
+            let timestamp = cache.get(&cache_id).map(|(_, t)| *t).unwrap_or_else(Utc::now);
+            Ok((ch, timestamp))
+        }
+        Err(e) => {
+            tracing::error!("Error filling descriptions for {}: {}", cache_id, e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))))
+        }
+    }
+}
+
+fn feed_etag(cache_id: &str, format: FeedFormat, days: i64, limit: Option<usize>, updated_at: &DateTime<Utc>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}|{}|{}|{:?}|{}", cache_id, format.extension(), days, limit, updated_at.timestamp_millis()).as_bytes());
+    let hash = format!("{:x}", hasher.finalize());
+    format!("\"{}\"", &hash[..32])
+}
+
+fn is_not_modified(headers: &HeaderMap, etag: &str, updated_at: &DateTime<Utc>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|h| h.to_str().ok()) {
+        return if_none_match.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        });
+    }
+    if let Some(since) = headers.get(header::IF_MODIFIED_SINCE).and_then(|h| h.to_str().ok()) {
+        if let Ok(since) = DateTime::parse_from_rfc2822(since) {
+            return updated_at.timestamp() <= since.timestamp();
+        }
+    }
+    false
+}
+
+fn feed_response(status: StatusCode, body: Body, format: FeedFormat, etag: &str, last_modified: &str, cache_control: &'static str) -> Response {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(format.content_type()));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(cache_control));
+    if let Ok(v) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, v);
+    }
+    if let Ok(v) = HeaderValue::from_str(last_modified) {
+        headers.insert(header::LAST_MODIFIED, v);
+    }
+    response
+}
@@ -3,15 +3,16 @@ use chrono::{NaiveDate, Utc};
 use regex::Regex;
 use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tokio::time;
 use tokio::sync::mpsc;
 use tracing;
 use lazy_static::lazy_static;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 
 use docx_rs::{DocumentChild, TableCell};
-use crate::models::{file_desc::FileDesc, files::{Config, Channel}};
+use crate::models::{file_desc::FileDesc, files::{Config, Channel, MediaEntry}, podcast, probe};
 use crate::storage::Storage;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
@@ -23,68 +24,214 @@ pub struct MonitorConfig{
     pub rss_days: i32,
     pub rss_output_path: String,
     pub video_list_path: String,    
+    // Quiet period after the last file system event before acting on a change
+    pub debounce_ms: u64,
+    // Full rescan of every watched directory, as a safety net for missed events
+    pub rescan_secs: u64,
 }
 
 pub async fn start_file_monitor(config: &MonitorConfig, storage: Arc<Mutex<Storage>>, cache: Arc<Mutex<HashMap<String, (Channel, chrono::DateTime<chrono::Utc>)>>>) -> Result<(), Box<dyn std::error::Error>> {
     let pattern = config.video_descr_file_pattern.as_str();
     let regex = Regex::new(pattern)?;
+    let debounce = Duration::from_millis(config.debounce_ms.max(100));
+    let rescan = Duration::from_secs(config.rescan_secs.max(60));
+
+    let rss_channels: Vec<(String, Channel)> = config.config.channels.iter().flat_map(|(l,m)| m.iter().map(|(k,v)| (format!("{}/{}", *l, k), v.clone()))).collect();
+    let (refresh_tx, mut refresh_rx) = mpsc::channel::<Refresh>(100);
 
     if !config.video_list_path.is_empty() {
         let scan_path = config.video_list_path.clone();
         let storage_clone = storage.clone();
+        let refresh_tx = refresh_tx.clone();
+        let all_channels = rss_channels.clone();
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel::<PathBuf>();
+        let watcher = watch_paths(&[(PathBuf::from(&scan_path), RecursiveMode::NonRecursive)], event_tx);
         tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(5)); // Poll every 5 seconds
+            let _watcher = watcher;
+            let mut interval = time::interval(rescan);
             loop {
-                interval.tick().await;
-                tracing::info!("Scanning files... {}", scan_path);
-                if let Err(e) = scan_and_store(&storage_clone, scan_path.as_str(), &regex).await {
-                    tracing::error!("Error scanning files: {}", e);
+                tokio::select! {
+                    _ = interval.tick() => {
+                        tracing::info!("Scanning files... {}", scan_path);
+                    }
+                    paths = next_changes(&mut event_rx, debounce) => {
+                        let Some(paths) = paths else { break };
+                        if !paths.iter().any(|p| p.file_name().is_some_and(|n| regex.is_match(&n.to_string_lossy()))) {
+                            continue;
+                        }
+                        tracing::info!("Description files changed in {}", scan_path);
+                    }
+                }
+                match scan_and_store(&storage_clone, scan_path.as_str(), &regex).await {
+                    // New descriptions may apply to any channel; the catalog is unchanged
+                    Ok(count) if count > 0 => {
+                        let _ = refresh_tx.send((all_channels.clone(), Some(HashSet::new()))).await;
+                    }
+                    Ok(_) => {}
+                    Err(e) => tracing::error!("Error scanning files: {}", e),
                 }
             }
         });
     }else{
         tracing::warn!("File Description List Scan Skipped - WATCH_PATH not set");
     }
-    let mut rss_days = config.rss_days;
-    if rss_days >= 0{
-        if rss_days == 0{
-            rss_days = 7;
-        }
-        let start_date = Utc::now().date_naive() - chrono::Duration::days(rss_days as i64);
-        let rss_channels: Vec<(String, Channel)> = config.config.channels.iter().flat_map(|(l,m)| m.iter().map(|(k,v)| (format!("{}/{}", *l, k), v.clone()))).collect();
-        let (tx1, rx1) = mpsc::channel::<(String, Channel)>(100);
+    // The catalog behind listings and feeds is kept current whether or not
+    // RSS files are written
+    {
+        // Watch channel directories; only channels whose directory changed are refreshed
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel::<PathBuf>();
+        let watcher = watch_paths(&channel_watch_paths(&rss_channels), event_tx);
+        let watched_channels = rss_channels.clone();
+        let changes_tx = refresh_tx.clone();
         tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(5));
+            let _watcher = watcher;
+            while let Some(paths) = next_changes(&mut event_rx, debounce).await {
+                let changed: Vec<(String, Channel)> = watched_channels.iter()
+                    .filter(|(_, ch)| paths.iter().any(|p| channel_contains(ch, p)))
+                    .cloned()
+                    .collect();
+                if changed.is_empty() {
+                    continue;
+                }
+                tracing::info!("Directory changes for {} channels", changed.len());
+                if changes_tx.send((changed, Some(paths))).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Low frequency full rescan, the first tick fills every channel at startup
+        let rescan_tx = refresh_tx.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(rescan);
             loop {
                 interval.tick().await;
-                if let Err(e) = fill_and_queue_channels(&rss_channels, &tx1).await {
+                if rescan_tx.send((rss_channels.clone(), None)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (tx1, rx1) = mpsc::channel::<(String, Channel)>(100);
+        let catalog = storage.clone();
+        tokio::spawn(async move {
+            while let Some((channels, changes)) = refresh_rx.recv().await {
+                if let Err(e) = fill_and_queue_channels(&channels, changes.as_ref(), &catalog, &tx1).await {
                     tracing::error!("Error filling channels: {}", e);
                 }
             }
         });
-        let (tx2, rx2) = mpsc::channel::<(String, Channel)>(100);
+        let mut rss_days = config.rss_days;
+        let tx2 = if rss_days >= 0 {
+            if rss_days == 0 {
+                rss_days = 7;
+            }
+            let start_date = Utc::now().date_naive() - chrono::Duration::days(rss_days as i64);
+            let (tx2, rx2) = mpsc::channel::<(String, Channel)>(100);
+            tokio::spawn(async move {
+                rss_writer(rx2, start_date).await;
+            });
+            Some(tx2)
+        } else {
+            tracing::warn!("RSS Refresh Skipped - RSS_DAYS not set");
+            None
+        };
         let cache_clone = cache.clone();
         let storage_clone = storage.clone();
         tokio::spawn(async move {
             fill_descriptions(rx1, storage_clone, cache_clone, tx2).await;
         });
-        tokio::spawn(async move {
-            rss_writer(rx2, start_date).await;
-        });
-    }else{
-        tracing::warn!("RSS Refresh Skipped - RSS_DAYS not set");
     }
     Ok(())
 }
 
-async fn fill_and_queue_channels(channels_to_process: &[(String, Channel)], tx: &mpsc::Sender<(String, Channel)>) -> Result<()> {
+// Channels to refresh, with the paths that changed under them. None rebuilds
+// their catalog from a full directory read.
+type Refresh = (Vec<(String, Channel)>, Option<HashSet<PathBuf>>);
+
+// Start a watcher forwarding the paths of content changes to `tx`. The watcher
+// stops when dropped, so callers keep it alive. None if watching is unavailable;
+// the periodic rescan still picks up changes then.
+fn watch_paths(paths: &[(PathBuf, RecursiveMode)], tx: mpsc::UnboundedSender<PathBuf>) -> Option<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        match res {
+            Ok(event) => {
+                // Reads (serving, probing) must not trigger a refresh
+                if matches!(event.kind, EventKind::Access(_)) {
+                    return;
+                }
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+            Err(e) => tracing::error!("File watch error: {}", e),
+        }
+    }).map_err(|e| tracing::error!("Failed to create file watcher: {}", e)).ok()?;
+    for (path, mode) in paths {
+        match watcher.watch(path, *mode) {
+            Ok(_) => tracing::info!("Watching {} ({:?})", path.display(), mode),
+            Err(e) => tracing::error!("Failed to watch {}: {}", path.display(), e),
+        }
+    }
+    Some(watcher)
+}
+
+// Wait for the next change, then keep collecting until `debounce` passes without
+// another event (or ten times that, for directories that never settle)
+async fn next_changes(rx: &mut mpsc::UnboundedReceiver<PathBuf>, debounce: Duration) -> Option<HashSet<PathBuf>> {
+    let mut paths = HashSet::from([rx.recv().await?]);
+    let deadline = time::Instant::now() + debounce * 10;
+    loop {
+        let wait = debounce.min(deadline.saturating_duration_since(time::Instant::now()));
+        match time::timeout(wait, rx.recv()).await {
+            Ok(Some(path)) => {
+                paths.insert(path);
+            }
+            Ok(None) | Err(_) => return Some(paths),
+        }
+    }
+}
+
+// Channel directories to watch, recursively when any channel scans sub folders
+fn channel_watch_paths(channels: &[(String, Channel)]) -> Vec<(PathBuf, RecursiveMode)> {
+    let mut dirs: HashMap<PathBuf, RecursiveMode> = HashMap::new();
+    for (_, ch) in channels {
+        if ch.file_path.is_empty() {
+            continue;
+        }
+        let mode = if ch.max_depth > 0 { RecursiveMode::Recursive } else { RecursiveMode::NonRecursive };
+        let current = dirs.entry(PathBuf::from(&ch.file_path)).or_insert(mode);
+        if mode == RecursiveMode::Recursive {
+            *current = mode;
+        }
+    }
+    dirs.into_iter().collect()
+}
+
+fn channel_contains(channel: &Channel, path: &Path) -> bool {
+    let dir = Path::new(&channel.file_path);
+    if channel.max_depth > 0 {
+        path.starts_with(dir)
+    } else {
+        path == dir || path.parent() == Some(dir)
+    }
+}
+
+async fn fill_and_queue_channels(channels_to_process: &[(String, Channel)], changes: Option<&HashSet<PathBuf>>, storage: &Arc<Mutex<Storage>>, tx: &mpsc::Sender<(String, Channel)>) -> Result<()> {
     tracing::info!("Processing {} channels", channels_to_process.len());
     for (channel_name, ch) in channels_to_process {
         tracing::info!("---------------------------------------------------------");
         tracing::info!("Filling channel {} {}", ch.cache_id(), &ch.file_path);
 
-        // Read and filter files from the directory
-        match Channel::read_dir(&ch) {
+        // Bring the catalog up to date and read the channel entries from it
+        let entries = match changes {
+            Some(paths) => sync_catalog_changes(ch, paths, storage),
+            None => Channel::read_dir(ch).map_err(anyhow::Error::from).and_then(|entries| {
+                storage.lock().unwrap().replace_catalog(&ch.cache_id(), &entries)?;
+                Ok(entries)
+            }),
+        };
+        match entries {
             Ok(entries) => {
                 if entries.is_empty() {
                     tracing::warn!("No entries found for channel {}", channel_name);
@@ -106,15 +253,86 @@ async fn fill_and_queue_channels(channels_to_process: &[(String, Channel)], tx:
     Ok(())
 }
 
-async fn fill_descriptions(mut rx: mpsc::Receiver<(String, Channel)>, storage: Arc<Mutex<Storage>>, cache: Arc<Mutex<HashMap<String, (Channel, chrono::DateTime<chrono::Utc>)>>>, tx: mpsc::Sender<(String, Channel)>) {
+// Re-parse only the changed files of a channel. A new or moved-in folder falls
+// back to a full read, since its contents produced no events of their own.
+fn sync_catalog_changes(ch: &Channel, paths: &HashSet<PathBuf>, storage: &Arc<Mutex<Storage>>) -> Result<Vec<MediaEntry>> {
+    let cache_id = ch.cache_id();
+    let mut upserts = Vec::new();
+    let mut removed = Vec::new();
+    let owners = sidecar_owners(paths);
+    for path in paths.iter().chain(owners.iter()) {
+        let Ok(rel) = path.strip_prefix(&ch.file_path) else { continue };
+        if rel.as_os_str().is_empty() {
+            continue;
+        }
+        if path.is_dir() && ch.source != "explorer" {
+            let entries = Channel::read_dir(ch)?;
+            storage.lock().unwrap().replace_catalog(&cache_id, &entries)?;
+            return Ok(entries);
+        }
+        let Some(sub_path) = ch.scan_sub_path(path) else { continue };
+        match MediaEntry::from_path(path, ch, &sub_path) {
+            Ok(entry) => upserts.push(entry),
+            Err(_) => removed.push(rel.to_string_lossy().to_string()),
+        }
+    }
+    let storage = storage.lock().unwrap();
+    storage.update_catalog(&cache_id, &upserts, &removed)?;
+    match storage.catalog_entries(&cache_id)? {
+        Some(entries) => Ok(entries),
+        None => {
+            let entries = Channel::read_dir(ch)?;
+            storage.replace_catalog(&cache_id, &entries)?;
+            Ok(entries)
+        }
+    }
+}
+
+// Media files whose transcripts, chapters or artwork changed, so their
+// catalogued sidecars are refreshed
+fn sidecar_owners(paths: &HashSet<PathBuf>) -> HashSet<PathBuf> {
+    let mut owners = HashSet::new();
+    for path in paths {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let (Some(stem), Some(dir)) = (podcast::sidecar_stem(&file_name), path.parent()) else { continue };
+        let Ok(siblings) = fs::read_dir(dir) else { continue };
+        for sibling in siblings.flatten() {
+            let name = sibling.file_name().to_string_lossy().to_string();
+            let is_owner = podcast::sidecar_stem(&name).is_none()
+                && Path::new(&name).file_stem().is_some_and(|s| s.to_string_lossy() == stem);
+            if is_owner && !paths.contains(&sibling.path()) {
+                owners.insert(sibling.path());
+            }
+        }
+    }
+    owners
+}
+
+// Probe the channel's new or changed media files. The storage lock is only
+// held to read and store the cached media info, not while files are read.
+async fn probe_media(ch: &Channel, storage: &Arc<Mutex<Storage>>) -> Result<()> {
+    let mut ch = ch.clone();
+    let unprobed = storage.lock().unwrap().fill_media_info(&mut ch)?;
+    if unprobed.is_empty() {
+        return Ok(());
+    }
+    let probed = tokio::task::spawn_blocking(move || probe::probe_files(&unprobed)).await?;
+    tracing::info!("Probed {} media files for {}", probed.len(), ch.cache_id());
+    storage.lock().unwrap().store_media_info(&probed)
+}
+
+async fn fill_descriptions(mut rx: mpsc::Receiver<(String, Channel)>, storage: Arc<Mutex<Storage>>, cache: Arc<Mutex<HashMap<String, (Channel, chrono::DateTime<chrono::Utc>)>>>, tx: Option<mpsc::Sender<(String, Channel)>>) {
     while let Some((cache_id, ch)) = rx.recv().await {
+        if let Err(e) = probe_media(&ch, &storage).await {
+            tracing::error!("Error probing media files for {}: {}", cache_id, e);
+        }
         let result = {
             let storage = storage.lock().unwrap();
             storage.channel_descriptions(ch, cache.clone())
         };
         match result {
             Ok((filled_ch, changed)) => {
-                if changed {
+                if let Some(tx) = tx.as_ref().filter(|_| changed) {
                     if let Err(e) = tx.send((cache_id.clone(), filled_ch)).await {
                         tracing::error!("Failed to send channel {} to queue: {}", cache_id, e);
                     }
@@ -129,14 +347,16 @@ async fn fill_descriptions(mut rx: mpsc::Receiver<(String, Channel)>, storage: A
 
 async fn rss_writer(mut rx: mpsc::Receiver<(String, Channel)>, start_date: NaiveDate) {
     while let Some((channel_name, mut ch)) = rx.recv().await {
-        let output_path = &ch.output_path.clone();
-        if let Err(e) = ch.write_rss_tofile(start_date, output_path) {
-            tracing::error!("Error writing RSS for {}: {}", channel_name, e);
+        for (format, output_path) in ch.feed_outputs() {
+            if let Err(e) = ch.write_feed_tofile(format, start_date, &output_path) {
+                tracing::error!("Error writing {} for {}: {}", format.extension(), channel_name, e);
+            }
         }
     }
 }
 
-async fn scan_and_store(storage: &Arc<Mutex<Storage>>, scan_path: &str, regex: &Regex) -> Result<()> {
+// Read descriptors from new description files, returning how many files were new
+async fn scan_and_store(storage: &Arc<Mutex<Storage>>, scan_path: &str, regex: &Regex) -> Result<usize> {
     let path = Path::new(scan_path);
     let mut current_files = HashSet::new();
 
@@ -173,7 +393,7 @@ async fn scan_and_store(storage: &Arc<Mutex<Storage>>, scan_path: &str, regex: &
 
     storage.insert_filenames(&current_files.into_iter().collect::<Vec<_>>())?;
 
-    Ok(())
+    Ok(new_files.len())
 }
 
 lazy_static! {
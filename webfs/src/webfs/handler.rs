@@ -1,17 +1,16 @@
 use axum::{
-    Json, body::Body,
+    Json,
     extract::{Path, State, OriginalUri, Request},
-    http::{Method, StatusCode, Uri, header::{self, HeaderMap}},
-    response::{IntoResponse, Response}
+    http::{Method, StatusCode, Uri, header::HeaderMap},
+    response::Response
 };
+use std::collections::HashMap;
 use std::path::Path as StdPath;
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
-use mime_guess;
 use chrono::Utc;
 use serde_json;
 use crate::models::files::*;
-use crate::auth::{keycloak};
+use crate::auth::{acl, keycloak};
+use crate::models::audit::{AuditEvent, AuditKind};
 use crate::models::auth::*;
 
 pub async fn list_files_root_handler(
@@ -43,16 +42,25 @@ async fn list_files(
     let auth_request = AuthRequest::new(uri, method, headers);
     let auth_request_clone = auth_request.clone();
     let mut fs_id = String::new();
+    let claims;
     match keycloak::check_auth(&state, &auth_request_clone, state.passwd.clone(), state.tokens.clone()).await {
         Ok(auth) => {
+            acl::authorize(&state, &auth, uri.path(), None)?;
             fs_id = auth.folder.as_ref().and_then(|f| Some(f.name.clone())).unwrap_or(String::new());
+            claims = auth.claims;
         },
         Err((status, msg)) => {
             tracing::info!("auth failed for {}", auth_request.url.as_ref().unwrap().clone());
             return Err((status, msg))
         }
     }
+    let audit_event = |kind| AuditEvent::new(kind, uri.path(), 200).client(headers).identity(&claims);
     let state = state.clone();
+    match channel_view(&state, path) {
+        Some((channel, "dates")) => return super::listing::channel_dates(&state, channel, uri),
+        Some((channel, _)) => return super::stats::channel_popular(&state, channel, uri),
+        None => {}
+    }
     let mut lang = "zh";
     let mut channel_opt: Option<Channel> = None;
     let mut full_path= String::new();
@@ -65,6 +73,7 @@ async fn list_files(
             println!("!!! Folder Not Found: {}", &fs_id);
         }
     }
+    let policy = super::paths::PathPolicy::new(state.config.folders.get(&fs_id).filter(|_| !fs_id.is_empty()));
 
     if path.starts_with("zh/") || path.starts_with("en/") {
         let parts: Vec<&str> = path.split('/').collect();
@@ -84,7 +93,7 @@ async fn list_files(
     }
 
     if full_path.is_empty() {
-        full_path = format!("{}/{}", base_path, path);
+        full_path = policy.resolve(&base_path, path)?.to_string_lossy().to_string();
     }
     let path_obj = StdPath::new(&full_path);
 
@@ -94,27 +103,26 @@ async fn list_files(
     }
 
     if path_obj.is_file() {
-        let file = File::open(&full_path).await
-            .map_err(|_| (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "File not found"}))))?;
-
-        let stream = ReaderStream::new(file);
-        let body = Body::from_stream(stream);
-        let mut response = Response::new(body);
-
-        let mime = mime_guess::from_path(&full_path).first_or_octet_stream();
-        let content_type = mime.to_string().parse().map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to determine content type"}))))?;
-        response.headers_mut().insert(header::CONTENT_TYPE, content_type);
-
-        return Ok(response);
+        let disposition = super::send_file::Disposition::from_query(uri.query());
+        return super::send_file::send_file(path_obj, headers, method == "HEAD", &disposition, Some(audit_event(AuditKind::Download))).await;
     } else if path_obj.is_dir() {
         // Continue with listing
         tracing::info!("Listing files for path: {} {}", lang, full_path);
+        crate::audit::record(audit_event(AuditKind::Listing));
+        let query = super::listing::ListQuery::from_uri(uri)?;
+        // Configured channels are catalogued by the file monitor; other folders are read directly
+        let catalogued = channel_opt.is_some();
         let channel = if let Some(ch) = channel_opt {
             ch
         } else {
             state.config.clone().get_folder_info(lang, &full_path).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to get folder info"}))))?
         };
         let cache_id = channel.cache_id().clone();
+        let counters = if query.wants_stats() {
+            super::stats::channel_counters(&state, &cache_id)?
+        } else {
+            HashMap::new()
+        };
 
         // Check cache
         {
@@ -122,20 +130,30 @@ async fn list_files(
             if let Some((cached_channel, timestamp)) = cache.get(&cache_id) {
                 if Utc::now().signed_duration_since(*timestamp).num_seconds() < 300 {
                     tracing::info!("Using cached channel data for {}", cache_id);
-                    return Ok(Json(cached_channel.clone()).into_response());
+                    let mut channel = cached_channel.clone();
+                    policy.filter_entries(&mut channel.entries);
+                    return super::listing::render(&channel, &query, &counters);
                 }
             }
         }
 
-        let entries = Channel::read_dir(&channel).map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to read directory"}))))?;
+        let storage = state.storage.lock().unwrap();
+        let entries = if catalogued {
+            storage.channel_entries(&channel)
+        } else {
+            Channel::read_dir(&channel).map_err(anyhow::Error::from)
+        };
+        let entries = entries.map_err(|e| {
+            tracing::error!("Error reading entries for {}: {}", cache_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to read directory"})))
+        })?;
         let mut channel = channel;
         channel.set_entries(entries);
 
-        let storage = state.storage.lock().unwrap();
-
         match storage.channel_descriptions(channel, state.channel_cache.clone()){
-            Ok((ch, _changed)) => {
-                return Ok(Json(ch).into_response());
+            Ok((mut ch, _changed)) => {
+                policy.filter_entries(&mut ch.entries);
+                return super::listing::render(&ch, &query, &counters);
             }
             Err(e) => {
                 tracing::error!("Error filling descriptions for {}: {}", cache_id, e);
@@ -147,3 +165,12 @@ async fn list_files(
     }
 }
 
+// The configured channel of a "{lang}/{channel}/dates" or
+// "{lang}/{channel}/popular" path, and which of the two
+fn channel_view<'a>(state: &crate::AppState, path: &'a str) -> Option<(Channel, &'a str)> {
+    let parts: Vec<&str> = path.trim_end_matches('/').split('/').collect();
+    match parts.as_slice() {
+        [lang, name, view @ ("dates" | "popular")] => state.config.channels.get(*lang).and_then(|m| m.get(*name)).cloned().map(|c| (c, *view)),
+        _ => None,
+    }
+}
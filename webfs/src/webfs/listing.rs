@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, HashMap};
+use axum::{
+    Json,
+    extract::Query,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use crate::models::files::{Channel, MediaEntry};
+use crate::models::stats::EntryCounters;
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+// Query parameters of a /fs/v1 directory listing. Without any of them the
+// full Channel is returned, as before.
+#[derive(Debug, Default, Deserialize)]
+pub struct ListQuery {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    // Comma separated: "c,r"
+    pub event_code: Option<String>,
+    // "video", "audio", "image"...
+    pub media_type: Option<String>,
+    pub location: Option<String>,
+    // Case-insensitive text match on title, description and file name
+    pub q: Option<String>,
+    // date, -date, name, -name, size, -size, duration, -duration, downloads, -downloads
+    pub sort: Option<String>,
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    // Include each entry's download counters
+    #[serde(default)]
+    pub stats: bool,
+}
+
+impl ListQuery {
+    pub fn from_uri(uri: &Uri) -> Result<ListQuery, (StatusCode, Json<serde_json::Value>)> {
+        if uri.query().is_none() {
+            return Ok(ListQuery::default());
+        }
+        Query::<ListQuery>::try_from_uri(uri)
+            .map(|Query(q)| q)
+            .map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e.body_text()}))))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.from.is_none() && self.to.is_none() && self.event_code.is_none() && self.media_type.is_none()
+            && self.location.is_none() && self.q.is_none() && self.sort.is_none()
+            && self.limit.is_none() && self.cursor.is_none() && !self.stats
+    }
+
+    // Whether the listing needs the channel's download counters
+    pub fn wants_stats(&self) -> bool {
+        self.stats || self.sort.as_deref().is_some_and(|s| s.trim_start_matches('-') == "downloads")
+    }
+
+    fn accepts(&self, entry: &MediaEntry, event_codes: &[String], q: Option<&str>) -> bool {
+        let date = entry.pub_date.date();
+        if self.from.is_some_and(|from| date < from) || self.to.is_some_and(|to| date > to) {
+            return false;
+        }
+        if !event_codes.is_empty() && !event_codes.contains(&entry.event_code.to_lowercase()) {
+            return false;
+        }
+        if self.media_type.as_ref().is_some_and(|m| !entry.media_type.eq_ignore_ascii_case(m)) {
+            return false;
+        }
+        if self.location.as_ref().is_some_and(|l| !entry.location.to_lowercase().contains(&l.to_lowercase())) {
+            return false;
+        }
+        if let Some(q) = q {
+            let matched = [&entry.title, &entry.description, &entry.event_desc, &entry.file_name]
+                .iter()
+                .any(|field| field.to_lowercase().contains(q));
+            if !matched {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+// Slim entry for query listings
+#[derive(Debug, Serialize)]
+pub struct EntrySummary {
+    pub guid: String,
+    pub title: String,
+    pub description: String,
+    pub link: String,
+    pub file_name: String,
+    pub sub_path: String,
+    pub event: String,
+    pub event_code: String,
+    pub index: String,
+    pub location: String,
+    pub media_type: String,
+    pub pub_date: NaiveDateTime,
+    pub size: u64,
+    pub duration: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<EntryCounters>,
+}
+
+impl From<&MediaEntry> for EntrySummary {
+    fn from(entry: &MediaEntry) -> Self {
+        EntrySummary {
+            guid: entry.guid.clone(),
+            title: entry.title.clone(),
+            description: entry.description.clone(),
+            link: entry.link.clone(),
+            file_name: entry.file_name.clone(),
+            sub_path: entry.sub_path.clone(),
+            event: entry.event.clone(),
+            event_code: entry.event_code.clone(),
+            index: entry.index.clone(),
+            location: entry.location.clone(),
+            media_type: entry.media_type.clone(),
+            pub_date: entry.pub_date,
+            size: entry.size,
+            duration: entry.media.duration,
+            stats: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListingResponse {
+    pub name: String,
+    pub title: String,
+    pub language: String,
+    // Entries in the folder, and entries matching the filters
+    pub total: usize,
+    pub matched: usize,
+    pub next_cursor: Option<String>,
+    pub entries: Vec<EntrySummary>,
+}
+
+// The listing response for a filled channel. `counters` are by
+// normalized_entry_id, read when the query asks for stats.
+pub fn render(channel: &Channel, query: &ListQuery, counters: &HashMap<String, EntryCounters>) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    if query.is_empty() {
+        return Ok(Json(channel).into_response());
+    }
+    let event_codes: Vec<String> = query.event_code.as_deref().unwrap_or_default()
+        .split(',')
+        .map(|c| c.trim().to_lowercase())
+        .filter(|c| !c.is_empty())
+        .collect();
+    let q = query.q.as_ref().map(|q| q.trim().to_lowercase()).filter(|q| !q.is_empty());
+    let mut entries: Vec<&MediaEntry> = channel.entries.iter()
+        .filter(|e| query.accepts(e, &event_codes, q.as_deref()))
+        .collect();
+    sort_entries(&mut entries, query.sort.as_deref().unwrap_or(""), counters)?;
+
+    let matched = entries.len();
+    let offset = match &query.cursor {
+        Some(cursor) => decode_cursor(cursor)
+            .filter(|offset| *offset <= matched)
+            .ok_or((StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "Invalid cursor"}))))?,
+        None => 0,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let next = offset.saturating_add(limit);
+    let next_cursor = (next < matched).then(|| encode_cursor(next));
+    Ok(Json(ListingResponse {
+        name: channel.name.clone(),
+        title: channel.title.clone(),
+        language: channel.language.clone(),
+        total: channel.entries.len(),
+        matched,
+        next_cursor,
+        entries: entries.into_iter().skip(offset).take(limit).map(|entry| {
+            let mut summary = EntrySummary::from(entry);
+            if query.stats {
+                summary.stats = Some(counters.get(&entry.normalized_entry_id("zsv")).cloned().unwrap_or_default());
+            }
+            summary
+        }).collect(),
+    }).into_response())
+}
+
+fn sort_entries(entries: &mut [&MediaEntry], sort: &str, counters: &HashMap<String, EntryCounters>) -> Result<(), (StatusCode, Json<serde_json::Value>)> {
+    let (desc, key) = match sort.strip_prefix('-') {
+        Some(key) => (true, key),
+        None => (false, sort),
+    };
+    match key {
+        // Keep the channel's own order
+        "" => return Ok(()),
+        "date" => entries.sort_by(|a, b| a.pub_date.cmp(&b.pub_date).then_with(|| a.file_name.cmp(&b.file_name))),
+        "name" => entries.sort_by(|a, b| a.file_name.cmp(&b.file_name)),
+        "title" => entries.sort_by(|a, b| a.title.cmp(&b.title)),
+        "size" => entries.sort_by_key(|e| e.size),
+        "duration" => entries.sort_by(|a, b| a.media.duration.total_cmp(&b.media.duration)),
+        "downloads" => entries.sort_by_cached_key(|e| counters.get(&e.normalized_entry_id("zsv")).map(|c| c.downloads).unwrap_or(0)),
+        _ => return Err((StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": format!("Unknown sort '{}'", sort)})))),
+    }
+    if desc {
+        entries.reverse();
+    }
+    Ok(())
+}
+
+// Cursors are opaque to clients; they carry the offset of the next page
+fn encode_cursor(offset: usize) -> String {
+    URL_SAFE_NO_PAD.encode(format!("o:{}", offset))
+}
+
+fn decode_cursor(cursor: &str) -> Option<usize> {
+    let decoded = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    String::from_utf8(decoded).ok()?.strip_prefix("o:")?.parse().ok()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DatesQuery {
+    // First and last month, "2024-01" (or a full date); unbounded when missing
+    pub from: Option<String>,
+    pub to: Option<String>,
+    // Also count entries per event code for each day
+    #[serde(default)]
+    pub by_event_code: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatesResponse {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub total: usize,
+    pub counts: BTreeMap<NaiveDate, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_codes: Option<BTreeMap<NaiveDate, BTreeMap<String, usize>>>,
+    // Nearest days with entries outside the range, for previous/next navigation
+    pub prev: Option<NaiveDate>,
+    pub next: Option<NaiveDate>,
+}
+
+// GET /fs/v1/{lang}/{channel}/dates?from=2024-01&to=2024-03, routed from
+// list_files since it shares the /fs/v1 wildcard
+pub fn channel_dates(state: &crate::AppState, channel: Channel, uri: &Uri) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let query = Query::<DatesQuery>::try_from_uri(uri)
+        .map(|Query(q)| q)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e.body_text()}))))?;
+    let bad_month = |m: &str| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": format!("Invalid month '{}'", m)})));
+    let from = query.from.as_deref().map(|m| parse_month(m).ok_or_else(|| bad_month(m))).transpose()?;
+    let to = query.to.as_deref()
+        .map(|m| parse_month(m).map(|start| if m.len() > 7 { start } else { month_end(start) }).ok_or_else(|| bad_month(m)))
+        .transpose()?;
+
+    let (channel, _) = super::feeds::cached_channel(state, channel)?;
+
+    let mut response = DatesResponse {
+        from,
+        to,
+        total: 0,
+        counts: BTreeMap::new(),
+        event_codes: query.by_event_code.then(BTreeMap::new),
+        prev: None,
+        next: None,
+    };
+    for entry in channel.entries.iter().filter(|e| e.content_type != "folder") {
+        let date = entry.pub_date.date();
+        if from.is_some_and(|from| date < from) {
+            response.prev = response.prev.max(Some(date));
+            continue;
+        }
+        if to.is_some_and(|to| date > to) {
+            response.next = Some(response.next.map_or(date, |next| next.min(date)));
+            continue;
+        }
+        response.total += 1;
+        *response.counts.entry(date).or_insert(0) += 1;
+        if let Some(event_codes) = response.event_codes.as_mut() {
+            *event_codes.entry(date).or_default().entry(entry.event_code.clone()).or_insert(0) += 1;
+        }
+    }
+    Ok(Json(response).into_response())
+}
+
+// "2024-01" as its first day, or a full "2024-01-15"
+fn parse_month(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+        .or_else(|| NaiveDate::parse_from_str(&format!("{}-01", value), "%Y-%m-%d").ok())
+}
+
+fn month_end(start: NaiveDate) -> NaiveDate {
+    let (year, month) = if start.month() == 12 { (start.year() + 1, 1) } else { (start.year(), start.month() + 1) };
+    NaiveDate::from_ymd_opt(year, month, 1).and_then(|d| d.pred_opt()).unwrap_or(start)
+}
@@ -1,2 +1,8 @@
+pub mod feeds;
 pub mod file_monitor;
-pub mod handler;
\ No newline at end of file
+pub mod handler;
+pub mod listing;
+pub mod paths;
+pub mod search;
+pub mod send_file;
+pub mod stats;
\ No newline at end of file
@@ -0,0 +1,86 @@
+use std::path::{Component, Path, PathBuf};
+use axum::{Json, http::StatusCode};
+use regex::Regex;
+use crate::models::files::{FolderShare, MediaEntry};
+use crate::models::scan::glob_to_regex;
+
+type HandlerError = (StatusCode, Json<serde_json::Value>);
+
+// Symlink and hidden-file rules for serving files under a folder share root
+pub struct PathPolicy {
+    follow_symlinks: bool,
+    show_hidden: bool,
+    hidden: Vec<Regex>,
+}
+
+impl PathPolicy {
+    // Without a folder share: no symlinks out of the root, dotfiles hidden
+    pub fn new(folder: Option<&FolderShare>) -> PathPolicy {
+        let hidden = folder.map(|f| f.hidden.as_slice()).unwrap_or_default().iter()
+            .filter_map(|p| Regex::new(&glob_to_regex(p)).map_err(|e| tracing::error!("Invalid hidden pattern '{}': {}", p, e)).ok())
+            .collect();
+        PathPolicy {
+            follow_symlinks: folder.is_some_and(|f| f.follow_symlinks),
+            show_hidden: folder.is_some_and(|f| f.show_hidden),
+            hidden,
+        }
+    }
+
+    pub fn is_hidden_name(&self, name: &str) -> bool {
+        (!self.show_hidden && name.starts_with('.')) || self.hidden.iter().any(|re| re.is_match(name))
+    }
+
+    // Whether any folder or file name of a relative path is hidden
+    pub fn is_hidden(&self, rel_path: &str) -> bool {
+        rel_path.split('/').filter(|c| !c.is_empty()).any(|c| self.is_hidden_name(c))
+    }
+
+    pub fn filter_entries(&self, entries: &mut Vec<MediaEntry>) {
+        entries.retain(|e| !self.is_hidden(&e.rel_path()));
+    }
+
+    // Resolve a request path under `root`. Rejects ".." and encoded traversal
+    // (400), hidden names and missing files (404), and symlinks out of the
+    // root unless the folder allows them (403).
+    pub fn resolve(&self, root: &str, request_path: &str) -> Result<PathBuf, HandlerError> {
+        let bad_request = || (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "Invalid path"})));
+        let not_found = || (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "File not found"})));
+
+        // The router already decoded the path once; a second layer of encoding
+        // ("%252e%252e") or a backslash is never legitimate here
+        let decoded = percent_encoding::percent_decode_str(request_path).decode_utf8_lossy();
+        if request_path.contains(['\\', '\0']) || decoded.contains(['\\', '\0']) {
+            return Err(bad_request());
+        }
+        let mut rel = PathBuf::new();
+        for path in [request_path, decoded.as_ref()] {
+            for component in Path::new(path.trim_start_matches('/')).components() {
+                match component {
+                    Component::Normal(_) | Component::CurDir => {}
+                    _ => return Err(bad_request()),
+                }
+            }
+        }
+        for component in Path::new(request_path.trim_start_matches('/')).components() {
+            if let Component::Normal(name) = component {
+                if self.is_hidden_name(&name.to_string_lossy()) {
+                    return Err(not_found());
+                }
+                rel.push(name);
+            }
+        }
+
+        let root = Path::new(root).canonicalize().map_err(|e| {
+            tracing::error!("Folder root {} is not accessible: {}", root, e);
+            not_found()
+        })?;
+        let resolved = root.join(&rel).canonicalize().map_err(|_| not_found())?;
+        if !resolved.starts_with(&root) && !self.follow_symlinks {
+            tracing::warn!("Refusing symlink out of {}: {}", root.display(), resolved.display());
+            return Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "Forbidden"}))));
+        }
+        // Serve the path as requested, so file names and content types come
+        // from the link rather than its target
+        Ok(root.join(rel))
+    }
+}
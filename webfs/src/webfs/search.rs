@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use axum::{
+    Json,
+    extract::{Query, State, OriginalUri},
+    http::{Method, StatusCode, header::HeaderMap},
+};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use crate::auth::{acl, keycloak};
+use crate::models::auth::AuthRequest;
+use crate::models::file_desc::FileDesc;
+use crate::search::{self, ChannelIndex, SearchFilter, SearchHit};
+
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    pub q: String,
+    // "zh", "en"...
+    pub lang: Option<String>,
+    // Channel name, or "lang/name"
+    pub channel: Option<String>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    // Comma separated event codes: "c,r"
+    pub event_code: Option<String>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+// GET /fs/v1/search?q=...
+pub async fn search_handler(
+    State(state): State<crate::AppState>,
+    OriginalUri(uri): OriginalUri,
+    method: Method,
+    headers: HeaderMap,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, (StatusCode, Json<serde_json::Value>)> {
+    let auth_request = AuthRequest::new(&uri, method.as_str(), &headers);
+    let auth = keycloak::check_auth(&state, &auth_request, state.passwd.clone(), state.tokens.clone()).await?;
+
+    if query.q.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "Missing search query"}))));
+    }
+
+    let mut indexes = Vec::new();
+    for (lang, channels) in &state.config.channels {
+        if query.lang.as_ref().is_some_and(|l| l != lang) {
+            continue;
+        }
+        for (name, channel) in channels {
+            if let Some(wanted) = &query.channel {
+                if wanted != name && *wanted != format!("{}/{}", lang, name) {
+                    continue;
+                }
+            }
+            // Only search channels the user may list
+            if !acl::permits(&state, &auth, &format!("/fs/v1/{}/{}", lang, name), None) {
+                continue;
+            }
+            indexes.push(fresh_index(&state, channel.clone())?);
+        }
+    }
+
+    let filter = SearchFilter {
+        from: query.from,
+        to: query.to,
+        event_codes: query.event_code.as_deref().unwrap_or_default()
+            .split(',')
+            .map(|c| c.trim().to_lowercase())
+            .filter(|c| !c.is_empty())
+            .collect(),
+    };
+    let hits = search::search(&indexes, &query.q, &filter);
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    Ok(Json(SearchResponse {
+        total: hits.len(),
+        offset,
+        limit,
+        hits: hits.into_iter().skip(offset).take(limit).collect(),
+    }))
+}
+
+// The channel's search index, rebuilt when the cached channel has changed
+fn fresh_index(state: &crate::AppState, channel: crate::models::files::Channel) -> Result<Arc<ChannelIndex>, (StatusCode, Json<serde_json::Value>)> {
+    let (channel, updated_at) = super::feeds::cached_channel(state, channel)?;
+    let cache_id = channel.cache_id();
+    if let Some(index) = search::channel_index(&cache_id, &updated_at) {
+        return Ok(index);
+    }
+    let ids: Vec<String> = channel.entries.iter().map(|e| e.normalized_event_id("zsv")).collect();
+    let ids: Vec<&str> = ids.iter().map(|s| s.as_str()).collect();
+    let descs: HashMap<String, FileDesc> = {
+        let storage = state.storage.lock().unwrap();
+        storage.get_batch_file_desc(&ids).map_err(|e| {
+            tracing::error!("Error reading descriptions for {}: {}", cache_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()})))
+        })?
+    }.into_iter().map(|d| (d.id.clone(), d)).collect();
+    let index = ChannelIndex::build(&channel, updated_at, &descs);
+    tracing::info!("Indexed {} entries for {}", index.len(), cache_id);
+    Ok(search::set_channel_index(&cache_id, index))
+}
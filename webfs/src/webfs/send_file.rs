@@ -0,0 +1,256 @@
+use std::io::{Cursor, SeekFrom};
+use std::path::Path;
+use axum::{
+    Json, body::Body,
+    http::{StatusCode, header::{self, HeaderMap, HeaderValue}},
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use crate::audit::DownloadMeter;
+use crate::models::audit::AuditEvent;
+
+const BUF_SIZE: usize = 64 * 1024;
+// Multi-range responses are built in memory; larger requests get the whole file
+const MAX_MULTIRANGE_BYTES: u64 = 64 * 1024 * 1024;
+const MAX_RANGES: usize = 16;
+
+type HandlerError = (StatusCode, Json<serde_json::Value>);
+
+// Content-Disposition options from the query string:
+// ?download=1 (attachment) and ?filename=name.mp4
+#[derive(Debug, Default)]
+pub struct Disposition {
+    pub attachment: bool,
+    pub filename: Option<String>,
+}
+
+impl Disposition {
+    pub fn from_query(query: Option<&str>) -> Disposition {
+        let mut disposition = Disposition::default();
+        for (key, value) in url::form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            match key.as_ref() {
+                "download" => disposition.attachment = value != "0" && value != "false",
+                "disposition" => disposition.attachment = value == "attachment",
+                "filename" if !value.is_empty() => disposition.filename = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        disposition
+    }
+}
+
+// Stream a file with Range (single and multipart/byteranges), conditional
+// request and Content-Disposition support. HEAD gets the same headers, no body.
+// A body sent is audited as `audit`, with the bytes that went out.
+pub async fn send_file(path: &Path, headers: &HeaderMap, head_only: bool, disposition: &Disposition, audit: Option<AuditEvent>) -> Result<Response, HandlerError> {
+    let not_found = || (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "File not found"})));
+    let mut file = File::open(path).await.map_err(|_| not_found())?;
+    let meta = file.metadata().await.map_err(|_| not_found())?;
+    let size = meta.len();
+    let modified: Option<DateTime<Utc>> = meta.modified().ok().map(DateTime::<Utc>::from);
+    let etag = modified.map(|m| format!("\"{}-{}\"", m.timestamp_millis(), size));
+    let last_modified = modified.map(http_date);
+
+    let mut response = Response::new(Body::empty());
+    if let (Some(etag), Some(modified)) = (&etag, &modified) {
+        if let Some(status) = precondition_status(headers, etag, modified) {
+            *response.status_mut() = status;
+            if status == StatusCode::NOT_MODIFIED {
+                set_header(&mut response, header::ETAG, etag);
+                set_header(&mut response, header::LAST_MODIFIED, &http_date(*modified));
+            }
+            return Ok(response);
+        }
+        set_header(&mut response, header::ETAG, etag);
+        set_header(&mut response, header::CACHE_CONTROL, "no-cache");
+    }
+    if let Some(last_modified) = &last_modified {
+        set_header(&mut response, header::LAST_MODIFIED, last_modified);
+    }
+
+    let content_type = mime_guess::from_path(path).first_or_octet_stream().to_string();
+    set_header(&mut response, header::CONTENT_TYPE, &content_type);
+    set_header(&mut response, header::ACCEPT_RANGES, "bytes");
+    let filename = disposition.filename.clone()
+        .unwrap_or_else(|| path.file_name().unwrap_or_default().to_string_lossy().to_string());
+    set_header(&mut response, header::CONTENT_DISPOSITION, &content_disposition(disposition.attachment, &filename));
+
+    let range = headers.get(header::RANGE).and_then(|r| r.to_str().ok())
+        .filter(|_| if_range_passes(headers, etag.as_deref(), modified.as_ref()));
+    // A Range header that doesn't parse is ignored (RFC 9110 section 14.2)
+    let ranges = range.and_then(|range| parse_range(range, size));
+    if ranges.as_ref().is_some_and(|r| r.is_empty()) {
+        *response.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+        set_header(&mut response, header::CONTENT_RANGE, &format!("bytes */{}", size));
+        return Ok(response);
+    }
+    let ranges = ranges.filter(|r| r.len() == 1 || r.iter().map(|(s, e)| e - s + 1).sum::<u64>() <= MAX_MULTIRANGE_BYTES);
+
+    let io_error = |e: std::io::Error| {
+        tracing::error!("Error reading {}: {}", path.display(), e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to read file"})))
+    };
+    match ranges {
+        Some(ranges) if ranges.len() == 1 => {
+            let (start, end) = ranges[0];
+            let range_size = end - start + 1;
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            set_header(&mut response, header::CONTENT_RANGE, &format!("bytes {}-{}/{}", start, end, size));
+            set_header(&mut response, header::CONTENT_LENGTH, &range_size.to_string());
+            if !head_only {
+                file.seek(SeekFrom::Start(start)).await.map_err(io_error)?;
+                let event = audit.map(|e| e.status(StatusCode::PARTIAL_CONTENT));
+                let body = DownloadMeter::new(file.take(range_size), range_size, event);
+                *response.body_mut() = Body::from_stream(ReaderStream::with_capacity(body, BUF_SIZE));
+            }
+        }
+        Some(ranges) => {
+            *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+            let boundary = uuid::Uuid::new_v4().simple().to_string();
+            let parts: Vec<(String, u64, u64)> = ranges.iter().map(|&(start, end)| {
+                let part_header = format!(
+                    "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    boundary, content_type, start, end, size
+                );
+                (part_header, start, end)
+            }).collect();
+            let closing = format!("--{}--\r\n", boundary);
+            let length = parts.iter().map(|(h, start, end)| h.len() as u64 + end - start + 1 + 2).sum::<u64>() + closing.len() as u64;
+            set_header(&mut response, header::CONTENT_TYPE, &format!("multipart/byteranges; boundary={}", boundary));
+            set_header(&mut response, header::CONTENT_LENGTH, &length.to_string());
+            if !head_only {
+                let mut body = Vec::with_capacity(length as usize);
+                for (part_header, start, end) in parts {
+                    body.extend_from_slice(part_header.as_bytes());
+                    let mut buffer = vec![0; (end - start + 1) as usize];
+                    file.seek(SeekFrom::Start(start)).await.map_err(io_error)?;
+                    file.read_exact(&mut buffer).await.map_err(io_error)?;
+                    body.extend_from_slice(&buffer);
+                    body.extend_from_slice(b"\r\n");
+                }
+                body.extend_from_slice(closing.as_bytes());
+                let size = body.len() as u64;
+                let event = audit.map(|e| e.status(StatusCode::PARTIAL_CONTENT));
+                *response.body_mut() = Body::from_stream(ReaderStream::new(DownloadMeter::new(Cursor::new(body), size, event)));
+            }
+        }
+        None => {
+            set_header(&mut response, header::CONTENT_LENGTH, &size.to_string());
+            if !head_only {
+                let body = DownloadMeter::new(file, size, audit);
+                *response.body_mut() = Body::from_stream(ReaderStream::with_capacity(body, BUF_SIZE));
+            }
+        }
+    }
+    Ok(response)
+}
+
+// 412 or 304 when the request's validators say so (RFC 7232 section 6)
+fn precondition_status(headers: &HeaderMap, etag: &str, modified: &DateTime<Utc>) -> Option<StatusCode> {
+    if let Some(if_match) = header_str(headers, header::IF_MATCH) {
+        if !etag_matches(if_match, etag, true) {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    } else if let Some(since) = header_str(headers, header::IF_UNMODIFIED_SINCE).and_then(parse_http_date) {
+        if modified.timestamp() > since.timestamp() {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    }
+    if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+        if etag_matches(if_none_match, etag, false) {
+            return Some(StatusCode::NOT_MODIFIED);
+        }
+    } else if let Some(since) = header_str(headers, header::IF_MODIFIED_SINCE).and_then(parse_http_date) {
+        if modified.timestamp() <= since.timestamp() {
+            return Some(StatusCode::NOT_MODIFIED);
+        }
+    }
+    None
+}
+
+// If-Range: only serve the range when the file is still the one the client has
+fn if_range_passes(headers: &HeaderMap, etag: Option<&str>, modified: Option<&DateTime<Utc>>) -> bool {
+    let Some(if_range) = header_str(headers, header::IF_RANGE) else { return true };
+    if if_range.starts_with('"') {
+        return etag == Some(if_range);
+    }
+    match (parse_http_date(if_range), modified) {
+        (Some(date), Some(modified)) => date.timestamp() == modified.timestamp(),
+        _ => false,
+    }
+}
+
+fn etag_matches(header_value: &str, etag: &str, strong: bool) -> bool {
+    header_value.split(',').map(|t| t.trim()).any(|tag| {
+        tag == "*" || if strong { tag == etag } else { tag.trim_start_matches("W/") == etag }
+    })
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn set_header(response: &mut Response, name: header::HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        response.headers_mut().insert(name, value);
+    }
+}
+
+pub fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn parse_http_date(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value).ok().map(|d| d.with_timezone(&Utc))
+}
+
+fn content_disposition(attachment: bool, filename: &str) -> String {
+    let kind = if attachment { "attachment" } else { "inline" };
+    let filename: String = filename.chars()
+        .map(|c| if (c.is_ascii_control() && c != '\t') || c == '"' { ' ' } else { c })
+        .collect();
+    if filename.is_ascii() {
+        format!("{}; filename=\"{}\"", kind, filename)
+    } else {
+        let ascii: String = filename.chars().map(|c| if c.is_ascii() { c } else { '_' }).collect();
+        let encoded: String = url::form_urlencoded::byte_serialize(filename.as_bytes()).collect::<String>().replace('+', "%20");
+        format!("{}; filename=\"{}\"; filename*=UTF-8''{}", kind, ascii, encoded)
+    }
+}
+
+// "bytes=0-99,200-" into inclusive (start, end) pairs. None for a header to
+// ignore: malformed, another unit or more than MAX_RANGES ranges. Empty when
+// no range is satisfiable.
+pub fn parse_range(range: &str, size: u64) -> Option<Vec<(u64, u64)>> {
+    let (unit, ranges) = range.split_once('=')?;
+    if unit.trim() != "bytes" {
+        return None;
+    }
+    let specs: Vec<&str> = ranges.split(',').map(|r| r.trim()).filter(|r| !r.is_empty()).collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+    let mut result = Vec::new();
+    for spec in specs {
+        let (start, end) = spec.split_once('-')?;
+        if start.is_empty() {
+            let suffix = end.parse::<u64>().ok()?;
+            if suffix > 0 && size > 0 {
+                result.push((size.saturating_sub(suffix), size - 1));
+            }
+        } else {
+            let start = start.parse::<u64>().ok()?;
+            let end = if end.is_empty() { None } else { Some(end.parse::<u64>().ok()?) };
+            if end.is_some_and(|end| end < start) {
+                return None;
+            }
+            if start < size {
+                result.push((start, end.unwrap_or(size - 1).min(size - 1)));
+            }
+        }
+    }
+    Some(result)
+}
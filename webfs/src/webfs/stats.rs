@@ -0,0 +1,135 @@
+use axum::{
+    Json,
+    extract::Query,
+    http::{StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use crate::models::audit::{AuditEvent, AuditKind};
+use crate::models::files::{Channel, Config};
+use crate::models::stats::{EntryAccess, EntryCounters, DAILY_DAYS};
+use super::listing::EntrySummary;
+
+const DEFAULT_POPULAR_DAYS: i64 = 7;
+const DEFAULT_POPULAR_LIMIT: usize = 10;
+const MAX_POPULAR_LIMIT: usize = 100;
+
+// Fold audited downloads of catalogued channel entries into their stats.
+// Channel media is fetched from the file server behind forward auth, which
+// audits each fetch by its URL under the channel's media_link.
+pub fn record(state: &crate::AppState, events: &[AuditEvent]) {
+    let storage = state.storage.lock().unwrap();
+    let mut accesses = Vec::new();
+    for event in events {
+        if event.kind != AuditKind::Download || event.bytes == Some(0) {
+            continue;
+        }
+        let Some(user) = event.sub.clone() else { continue };
+        for (channel, rel_path) in channel_files(&state.config, &event.path) {
+            let cache_id = channel.cache_id();
+            match storage.catalog_entry(&cache_id, &rel_path) {
+                Ok(Some(entry)) => accesses.push(EntryAccess {
+                    cache_id,
+                    entry_id: entry.normalized_entry_id("zsv"),
+                    user: user.clone(),
+                    time: event.time,
+                }),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Failed to look up {} in {}: {}", rel_path, cache_id, e),
+            }
+        }
+    }
+    if accesses.is_empty() {
+        return;
+    }
+    if let Err(e) = storage.record_downloads(&accesses) {
+        tracing::error!("Failed to update stats of {} downloads: {}", accesses.len(), e);
+    }
+}
+
+// The channels whose media_link the decoded file URL `url` is under, with
+// the file's path within each. Channels may share a media_link, and then
+// the one file is counted in each that lists it.
+pub fn channel_files<'a>(config: &'a Config, url: &str) -> Vec<(&'a Channel, String)> {
+    let without_scheme = |url: &str| url.split_once("://").map_or(url, |(_, rest)| rest).to_string();
+    let url = without_scheme(url);
+    config.channels.values().flat_map(|channels| channels.values())
+        .filter(|channel| !channel.media_link.is_empty())
+        .filter_map(|channel| {
+            let base = without_scheme(&percent_decode_str(channel.media_link.trim_end_matches('/')).decode_utf8_lossy());
+            let rel_path = url.strip_prefix(&base)?.strip_prefix('/')?;
+            (!rel_path.is_empty()).then(|| (channel, rel_path.to_string()))
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PopularQuery {
+    pub days: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PopularEntry {
+    // Downloads within the window
+    pub recent: u64,
+    #[serde(flatten)]
+    pub entry: EntrySummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PopularResponse {
+    pub name: String,
+    pub title: String,
+    pub language: String,
+    pub days: i64,
+    pub entries: Vec<PopularEntry>,
+}
+
+// GET /fs/v1/{lang}/{channel}/popular?days=7&limit=10: the most downloaded
+// entries of the last days, routed from list_files like /dates
+pub fn channel_popular(state: &crate::AppState, channel: Channel, uri: &Uri) -> Result<Response, (StatusCode, Json<serde_json::Value>)> {
+    let query = Query::<PopularQuery>::try_from_uri(uri)
+        .map(|Query(q)| q)
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e.body_text()}))))?;
+    let days = query.days.unwrap_or(DEFAULT_POPULAR_DAYS).clamp(1, DAILY_DAYS);
+    let limit = query.limit.unwrap_or(DEFAULT_POPULAR_LIMIT).clamp(1, MAX_POPULAR_LIMIT);
+
+    let (channel, _) = super::feeds::cached_channel(state, channel)?;
+    let stats = state.storage.lock().unwrap().entry_stats(&channel.cache_id()).map_err(|e| {
+        tracing::error!("Error reading stats for {}: {}", channel.cache_id(), e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to read stats"})))
+    })?;
+
+    let mut popular: Vec<_> = channel.entries.iter()
+        .filter(|e| e.content_type != "folder")
+        .filter_map(|entry| {
+            let stats = stats.get(&entry.normalized_entry_id("zsv"))?;
+            Some((stats.since(days), stats.last_accessed, entry, stats))
+        })
+        .filter(|(recent, ..)| *recent > 0)
+        .collect();
+    popular.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| b.1.cmp(&a.1)));
+    let entries = popular.into_iter().take(limit).map(|(recent, _, entry, stats)| {
+        let mut summary = EntrySummary::from(entry);
+        summary.stats = Some(stats.counters());
+        PopularEntry { recent, entry: summary }
+    }).collect();
+    Ok(Json(PopularResponse {
+        name: channel.name.clone(),
+        title: channel.title.clone(),
+        language: channel.language.clone(),
+        days,
+        entries,
+    }).into_response())
+}
+
+// Counters of a channel's entries by normalized_entry_id, for listings
+pub fn channel_counters(state: &crate::AppState, cache_id: &str) -> Result<std::collections::HashMap<String, EntryCounters>, (StatusCode, Json<serde_json::Value>)> {
+    let stats = state.storage.lock().unwrap().entry_stats(cache_id).map_err(|e| {
+        tracing::error!("Error reading stats for {}: {}", cache_id, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": "Failed to read stats"})))
+    })?;
+    Ok(stats.into_iter().map(|(id, stats)| (id, stats.counters())).collect())
+}
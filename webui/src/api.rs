@@ -14,6 +14,10 @@ fn get_api_refresh_token_url() -> String {
   match option_env!("API_REFRESH_TOKEN_URL") { Some(s) => s.to_string(), None => "/auth/v1/refresh".to_string() }
 }
 
+fn get_api_logout_url() -> String {
+  match option_env!("API_LOGOUT_URL") { Some(s) => s.to_string(), None => "/auth/v1/logout".to_string() }
+}
+
 pub fn get_api_file_listing_url() -> String {
   match option_env!("API_FILE_LISTING_URL") { Some(s) => s.to_string(), None => "/fs/v1".to_string() }
 }
@@ -150,6 +154,26 @@ pub async fn login(i18n: I18nContext<Locale, I18nKeys>, email: &str, password: &
 //     weeks
 // }
 
+// Ends the session on the server, so the token stops working everywhere
+pub async fn logout_request(jwt_token: String, refresh_token: Option<String>)-> AnyhowResult<()> {
+  let body = serde_json::json!({
+    "refresh_token": refresh_token,
+  });
+  let resp = Request::post(&get_api_logout_url())
+    .header("Authorization", &format!("Bearer {jwt_token}"))
+    .json(&body)
+    .map_err(|e| anyhow!("Failed to create logout request: {:?}", e))?
+    .send()
+    .await
+    .map_err(|e| anyhow!("Network error during logout: {:?}", e))?;
+  // 401: the token was already expired or revoked
+  if resp.ok() || resp.status() == 401 {
+    Ok(())
+  } else {
+    Err(anyhow!("Logout failed with status: {:?}", resp.status()))
+  }
+}
+
 pub async fn refresh_token_request(refresh_token: String)-> AnyhowResult<AuthResponse> {
   let body = serde_json::json!({
     "refresh_token": refresh_token,
@@ -12,7 +12,7 @@ use wasm_bindgen::JsCast;
 use url::Url;
 use crate::models::channel::{Channel, FolderShare};
 use crate::models::auth::{AuthResponse, Claims};
-use crate::api::{refresh_token_request, get_api_file_listing_url};
+use crate::api::{logout_request, refresh_token_request, get_api_file_listing_url};
 use crate::storage::{get_auth_from_store, store_auth, clear_tokens};
 use crate::{utc_to_local};
 
@@ -190,6 +190,15 @@ pub fn set_auth_cookie(domain: &str, response: &AuthResponse) -> Result<()>{
 }
 
 pub fn logout(state: &AppState) {
+    // Revoke the session on the server; local state is cleared regardless
+    if let Some(auth) = state.auth.get_untracked() {
+        spawn_local(async move {
+            if let Err(e) = logout_request(auth.jwt_token, auth.refresh_token).await {
+                leptos::logging::error!("Failed to log out: {}", e);
+            }
+        });
+    }
+
     // Clear auth signal
     state.auth.set(None);
     